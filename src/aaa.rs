@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::aircraft::Aircraft;
+use crate::coalition::Coalition;
+use crate::explosion::{spawn_smoke_puff, ExplosionAssets, SmokePuff};
+use crate::gun::{compute_gun_lead, PROJECTILE_GRAVITY};
+use crate::health::BlastEvent;
+use crate::line_of_sight::check_line_of_sight;
+use crate::radar::{RadarEmitter, RadarMode};
+use crate::sam::EngagementEnvelope;
+use crate::terrain::TerrainData;
+use crate::threats::FlakData;
+use crate::util::{get_time_millis, random_f32, random_u64, random_vec3};
+
+/* Anti-aircraft artillery. Radar directed guns fire at whatever their radar has locked, with the
+   lead computed from the target's motion. With the radar dark or destroyed the gunners fall back
+   to aiming by eye, which only works at short range and is much less accurate. The shells are
+   time fuzed and burst where the target was predicted to be. */
+
+/// Optical aiming is this much less accurate than radar directed fire
+const OPTICAL_DISPERSION_FACTOR: f32 = 3.0;
+/// Longest fuze setting, in seconds. Enough for the shells to reach the guns' maximum range.
+const MAX_FUZE_TIME: f32 = 12.0;
+
+#[derive(Component)]
+pub struct FlakGun {
+    pub rate_of_fire: f32, // Bursts per minute
+    pub muzzle_velocity: f32, // World units per second
+    pub max_range: f32,
+    pub max_altitude: f32, // Feet above the gun
+    pub optical_range: f32, // The gunners aim by eye within this range when the radar is dark
+    pub dispersion: f32, // Radians
+    pub burst_radius: f32,
+    pub damage: f32,
+    pub last_shot_time: u64,
+}
+
+impl FlakGun {
+    pub fn from_data(data: &FlakData, envelope: EngagementEnvelope) -> FlakGun {
+        FlakGun {
+            rate_of_fire: data.rate_of_fire,
+            muzzle_velocity: data.muzzle_velocity,
+            max_range: envelope.max_range,
+            max_altitude: envelope.max_altitude,
+            optical_range: data.optical_range,
+            dispersion: data.dispersion,
+            burst_radius: data.burst_radius,
+            damage: data.damage,
+            last_shot_time: 0,
+        }
+    }
+}
+
+/// A shell on its way to the burst point
+#[derive(Component)]
+pub struct FlakBurst {
+    pub burst_time: u64,
+    pub radius: f32,
+    pub damage: f32,
+}
+
+pub fn update_flak_guns(
+    mut commands: Commands,
+    mut guns: Query<(&mut FlakGun, &Transform, &Coalition, Option<&RadarEmitter>)>,
+    aircrafts: Query<(Entity, &Transform, &Coalition, Option<&Velocity>), With<Aircraft>>,
+    targets: Query<(&Transform, Option<&Velocity>)>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let milliseconds = get_time_millis();
+    for (mut gun, transform, coalition, radar_emitter) in guns.iter_mut() {
+        let shot_interval = (60_000.0 / gun.rate_of_fire) as u64;
+        if milliseconds - gun.last_shot_time < shot_interval {
+            continue;
+        }
+        let gun_position = transform.translation;
+
+        // The radar solution when there is one, the gunners' eyes otherwise
+        let radar_target = radar_emitter
+            .filter(|r| r.emitting && matches!(r.mode, RadarMode::LOCK | RadarMode::LAUNCH))
+            .and_then(|r| r.locked_target)
+            .and_then(|target| targets.get(target).ok());
+        let (target_position, target_velocity, dispersion) = match radar_target {
+            Some((target_transform, velocity)) => (target_transform.translation, velocity.map(|v| v.linvel).unwrap_or(Vec3::ZERO), gun.dispersion),
+            None => {
+                let seen = aircrafts.iter()
+                    .filter(|(_, _, c, _)| c.side != coalition.side)
+                    .map(|(entity, t, _, v)| (entity, t.translation, v.map(|v| v.linvel).unwrap_or(Vec3::ZERO)))
+                    .filter(|(_, position, _)| position.distance(gun_position) < gun.optical_range)
+                    .filter(|(entity, position, _)| check_line_of_sight(None, terrain.as_deref(), *entity, gun_position, *position))
+                    .min_by(|a, b| a.1.distance(gun_position).total_cmp(&b.1.distance(gun_position)));
+                let Some((_, position, velocity)) = seen else { continue };
+                (position, velocity, gun.dispersion * OPTICAL_DISPERSION_FACTOR)
+            },
+        };
+        // Altitude is shown in feet, at ten feet per world unit
+        if target_position.distance(gun_position) > gun.max_range || (target_position.y - gun_position.y) * 10.0 > gun.max_altitude {
+            continue;
+        }
+        let Some((aim_point, time_of_flight)) = compute_gun_lead(gun_position, Vec3::ZERO, target_position, target_velocity, gun.muzzle_velocity, MAX_FUZE_TIME) else { continue };
+        // The barrels point above the intercept to allow for the drop, the shell bursts at the intercept
+        let burst_point = aim_point + PROJECTILE_GRAVITY * 0.5 * time_of_flight * time_of_flight;
+
+        gun.last_shot_time = milliseconds;
+        let miss = random_vec3(burst_point.distance(gun_position) * dispersion + 0.1);
+        commands.spawn((
+            FlakBurst {
+                burst_time: milliseconds + (time_of_flight * 1000.0) as u64,
+                radius: gun.burst_radius,
+                damage: gun.damage,
+            },
+            Transform::from_translation(burst_point + miss),
+        ));
+    }
+}
+
+/// Set off the shells that reached their fuze time
+pub fn update_flak_bursts(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Res<ExplosionAssets>,
+    bursts: Query<(Entity, &FlakBurst, &Transform)>,
+    mut blast_events: MessageWriter<BlastEvent>,
+) {
+    let milliseconds = get_time_millis();
+    for (entity, burst, transform) in bursts.iter() {
+        if milliseconds < burst.burst_time {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        blast_events.write(BlastEvent {
+            position: transform.translation,
+            radius: burst.radius,
+            damage: burst.damage,
+        });
+        spawn_smoke_puff(&mut commands, &mut materials, &explosion_assets, transform.translation, SmokePuff {
+            start_time: milliseconds,
+            life_time: random_u64(3000, 5000),
+            start_size: 0.5,
+            end_size: random_f32(2.0, 3.0),
+            velocity: random_vec3(0.1),
+            opacity: 0.8,
+        });
+    }
+}
@@ -8,6 +8,7 @@ use crate::player::*;
 use crate::missile::*;
 use crate::targeting::SensorTarget;
 use crate::targeting::Targetable;
+use crate::radar::RadarDetectable;
 
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub enum AircraftType {
@@ -81,6 +82,9 @@ const SIDESLIP_DRAG_FACTOR: f32 = 1.0;
 /// Sea-level air density (kg/m^3), used as reference for density ratio.
 const RHO_SEA_LEVEL: f32 = 1.2041;
 
+/// How long the weapon bay stays open for a launch (ms).
+pub const BAY_DOOR_OPEN_TIME: u64 = 2000;
+
 /// Control input parameters.
 const CONTROL_CENTER_RATE: f32 = 10.0;
 const INPUT_RAMP: f32 = 6.0;
@@ -110,6 +114,14 @@ fn aero_config(aircraft_type: &AircraftType) -> AeroConfig {
     }
 }
 
+/// Radar cross section table of the airframe, see assets/rcs
+pub fn rcs_table_file(aircraft_type: &AircraftType) -> &'static str {
+    match aircraft_type {
+        AircraftType::F117A => "rcs/f117a.rcs.toml",
+        AircraftType::MIG29 => "rcs/mig29.rcs.toml",
+    }
+}
+
 // ===============================================================
 // Aircraft component
 // ===============================================================
@@ -412,7 +424,7 @@ pub fn update_aircraft_forces(
 // ===============================================================
 
 pub fn update_player_weapon_controls(
-    aircrafts: Query<(&Aircraft, Entity, &Transform, &Velocity), With<Player>>,
+    mut aircrafts: Query<(&Aircraft, Entity, &Transform, &Velocity, &mut RadarDetectable), With<Player>>,
     asset_server: Res<AssetServer>,
     mut commands: Commands,
     input: Res<ButtonInput<KeyCode>>,
@@ -422,7 +434,8 @@ pub fn update_player_weapon_controls(
         for (target, target_transform) in targets.iter() {
             info!("Firing missile");
             commands.spawn(AudioPlayer::new(asset_server.load("sounds/internallaunch.ogg")));
-            for (_ac, entity, transform, vel) in aircrafts.iter() {
+            for (_ac, entity, transform, vel, mut detectable) in aircrafts.iter_mut() {
+                detectable.open_bay_doors(BAY_DOOR_OPEN_TIME);
                 commands.spawn(SceneRoot(asset_server.load("models/weapons/AGM-65.glb#Scene0")))
                 .insert(Missile {
                     launching_vehicle: entity, target: target,
@@ -458,7 +471,7 @@ fn slew_to_zero(v: f32, rate: f32, dt: f32) -> f32 {
 }
 
 pub fn update_player_aircraft_controls(
-    mut aircrafts: Query<(&mut Aircraft, &mut Transform), With<Player>>,
+    mut aircrafts: Query<(&mut Aircraft, &mut Transform), (With<Player>, Without<ShotDown>)>,
     input: Res<ButtonInput<KeyCode>>, time: Res<Time>,
 ) {
     let dt = time.delta_secs();
@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::aircraft::BAY_DOOR_OPEN_TIME;
+use crate::coalition::{Coalition, CoalitionType};
+use crate::definitions::*;
+use crate::missile::{Missile, SeekerState};
+use crate::player::Player;
+use crate::radar::{RadarDetectable, RadarEmitter};
+use crate::targeting::{SensorTarget, Targetable};
+
+/* Anti-radiation missiles. The AGM-88 seeker listens for radar emissions and homes on them. While
+   the radar keeps radiating the seeker knows exactly where it is, once it goes dark the missile
+   flies on to the last position it heard it from, and picks it up again if it comes back on.
+   In target-of-opportunity mode the missile is fired at an emitter the seeker already hears,
+   in pre-briefed mode it is fired at a known position, even a silent one, and the seeker
+   searches for an emitter around it on the way. */
+
+/// Half angle of the seeker field of view in radians
+const ARM_FIELD_OF_VIEW: f32 = 0.5;
+/// Emitters further away than this are too faint for the seeker at launch
+const ARM_MAX_RANGE: f32 = 40000.0;
+/// The seeker only takes an emitter this close to the briefed or last known position
+const ARM_SEARCH_RADIUS: f32 = 1500.0;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArmMode {
+    TARGET_OF_OPPORTUNITY, // Fired at an emitter the seeker hears at launch
+    PRE_BRIEFED, // Fired at a known position, the seeker searches around it
+}
+
+/// Anti-radiation missiles carried by an aircraft
+#[derive(Component)]
+pub struct ArmLauncher {
+    pub mode: ArmMode,
+    pub missiles: u32,
+}
+
+impl Default for ArmLauncher {
+    fn default() -> Self {
+        ArmLauncher {
+            mode: ArmMode::TARGET_OF_OPPORTUNITY,
+            missiles: 2,
+        }
+    }
+}
+
+/// Seeker of an anti-radiation missile
+#[derive(Component)]
+pub struct ArmSeeker {
+    pub side: CoalitionType, // The seeker ignores the emitters of its own side
+    pub emitter: Option<Entity>, // The emitter it is homing on, or last homed on
+    pub last_known_position: Vec3,
+}
+
+/// Whether a radar is on and within the seeker's field of view
+fn in_seeker_view(radar_emitter: &RadarEmitter, coalition: &Coalition, side: CoalitionType, seeker: Vec3, boresight: Vec3, position: Vec3) -> bool {
+    radar_emitter.emitting
+        && coalition.side != side
+        && (position - seeker).angle_between(boresight) < ARM_FIELD_OF_VIEW
+}
+
+pub fn handle_arm_controls(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    input: Res<ButtonInput<KeyCode>>,
+    mut players: Query<(Entity, &mut ArmLauncher, &Transform, &Velocity, &Coalition, &mut RadarDetectable), With<Player>>,
+    emitters: Query<(Entity, &RadarEmitter, &Transform, &Coalition), Without<Player>>,
+    sensor_targets: Query<&Transform, (With<SensorTarget>, Without<Player>)>,
+) {
+    for (entity, mut launcher, transform, velocity, coalition, mut detectable) in players.iter_mut() {
+        if input.just_pressed(KeyCode::KeyQ) {
+            launcher.mode = match launcher.mode {
+                ArmMode::TARGET_OF_OPPORTUNITY => ArmMode::PRE_BRIEFED,
+                ArmMode::PRE_BRIEFED => ArmMode::TARGET_OF_OPPORTUNITY,
+            };
+            info!("AGM-88 mode {:?}", launcher.mode);
+        }
+        if !input.just_pressed(KeyCode::KeyR) {
+            continue;
+        }
+        if launcher.missiles == 0 {
+            info!("No AGM-88 left");
+            continue;
+        }
+
+        let boresight = velocity.linvel.normalize_or(*transform.forward());
+        let (emitter, aim_position) = match launcher.mode {
+            ArmMode::TARGET_OF_OPPORTUNITY => {
+                // The emitter closest to the nose that the seeker can hear
+                let heard = emitters.iter()
+                    .filter(|(_, radar_emitter, emitter_transform, emitter_coalition)| {
+                        emitter_transform.translation.distance(transform.translation) < ARM_MAX_RANGE
+                            && in_seeker_view(radar_emitter, emitter_coalition, coalition.side, transform.translation, boresight, emitter_transform.translation)
+                    })
+                    .min_by(|a, b| {
+                        let angle = |t: &Transform| (t.translation - transform.translation).angle_between(boresight);
+                        angle(a.2).total_cmp(&angle(b.2))
+                    });
+                match heard {
+                    Some((emitter, _, emitter_transform, _)) => (Some(emitter), emitter_transform.translation),
+                    None => {
+                        info!("AGM-88: no emitter in the seeker's field of view");
+                        continue;
+                    },
+                }
+            },
+            ArmMode::PRE_BRIEFED => match sensor_targets.iter().next() {
+                Some(target_transform) => (None, target_transform.translation),
+                None => {
+                    info!("AGM-88: no target position to fire at");
+                    continue;
+                },
+            },
+        };
+
+        info!("Firing AGM-88 {:?}", launcher.mode);
+        launcher.missiles -= 1;
+        detectable.open_bay_doors(BAY_DOOR_OPEN_TIME);
+        commands.spawn(AudioPlayer::new(asset_server.load("sounds/internallaunch.ogg")));
+        commands.spawn(SceneRoot(asset_server.load("models/weapons/agm-65.glb#Scene0")))
+        .insert(Missile {
+            launching_vehicle: entity,
+            target: emitter.unwrap_or(Entity::PLACEHOLDER),
+            target_transform: Transform::from_translation(aim_position),
+            max_thrust: 60.0,
+            motor_burn_time: 20000,
+            warhead_radius: 3.0,
+            warhead_damage: 150.0,
+            seeker_state: if emitter.is_some() { SeekerState::IGNITION } else { SeekerState::TARGET_LOST },
+            ..default()
+        })
+        .insert(ArmSeeker {
+            side: coalition.side,
+            emitter,
+            last_known_position: aim_position,
+        })
+        .insert(*transform)
+        .insert(Velocity { linvel: velocity.linvel, ..default() })
+        .insert(ExternalForce { ..default() })
+        .insert(Collider::cuboid(0.2, 0.05, 0.2))
+        .insert(ActiveEvents::COLLISION_EVENTS)
+        .insert(CollisionGroups::new(
+            Group::from_bits_truncate(COLLISION_MASK_MISSILE),
+            Group::from_bits_truncate(
+                COLLISION_MASK_TERRAIN | COLLISION_MASK_AIRCRAFT |
+                COLLISION_MASK_GROUNDVEHICLE | COLLISION_MASK_MISSILE)))
+        .insert(Ccd::enabled())
+        .insert(Restitution::coefficient(0.4))
+        .insert(RigidBody::Dynamic)
+        .insert(GravityScale(1.0))
+        .insert(Damping { linear_damping: 0.3, angular_damping: 1.0 })
+        .insert(ColliderMassProperties::Density(15.0))
+        .insert(Targetable);
+    }
+}
+
+/// Home on the emitter while it radiates, fly on to where it was heard last when it goes dark,
+/// and take any emitter that comes on near that position.
+pub fn update_arm_seekers(
+    mut missiles: Query<(&mut Missile, &mut ArmSeeker, &Transform)>,
+    emitters: Query<(Entity, &RadarEmitter, &Transform, &Coalition), Without<Missile>>,
+) {
+    for (mut missile, mut seeker, missile_transform) in missiles.iter_mut() {
+        let seeker_position = missile_transform.translation;
+        let boresight = (seeker.last_known_position - seeker_position).normalize_or(*missile_transform.forward());
+        let side = seeker.side;
+        let tracked = seeker.emitter.and_then(|e| emitters.get(e).ok())
+            .filter(|(_, radar_emitter, transform, coalition)| in_seeker_view(radar_emitter, coalition, side, seeker_position, boresight, transform.translation));
+        let heard = tracked.or_else(|| emitters.iter()
+            .filter(|(_, radar_emitter, transform, coalition)| {
+                transform.translation.distance(seeker.last_known_position) < ARM_SEARCH_RADIUS
+                    && in_seeker_view(radar_emitter, coalition, side, seeker_position, boresight, transform.translation)
+            })
+            .min_by(|a, b| a.2.translation.distance(seeker.last_known_position).total_cmp(&b.2.translation.distance(seeker.last_known_position))));
+
+        match heard {
+            Some((emitter, _, transform, _)) => {
+                if seeker.emitter != Some(emitter) || missile.seeker_state == SeekerState::TARGET_LOST {
+                    info!("AGM-88 seeker locked on an emitter");
+                    // The proximity fuze starts over with the new target
+                    missile.last_target_distance = f32::MAX;
+                }
+                seeker.emitter = Some(emitter);
+                seeker.last_known_position = transform.translation;
+                missile.target = emitter;
+                missile.target_transform.translation = transform.translation;
+                if missile.seeker_state == SeekerState::TARGET_LOST {
+                    missile.seeker_state = SeekerState::TRACKING;
+                }
+            },
+            None => {
+                if missile.seeker_state != SeekerState::TARGET_LOST {
+                    info!("AGM-88 lost the emitter, flying on to its last known position");
+                    missile.seeker_state = SeekerState::TARGET_LOST;
+                }
+                missile.target_transform.translation = seeker.last_known_position;
+            },
+        }
+    }
+}
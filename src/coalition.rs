@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum CoalitionType {
     RED,
     BLUE,
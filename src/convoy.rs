@@ -0,0 +1,255 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::coalition::{Coalition, CoalitionType};
+use crate::definitions::*;
+use crate::explosion::{Volatile, VolatileCargo};
+use crate::health::Health;
+use crate::road_network::{RoadNetwork, RoadNodeType};
+use crate::targeting::Targetable;
+use crate::terrain::TerrainData;
+use crate::util::{get_time_millis, random_u64};
+use crate::vehicle::Vehicle;
+
+/* Road traffic. A convoy drives a route through the road network, every vehicle keeping its
+   place in the column and following the height of the road. At the end of the route it stops to
+   unload, then path-finds to another city. A convoy that loses a vehicle halts for a while
+   before the survivors drive on. */
+
+/// Gap between two vehicles of a convoy
+const CONVOY_SPACING: f32 = 8.0;
+/// Convoy speed in world units per second
+const CONVOY_SPEED: f32 = 5.0;
+/// A convoy that lost a vehicle stops for this long before driving on (ms)
+const CONVOY_HALT_TIME: u64 = 60000;
+/// Time spent at the end of the route before driving on (ms)
+const CONVOY_UNLOAD_TIME: u64 = 30000;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConvoyVehicleType {
+    TRUCK,
+    SA6_TEL,
+    SCUD_TEL,
+}
+
+pub struct ConvoyVehicleData {
+    pub size: Vec3,
+    pub health: f32,
+    pub cargo: VolatileCargo,
+    pub model: Option<&'static str>,
+}
+
+impl ConvoyVehicleType {
+    pub fn data(&self) -> ConvoyVehicleData {
+        match self {
+            ConvoyVehicleType::TRUCK => ConvoyVehicleData {
+                size: Vec3::new(0.5, 0.5, 1.2),
+                health: 50.0,
+                cargo: VolatileCargo::FUEL,
+                model: None,
+            },
+            ConvoyVehicleType::SA6_TEL => ConvoyVehicleData {
+                size: Vec3::new(0.5, 0.7, 0.8),
+                health: 100.0,
+                cargo: VolatileCargo::AMMUNITION,
+                model: Some("models/vehicles/SA6.gltf#Scene0"),
+            },
+            ConvoyVehicleType::SCUD_TEL => ConvoyVehicleData {
+                size: Vec3::new(0.6, 0.6, 2.0),
+                health: 120.0,
+                cargo: VolatileCargo::FUEL,
+                model: None,
+            },
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ConvoyVehicle {
+    pub vehicle_type: ConvoyVehicleType,
+}
+
+#[derive(Component)]
+pub struct Convoy {
+    pub name: String,
+    pub route: Vec<Vec2>, // Original terrain coordinates
+    pub origin: usize, // Road network nodes at both ends of the route
+    pub destination: usize,
+    pub vehicles: Vec<Entity>, // Lead vehicle first, destroyed ones keep their place
+    pub speed: f32,
+    pub distance: f32, // Distance of the lead vehicle along the route
+    pub halted_until: u64,
+    pub survivors: usize,
+}
+
+/// Total length of a polyline
+pub fn route_length(route: &[Vec2]) -> f32 {
+    route.windows(2).map(|s| (s[1] - s[0]).length()).sum()
+}
+
+/// Point at the given distance along a polyline and the direction of travel there
+pub fn route_point(route: &[Vec2], distance: f32) -> (Vec2, Vec2) {
+    let mut travelled = 0.0;
+    for s in route.windows(2) {
+        let length = (s[1] - s[0]).length();
+        if length > 0.0 && travelled + length >= distance {
+            return (s[0].lerp(s[1], (distance - travelled) / length), (s[1] - s[0]) / length);
+        }
+        travelled += length;
+    }
+    let n = route.len();
+    (route[n - 1], (route[n - 1] - route[n - 2]).normalize_or(Vec2::X))
+}
+
+/// The part of a polyline from the given distance to its end
+fn route_tail(route: &[Vec2], distance: f32) -> Vec<Vec2> {
+    let mut tail = vec![route_point(route, distance).0];
+    let mut travelled = 0.0;
+    for s in route.windows(2) {
+        travelled += (s[1] - s[0]).length();
+        if travelled > distance {
+            tail.push(s[1]);
+        }
+    }
+    tail.dedup();
+    tail
+}
+
+/// Place a vehicle on the road, at its ride height and pointing along the road
+pub fn place_on_road(transform: &mut Transform, route: &[Vec2], distance: f32, ride_height: f32, terrain: &TerrainData) {
+    let (point, direction) = route_point(route, distance);
+    let (x, z) = (point.x - terrain.origin_shift.x, point.y - terrain.origin_shift.z);
+    transform.translation = Vec3::new(x, terrain.get_height_world(x, z) + ride_height, z);
+    transform.rotation = Quat::from_rotation_y(direction.x.atan2(direction.y));
+}
+
+/// Line up a column of vehicles at the start of the route
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_convoy(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    terrain: &TerrainData,
+    name: &str,
+    route: Vec<Vec2>,
+    origin: usize,
+    destination: usize,
+    vehicle_types: &[ConvoyVehicleType],
+    side: CoalitionType,
+) {
+    let material = materials.add(Color::srgb(0.33, 0.35, 0.27));
+    let distance = CONVOY_SPACING * (vehicle_types.len() as f32 - 1.0);
+    let vehicles: Vec<Entity> = vehicle_types.iter().enumerate().map(|(i, vehicle_type)| {
+        let data = vehicle_type.data();
+        let mut transform = Transform::default();
+        place_on_road(&mut transform, &route, distance - i as f32 * CONVOY_SPACING, data.size.y / 2.0, terrain);
+        let vehicle = match data.model {
+            Some(model) => commands.spawn(SceneRoot(asset_server.load(model))).id(),
+            None => commands.spawn((
+                Mesh3d(meshes.add(Cuboid::new(data.size.x, data.size.y, data.size.z))),
+                MeshMaterial3d(material.clone()),
+            )).id(),
+        };
+        commands.entity(vehicle)
+        .insert(transform)
+        .insert(Name::new(format!("{} {:?}", name, vehicle_type)))
+        .insert(ConvoyVehicle{vehicle_type: *vehicle_type})
+        .insert(Vehicle{..default()})
+        .insert(Coalition{side: side})
+        .insert(Collider::cuboid(data.size.x / 2.0, data.size.y / 2.0, data.size.z / 2.0))
+        .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_GROUNDVEHICLE),
+            Group::from_bits_truncate(
+                COLLISION_MASK_AIRCRAFT |
+                COLLISION_MASK_MISSILE |
+                COLLISION_MASK_PLAYER
+            )))
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(Targetable)
+        .insert(Health{health: data.health})
+        .insert(Volatile{cargo: data.cargo});
+        vehicle
+    }).collect();
+
+    info!("{} on the road with {} vehicles", name, vehicles.len());
+    commands.spawn(Convoy {
+        name: String::from(name),
+        route,
+        origin,
+        destination,
+        survivors: vehicles.len(),
+        vehicles,
+        speed: CONVOY_SPEED,
+        distance,
+        halted_until: 0,
+    });
+}
+
+pub fn update_convoys(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut convoys: Query<(Entity, &mut Convoy)>,
+    mut vehicles: Query<(&mut Transform, &ConvoyVehicle)>,
+    terrain: Option<Res<TerrainData>>,
+    road_network: Option<Res<RoadNetwork>>,
+) {
+    let Some(terrain) = terrain else { return };
+    let milliseconds = get_time_millis();
+    for (convoy_entity, mut convoy) in convoys.iter_mut() {
+        let survivors = convoy.vehicles.iter().filter(|v| vehicles.contains(**v)).count();
+        if survivors == 0 {
+            info!("{} wiped out", convoy.name);
+            commands.entity(convoy_entity).despawn();
+            continue;
+        }
+        if survivors < convoy.survivors {
+            info!("{} lost a vehicle, halting", convoy.name);
+            convoy.halted_until = milliseconds + CONVOY_HALT_TIME;
+        }
+        convoy.survivors = survivors;
+        if milliseconds < convoy.halted_until {
+            continue;
+        }
+
+        convoy.distance += convoy.speed * time.delta_secs();
+        let length = route_length(&convoy.route);
+        if convoy.distance >= length {
+            info!("{} reached its destination", convoy.name);
+            convoy.halted_until = milliseconds + CONVOY_UNLOAD_TIME;
+            let column_length = CONVOY_SPACING * (convoy.vehicles.len() as f32 - 1.0);
+            let destinations: Vec<usize> = road_network.iter()
+                .flat_map(|network| network.nodes.iter().enumerate())
+                .filter(|(i, n)| *i != convoy.destination && matches!(n.node_type, RoadNodeType::CITY(_)))
+                .map(|(i, _)| i)
+                .collect();
+            let next = (!destinations.is_empty())
+                .then(|| destinations[random_u64(0, destinations.len() as u64) as usize])
+                .and_then(|to| road_network.as_deref().and_then(|n| n.route(convoy.destination, to)).map(|route| (to, route)));
+            match next {
+                Some((to, route)) => {
+                    // The column stays where it is, on the tail of the old route
+                    let mut new_route = route_tail(&convoy.route, (length - column_length).max(0.0));
+                    new_route.extend(route.into_iter().skip(1));
+                    convoy.route = new_route;
+                    convoy.origin = convoy.destination;
+                    convoy.destination = to;
+                },
+                None => {
+                    // Nowhere else to go, the last vehicle leads the way back
+                    convoy.route.reverse();
+                    convoy.vehicles.reverse();
+                    let origin = convoy.origin;
+                    convoy.origin = convoy.destination;
+                    convoy.destination = origin;
+                },
+            }
+            convoy.distance = column_length;
+        }
+        for (i, entity) in convoy.vehicles.iter().enumerate() {
+            let Ok((mut transform, vehicle)) = vehicles.get_mut(*entity) else { continue };
+            let distance = (convoy.distance - i as f32 * CONVOY_SPACING).max(0.0);
+            place_on_road(&mut transform, &convoy.route, distance, vehicle.vehicle_type.data().size.y / 2.0, &terrain);
+        }
+    }
+}
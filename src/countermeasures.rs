@@ -0,0 +1,302 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::coalition::Coalition;
+use crate::explosion::Wind;
+use crate::infrared::IrSignature;
+use crate::player::Player;
+use crate::pointlight::{LightBillboardToBeAdded, LightColor, LightSourceType, LightType};
+use crate::radar::RadarDetectable;
+use crate::targeting::Targetable;
+use crate::util::{get_time_millis, random_vec3};
+
+/* Radar and IR countermeasures. Chaff bundles blossom into a cloud with a large radar cross section
+   that a radar may track instead of the aircraft that dropped it. A noise jammer hides the
+   aircraft's echo in noise, but radars capable of it can home on the jamming instead.
+   Flares burn hotter than any engine for a few seconds to pull IR seekers off the aircraft. */
+
+/// How long the dispenser doors stay open after a release (ms)
+const DISPENSER_OPEN_TIME: u64 = 600;
+/// Chaff slows down to the speed of the surrounding air quickly
+const CHAFF_DRAG: f32 = 2.5;
+/// Sink rate of a chaff cloud in world units per second
+const CHAFF_FALL_SPEED: f32 = 0.5;
+/// Flares are heavier than chaff and keep more of the aircraft's speed
+const FLARE_DRAG: f32 = 0.8;
+/// Flares reach full intensity this long after ignition (ms)
+const FLARE_IGNITION_TIME: u64 = 150;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChaffProgram {
+    SINGLE,
+    DOUBLE,
+    SALVO,
+}
+
+impl ChaffProgram {
+    fn bundles(&self) -> u32 {
+        match self {
+            ChaffProgram::SINGLE => 1,
+            ChaffProgram::DOUBLE => 2,
+            ChaffProgram::SALVO => 6,
+        }
+    }
+
+    fn interval(&self) -> u64 {
+        match self {
+            ChaffProgram::SINGLE => 0,
+            ChaffProgram::DOUBLE => 300,
+            ChaffProgram::SALVO => 150,
+        }
+    }
+
+    fn next(&self) -> ChaffProgram {
+        match self {
+            ChaffProgram::SINGLE => ChaffProgram::DOUBLE,
+            ChaffProgram::DOUBLE => ChaffProgram::SALVO,
+            ChaffProgram::SALVO => ChaffProgram::SINGLE,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ChaffDispenser {
+    pub chaff_count: u32,
+    pub program: ChaffProgram,
+    pub bundles_pending: u32, // Remaining bundles of the running program
+    pub last_release_time: u64,
+}
+
+impl Default for ChaffDispenser {
+    fn default() -> Self {
+        ChaffDispenser {
+            chaff_count: 30,
+            program: ChaffProgram::DOUBLE,
+            bundles_pending: 0,
+            last_release_time: 0,
+        }
+    }
+}
+
+/// A chaff cloud. Its RCS blooms while the bundle opens up, then decays as the dipoles disperse.
+#[derive(Component)]
+pub struct Chaff {
+    pub release_time: u64,
+    pub peak_rcs: f32,
+    pub bloom_time: u64,
+    pub life_time: u64,
+    pub velocity: Vec3,
+}
+
+#[derive(Component)]
+pub struct FlareDispenser {
+    pub flare_count: u32,
+    pub flares_per_release: u32,
+    pub release_interval: u64, // ms between flares of one release
+    pub flares_pending: u32,
+    pub last_release_time: u64,
+}
+
+impl Default for FlareDispenser {
+    fn default() -> Self {
+        FlareDispenser {
+            flare_count: 24,
+            flares_per_release: 2,
+            release_interval: 200,
+            flares_pending: 0,
+            last_release_time: 0,
+        }
+    }
+}
+
+/// A burning flare. Its IR intensity drops off as the pellet burns down.
+#[derive(Component)]
+pub struct Flare {
+    pub release_time: u64,
+    pub peak_intensity: f32,
+    pub burn_time: u64,
+    pub velocity: Vec3,
+}
+
+#[derive(Component)]
+pub struct NoiseJammer {
+    pub active: bool,
+    pub power: f32, // Added to the noise floor of radars painting the jammer, at the edge of their range
+}
+
+impl Default for NoiseJammer {
+    fn default() -> Self {
+        NoiseJammer {
+            active: false,
+            power: 20.0,
+        }
+    }
+}
+
+pub fn handle_countermeasure_controls(
+    input: Res<ButtonInput<KeyCode>>,
+    mut players: Query<(&mut ChaffDispenser, Option<&mut FlareDispenser>, Option<&mut NoiseJammer>), With<Player>>,
+) {
+    for (mut dispenser, flare_dispenser, jammer) in players.iter_mut() {
+        if input.just_pressed(KeyCode::KeyV) {
+            dispenser.program = dispenser.program.next();
+            info!("Chaff program {:?}", dispenser.program);
+        }
+        if input.just_pressed(KeyCode::KeyC) && dispenser.bundles_pending == 0 {
+            dispenser.bundles_pending = dispenser.program.bundles();
+        }
+        if let Some(mut flare_dispenser) = flare_dispenser {
+            if input.just_pressed(KeyCode::KeyF) && flare_dispenser.flares_pending == 0 {
+                flare_dispenser.flares_pending = flare_dispenser.flares_per_release;
+            }
+        }
+        if let Some(mut jammer) = jammer {
+            if input.just_pressed(KeyCode::KeyX) {
+                jammer.active = !jammer.active;
+                info!("Jammer {}", if jammer.active { "on" } else { "off" });
+            }
+        }
+    }
+}
+
+/// Run the chaff programs and release bundles
+pub fn update_chaff_dispensers(
+    mut commands: Commands,
+    mut dispensers: Query<(&mut ChaffDispenser, &mut RadarDetectable, &Transform, &Coalition, Option<&Velocity>)>,
+) {
+    let milliseconds = get_time_millis();
+    for (mut dispenser, mut detectable, transform, coalition, velocity) in dispensers.iter_mut() {
+        if dispenser.bundles_pending == 0 {
+            continue;
+        }
+        if dispenser.chaff_count == 0 {
+            dispenser.bundles_pending = 0;
+            continue;
+        }
+        if milliseconds - dispenser.last_release_time < dispenser.program.interval() {
+            continue;
+        }
+        dispenser.bundles_pending -= 1;
+        dispenser.chaff_count -= 1;
+        dispenser.last_release_time = milliseconds;
+        // Opening the dispenser is not stealthy
+        detectable.dispenser_open_until = milliseconds + DISPENSER_OPEN_TIME;
+
+        let aircraft_velocity = velocity.map(|v| v.linvel).unwrap_or(Vec3::ZERO);
+        commands.spawn((
+            Chaff {
+                release_time: milliseconds,
+                peak_rcs: 3.0,
+                bloom_time: 500,
+                life_time: 8000,
+                velocity: aircraft_velocity + random_vec3(2.0) - transform.up() * 3.0,
+            },
+            RadarDetectable {
+                base_radar_cross_section: 0.0,
+                ..default()
+            },
+            Coalition { side: coalition.side },
+            Transform::from_translation(transform.translation),
+        ));
+    }
+}
+
+pub fn update_chaff(
+    mut commands: Commands,
+    mut chaff_clouds: Query<(Entity, &mut Chaff, &mut RadarDetectable, &mut Transform)>,
+    wind: Res<Wind>,
+    time: Res<Time>,
+) {
+    let milliseconds = get_time_millis();
+    let dt = time.delta_secs();
+    for (entity, mut chaff, mut detectable, mut transform) in chaff_clouds.iter_mut() {
+        let age = milliseconds - chaff.release_time;
+        if age > chaff.life_time {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        detectable.base_radar_cross_section = if age < chaff.bloom_time {
+            chaff.peak_rcs * age as f32 / chaff.bloom_time as f32
+        } else {
+            chaff.peak_rcs * (1.0 - (age - chaff.bloom_time) as f32 / (chaff.life_time - chaff.bloom_time) as f32)
+        };
+
+        // Drift with the wind and slowly fall
+        let air_velocity = wind.velocity - Vec3::Y * CHAFF_FALL_SPEED;
+        chaff.velocity = chaff.velocity.lerp(air_velocity, (CHAFF_DRAG * dt).min(1.0));
+        transform.translation += chaff.velocity * dt;
+    }
+}
+
+/// Release flares, one at a time
+pub fn update_flare_dispensers(
+    mut commands: Commands,
+    mut dispensers: Query<(&mut FlareDispenser, &Transform, Option<&Velocity>)>,
+) {
+    let milliseconds = get_time_millis();
+    for (mut dispenser, transform, velocity) in dispensers.iter_mut() {
+        if dispenser.flares_pending == 0 {
+            continue;
+        }
+        if dispenser.flare_count == 0 {
+            dispenser.flares_pending = 0;
+            continue;
+        }
+        if milliseconds - dispenser.last_release_time < dispenser.release_interval {
+            continue;
+        }
+        dispenser.flares_pending -= 1;
+        dispenser.flare_count -= 1;
+        dispenser.last_release_time = milliseconds;
+
+        let aircraft_velocity = velocity.map(|v| v.linvel).unwrap_or(Vec3::ZERO);
+        commands.spawn((
+            Flare {
+                release_time: milliseconds,
+                peak_intensity: 40.0,
+                burn_time: 4000,
+                velocity: aircraft_velocity + random_vec3(1.0) - transform.up() * 8.0,
+            },
+            IrSignature {
+                max_engine_intensity: 0.0,
+                skin_heating: 0.0,
+                ..default()
+            },
+            LightBillboardToBeAdded {
+                light_color: LightColor::WHITE,
+                light_type: LightType::SOLID,
+                lightsource_type: LightSourceType::POINT,
+            },
+            Targetable,
+            Transform::from_translation(transform.translation),
+            Visibility::default(),
+        ));
+    }
+}
+
+pub fn update_flares(
+    mut commands: Commands,
+    mut flares: Query<(Entity, &mut Flare, &mut IrSignature, &mut Transform)>,
+    wind: Res<Wind>,
+    time: Res<Time>,
+) {
+    let milliseconds = get_time_millis();
+    let dt = time.delta_secs();
+    for (entity, mut flare, mut signature, mut transform) in flares.iter_mut() {
+        let age = milliseconds - flare.release_time;
+        if age > flare.burn_time {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        signature.skin_intensity = if age < FLARE_IGNITION_TIME {
+            flare.peak_intensity * age as f32 / FLARE_IGNITION_TIME as f32
+        } else {
+            flare.peak_intensity * (1.0 - (age - FLARE_IGNITION_TIME) as f32 / (flare.burn_time - FLARE_IGNITION_TIME) as f32)
+        };
+
+        // Slow down to the speed of the wind while falling freely
+        let drag = (wind.velocity - flare.velocity) * (FLARE_DRAG * dt).min(1.0);
+        flare.velocity += drag + Vec3::new(0.0, -9.81, 0.0) * dt;
+        transform.translation += flare.velocity * dt;
+    }
+}
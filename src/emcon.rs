@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::coalition::Coalition;
+use crate::line_of_sight::check_line_of_sight;
+use crate::player::Player;
+use crate::radar::RadarEmitter;
+use crate::targeting::SensorTarget;
+use crate::terrain::TerrainData;
+use crate::util::{get_time_millis, random_vec3};
+
+/* Emission control. Apart from the FLIR, every sensor of the aircraft emits something an enemy
+   ELINT receiver can pick up: the radar altimeter, the laser rangefinder/designator, the datalink
+   and the IFF transponder. The EMCON level decides which of them are allowed to transmit.
+   Intercepted emissions give the enemy a rough position to point their radars at. */
+
+/// Half angle of the radar altimeter beam, pointing straight down (radians)
+const RADAR_ALTIMETER_BEAM: f32 = 1.0;
+/// Laser warning receivers pick up the designator spot within this distance of the target
+const LASER_WARNING_RADIUS: f32 = 1000.0;
+/// Intercepts are this inaccurate per unit of distance to the emitter
+const ELINT_POSITION_ERROR: f32 = 0.05;
+/// Intercepts older than this are forgotten (ms)
+const ELINT_MEMORY: u64 = 15000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EmconLevel {
+    SILENT, // Passive sensors only
+    LIMITED, // Radar altimeter and datalink
+    FULL, // Everything on
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EmitterType {
+    RADAR_ALTIMETER,
+    LASER,
+    DATALINK,
+    IFF,
+}
+
+impl EmitterType {
+    /// Distance at which an ELINT receiver of standard sensitivity intercepts this emitter
+    pub fn intercept_range(&self) -> f32 {
+        match self {
+            EmitterType::RADAR_ALTIMETER => 8000.0,
+            EmitterType::LASER => 5000.0,
+            EmitterType::DATALINK => 25000.0,
+            EmitterType::IFF => 40000.0,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Emcon {
+    pub level: EmconLevel,
+}
+
+impl Default for Emcon {
+    fn default() -> Self {
+        Emcon {
+            level: EmconLevel::SILENT,
+        }
+    }
+}
+
+impl Emcon {
+    pub fn next_level(&self) -> EmconLevel {
+        match self.level {
+            EmconLevel::SILENT => EmconLevel::LIMITED,
+            EmconLevel::LIMITED => EmconLevel::FULL,
+            EmconLevel::FULL => EmconLevel::SILENT,
+        }
+    }
+
+    pub fn is_active(&self, emitter: EmitterType) -> bool {
+        match self.level {
+            EmconLevel::SILENT => false,
+            EmconLevel::LIMITED => matches!(emitter, EmitterType::RADAR_ALTIMETER | EmitterType::DATALINK),
+            EmconLevel::FULL => true,
+        }
+    }
+
+    /// Short label for the HUD
+    pub fn label(&self) -> &'static str {
+        match self.level {
+            EmconLevel::SILENT => "EMCON 1",
+            EmconLevel::LIMITED => "EMCON 2",
+            EmconLevel::FULL => "EMCON 3",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ElintIntercept {
+    pub position: Vec3, // Estimated emitter position
+    pub time: u64,
+}
+
+/// Passive receiver listening for emissions of hostile aircraft
+#[derive(Component)]
+pub struct ElintReceiver {
+    pub sensitivity: f32, // Multiplies the intercept range of every emitter type
+    pub intercepts: HashMap<Entity, ElintIntercept>,
+}
+
+impl Default for ElintReceiver {
+    fn default() -> Self {
+        ElintReceiver {
+            sensitivity: 1.0,
+            intercepts: HashMap::new(),
+        }
+    }
+}
+
+pub fn handle_emcon_controls(
+    input: Res<ButtonInput<KeyCode>>,
+    mut players: Query<&mut Emcon, With<Player>>,
+) {
+    if input.just_pressed(KeyCode::KeyE) {
+        for mut emcon in players.iter_mut() {
+            emcon.level = emcon.next_level();
+            info!("{}", emcon.label());
+        }
+    }
+}
+
+/// Let ELINT receivers listen for active emitters, and point the receiver's own radar at what they hear
+pub fn update_elint(
+    emitters: Query<(Entity, &Emcon, &Transform, &Coalition)>,
+    mut receivers: Query<(&mut ElintReceiver, &Transform, &Coalition, Option<&mut RadarEmitter>), Without<Emcon>>,
+    designated_targets: Query<&Transform, (With<SensorTarget>, Without<Emcon>, Without<ElintReceiver>)>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let milliseconds = get_time_millis();
+    let designated_position = designated_targets.iter().next().map(|t| t.translation);
+    for (mut receiver, receiver_transform, receiver_coalition, mut radar_emitter) in receivers.iter_mut() {
+        receiver.intercepts.retain(|_, intercept| milliseconds - intercept.time < ELINT_MEMORY);
+        for (emitter_entity, emcon, emitter_transform, emitter_coalition) in emitters.iter() {
+            if emitter_coalition.side == receiver_coalition.side || emcon.level == EmconLevel::SILENT {
+                continue;
+            }
+            let to_receiver = receiver_transform.translation - emitter_transform.translation;
+            let distance = to_receiver.length();
+            let intercepted = [EmitterType::RADAR_ALTIMETER, EmitterType::LASER, EmitterType::DATALINK, EmitterType::IFF].into_iter()
+                .filter(|emitter_type| emcon.is_active(*emitter_type))
+                .find(|emitter_type| {
+                    if distance > emitter_type.intercept_range() * receiver.sensitivity {
+                        return false;
+                    }
+                    match emitter_type {
+                        // The altimeter only radiates downwards
+                        EmitterType::RADAR_ALTIMETER => to_receiver.angle_between(Vec3::NEG_Y) < RADAR_ALTIMETER_BEAM,
+                        // The laser is only seen around the spot it designates
+                        EmitterType::LASER => designated_position
+                            .is_some_and(|spot| spot.distance(receiver_transform.translation) < LASER_WARNING_RADIUS),
+                        EmitterType::DATALINK | EmitterType::IFF => true,
+                    }
+                });
+            let Some(emitter_type) = intercepted else { continue };
+            if !check_line_of_sight(None, terrain.as_deref(), emitter_entity, receiver_transform.translation, emitter_transform.translation) {
+                continue;
+            }
+
+            if !receiver.intercepts.contains_key(&emitter_entity) {
+                info!("ELINT intercept: {:?}", emitter_type);
+            }
+            // Direction finding gets less accurate with distance
+            let position = emitter_transform.translation + random_vec3((distance * ELINT_POSITION_ERROR).max(0.1));
+            receiver.intercepts.insert(emitter_entity, ElintIntercept { position, time: milliseconds });
+            if let Some(radar_emitter) = radar_emitter.as_deref_mut() {
+                if radar_emitter.emitting {
+                    radar_emitter.cue(emitter_entity, position, milliseconds);
+                }
+            }
+        }
+    }
+}
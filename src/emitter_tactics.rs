@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::RigidBody;
+
+use crate::arm::ArmSeeker;
+use crate::missile::Missile;
+use crate::radar::{RadarEmitter, RadarMode};
+use crate::sam::SamBattery;
+use crate::terrain::TerrainData;
+use crate::util::{get_time_millis, random_f32};
+
+/* Emitter tactics. A radar that is always on is a radar that gets found, so the crews ration
+   their emissions. Some sites blink, coming on for short random periods, some go dark as soon as
+   a missile is homing on their radar, and mobile batteries pack up after firing and drive to
+   another pre-surveyed position, leaving the briefed position empty. Decoy emitters imitate a
+   fire-control radar with no missiles behind it. Which tactics a site uses is decided per site
+   when the world is laid out. */
+
+/// Distance at which the crew notices a missile homing on its radar
+const ARM_WARNING_RANGE: f32 = 15000.0;
+/// Radars stay dark for this long after the last missile homing on them is gone (ms)
+const ARM_SHUTDOWN_HOLD: u64 = 30000;
+
+/// Periodic emission with random silent periods, in seconds
+#[derive(Debug, Copy, Clone)]
+pub struct BlinkCycle {
+    pub min_on_time: f32,
+    pub max_on_time: f32,
+    pub min_off_time: f32,
+    pub max_off_time: f32,
+}
+
+impl Default for BlinkCycle {
+    fn default() -> Self {
+        BlinkCycle {
+            min_on_time: 15.0,
+            max_on_time: 45.0,
+            min_off_time: 20.0,
+            max_off_time: 90.0,
+        }
+    }
+}
+
+/// Pre-surveyed positions a mobile battery moves between after firing
+#[derive(Debug, Clone)]
+pub struct ScootPlan {
+    pub positions: Vec<Vec2>, // Original terrain coordinates, the battery starts at the first one
+    pub scoot_delay: f32, // Seconds after the last launch before packing up
+    pub drive_speed: f32, // World units per second
+}
+
+impl Default for ScootPlan {
+    fn default() -> Self {
+        ScootPlan {
+            positions: Vec::new(),
+            scoot_delay: 20.0,
+            drive_speed: 4.0,
+        }
+    }
+}
+
+/// How a battery uses its radars to stay alive
+#[derive(Component)]
+pub struct EmitterTactics {
+    pub blink: Option<BlinkCycle>,
+    pub arm_shutdown: bool, // Go dark while a missile is homing on the radars
+    pub scoot: Option<ScootPlan>,
+    pub silent: bool, // In the dark part of the blink cycle
+    pub next_blink_time: u64,
+    pub arm_threat_time: Option<u64>, // Last time a missile was seen homing on the radars
+    pub position_index: usize,
+    pub relocating: bool,
+    pub emplaced_time: u64,
+}
+
+impl Default for EmitterTactics {
+    fn default() -> Self {
+        EmitterTactics {
+            blink: None,
+            arm_shutdown: false,
+            scoot: None,
+            silent: false,
+            next_blink_time: 0,
+            arm_threat_time: None,
+            position_index: 0,
+            relocating: false,
+            emplaced_time: 0,
+        }
+    }
+}
+
+/// A radar imitating the fire-control radar of a SAM system, with nothing to fire behind it
+#[derive(Component)]
+pub struct DecoyEmitter;
+
+/// Overrides the radars of batteries with tactics, after the IADS and the battery have decided
+/// whether they should be on. A blinking radar doesn't go dark in the middle of an engagement.
+pub fn update_emitter_tactics(
+    time: Res<Time>,
+    mut batteries: Query<(&SamBattery, &mut EmitterTactics, &mut Transform)>,
+    mut radars: Query<&mut RadarEmitter>,
+    mut vehicles: Query<(&mut Transform, &mut RigidBody), (Without<SamBattery>, Without<Missile>)>,
+    missiles: Query<(&ArmSeeker, &Transform), (With<Missile>, Without<SamBattery>)>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let milliseconds = get_time_millis();
+    for (battery, mut tactics, mut battery_transform) in batteries.iter_mut() {
+        let radar_entities = [battery.acquisition_radar, battery.fire_control_radar];
+        let site = battery_transform.translation;
+
+        if tactics.arm_shutdown {
+            let threatened = missiles.iter().any(|(seeker, transform)| {
+                seeker.emitter.is_some_and(|e| radar_entities.contains(&e))
+                    && transform.translation.distance(site) < ARM_WARNING_RANGE
+            });
+            if threatened {
+                if tactics.arm_threat_time.is_none() {
+                    info!("{} shutting down its radars, missile inbound", battery.name);
+                }
+                tactics.arm_threat_time = Some(milliseconds);
+            } else if tactics.arm_threat_time.is_some_and(|t| milliseconds - t > ARM_SHUTDOWN_HOLD) {
+                info!("{} radars back on", battery.name);
+                tactics.arm_threat_time = None;
+            }
+        }
+
+        if let Some(blink) = tactics.blink {
+            if milliseconds >= tactics.next_blink_time {
+                tactics.silent = !tactics.silent;
+                let duration = if tactics.silent {
+                    random_f32(blink.min_off_time, blink.max_off_time)
+                } else {
+                    random_f32(blink.min_on_time, blink.max_on_time)
+                };
+                tactics.next_blink_time = milliseconds + (duration * 1000.0) as u64;
+            }
+        }
+
+        if let (Some(plan), Some(terrain)) = (tactics.scoot.clone(), terrain.as_deref()) {
+            if !tactics.relocating && battery.last_launch_time > tactics.emplaced_time
+                && milliseconds - battery.last_launch_time > (plan.scoot_delay * 1000.0) as u64 {
+                tactics.relocating = true;
+                tactics.position_index = (tactics.position_index + 1) % plan.positions.len();
+                info!("{} packing up and moving to another position", battery.name);
+            }
+            if tactics.relocating {
+                // Everything drives in formation, keeping its place on the site
+                let destination = plan.positions[tactics.position_index];
+                let destination = Vec3::new(destination.x, 0.0, destination.y) - terrain.origin_shift;
+                let remaining = Vec3::new(destination.x - site.x, 0.0, destination.z - site.z);
+                let step = remaining.clamp_length_max(plan.drive_speed * time.delta_secs());
+                let arrived = step.length() >= remaining.length();
+                let mut vehicle_entities: Vec<Entity> = battery.launchers.iter().chain(battery.loaders.iter())
+                    .chain(radar_entities.iter()).copied().collect();
+                vehicle_entities.sort();
+                vehicle_entities.dedup();
+                for entity in vehicle_entities {
+                    let Ok((mut transform, mut body)) = vehicles.get_mut(entity) else { continue };
+                    let rigid_body = if arrived { RigidBody::Dynamic } else { RigidBody::KinematicPositionBased };
+                    if *body != rigid_body {
+                        *body = rigid_body;
+                    }
+                    let p = transform.translation;
+                    let ride_height = p.y - terrain.get_height_world(p.x, p.z);
+                    let (x, z) = (p.x + step.x, p.z + step.z);
+                    transform.translation = Vec3::new(x, terrain.get_height_world(x, z) + ride_height, z);
+                }
+                let (x, z) = (site.x + step.x, site.z + step.z);
+                battery_transform.translation = Vec3::new(x, terrain.get_height_world(x, z), z);
+                if arrived {
+                    info!("{} set up at its new position", battery.name);
+                    tactics.relocating = false;
+                    tactics.emplaced_time = milliseconds;
+                }
+            }
+        }
+
+        let engaging = radars.get(battery.fire_control_radar)
+            .is_ok_and(|r| r.emitting && matches!(r.mode, RadarMode::LOCK | RadarMode::LAUNCH));
+        let dark = tactics.relocating || tactics.arm_threat_time.is_some() || (tactics.silent && !engaging);
+        if dark {
+            for entity in radar_entities {
+                if let Ok(mut radar_emitter) = radars.get_mut(entity) {
+                    radar_emitter.emitting = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::coalition::CoalitionType;
+    use crate::sam::{EngagementEnvelope, SAMType};
+
+    #[test]
+    fn radars_go_dark_when_a_missile_homes_on_them() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        let radar = world.spawn((RadarEmitter::default(), Transform::default(), RigidBody::Dynamic)).id();
+        world.spawn((
+            SamBattery {
+                name: String::from("Test site"),
+                sam_type: SAMType::SA6,
+                acquisition_radar: radar,
+                fire_control_radar: radar,
+                launchers: Vec::new(),
+                loaders: Vec::new(),
+                envelope: EngagementEnvelope { min_range: 0.0, max_range: 1.0, min_altitude: 0.0, max_altitude: 1.0 },
+                salvo_size: 1,
+                salvo_interval: 0,
+                last_launch_time: 0,
+            },
+            EmitterTactics { arm_shutdown: true, ..default() },
+            Transform::default(),
+        ));
+        world.spawn((
+            Missile::default(),
+            ArmSeeker { side: CoalitionType::BLUE, emitter: Some(radar), last_known_position: Vec3::ZERO },
+            Transform::from_xyz(5000.0, 2000.0, 0.0),
+        ));
+
+        world.run_system_once(update_emitter_tactics).unwrap();
+        assert!(!world.get::<RadarEmitter>(radar).unwrap().emitting);
+    }
+}
@@ -1,9 +1,9 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::{util::{get_time_millis, random_vec3, random_u64, random_f32}, player::Player, definitions::*};
+use crate::{util::{get_time_millis, random_vec3, random_u64, random_f32}, player::Player, definitions::*, billboard::Billboard, terrain::TerrainData};
 
-#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ExplosionType {
     SMALL,
     MEDIUM,
@@ -11,36 +11,312 @@ pub enum ExplosionType {
     HUGE,
 }
 
+/// What a destroyed target was carrying, for secondary explosions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VolatileCargo {
+    AMMUNITION,
+    FUEL,
+}
+
+/// Targets with this component cook off after being destroyed
+#[derive(Component)]
+pub struct Volatile {
+    pub cargo: VolatileCargo,
+}
+
 #[derive(Component)]
 pub struct ExplosionEffect {
     pub start_time: u64,
     pub life_time: u64,
 }
 
+/// An explosion waiting to go off. Secondary explosions are queued with a delay.
+#[derive(Component)]
+pub struct PendingExplosion {
+    pub explosion_type: ExplosionType,
+    pub detonation_time: u64,
+}
+
+#[derive(Component)]
+pub struct ExplosionFlash {
+    pub start_time: u64,
+    pub life_time: u64,
+    pub intensity: f32,
+}
+
+#[derive(Component)]
+pub struct Fireball {
+    pub start_time: u64,
+    pub life_time: u64,
+    pub max_size: f32,
+}
+
+#[derive(Component)]
+pub struct Shockwave {
+    pub start_time: u64,
+    pub life_time: u64,
+    pub max_radius: f32,
+}
+
+/// Keeps emitting smoke puffs at its position until end_time
+#[derive(Component)]
+pub struct SmokeColumn {
+    pub end_time: u64,
+    pub emit_interval: u64,
+    pub last_emit_time: u64,
+    pub puff_size: f32,
+    pub puff_life_time: u64,
+    pub rise_speed: f32,
+}
+
+#[derive(Component)]
+pub struct SmokePuff {
+    pub start_time: u64,
+    pub life_time: u64,
+    pub start_size: f32,
+    pub end_size: f32,
+    pub velocity: Vec3,
+    pub opacity: f32,
+}
+
+/// Wind drift applied to smoke, in world units per second
+#[derive(Resource)]
+pub struct Wind {
+    pub velocity: Vec3,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Wind {
+            velocity: Vec3::new(1.2, 0.0, 0.5),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ExplosionAssets {
+    fireball_mesh: Handle<Mesh>,
+    shockwave_mesh: Handle<Mesh>,
+    smoke_mesh: Handle<Mesh>,
+    smoke_texture: Handle<Image>,
+    sounds_small: [Handle<AudioSource>; 2],
+    sound_medium: Handle<AudioSource>,
+    sound_large: Handle<AudioSource>,
+    sound_huge: Handle<AudioSource>,
+    sound_water: Handle<AudioSource>,
+}
+
+struct ExplosionConfig {
+    giblets: usize,
+    giblet_size: f32,
+    giblet_speed: f32,
+    flash_intensity: f32,
+    flash_range: f32,
+    flash_time: u64,
+    fireball_size: f32,
+    fireball_time: u64,
+    shockwave_radius: f32, // 0.0 means no shockwave
+    smoke_time: u64, // How long the smoke column keeps burning
+    smoke_size: f32,
+}
+
+fn explosion_config(explosion_type: ExplosionType) -> ExplosionConfig {
+    match explosion_type {
+        ExplosionType::SMALL => ExplosionConfig {
+            giblets: 20, giblet_size: 0.1, giblet_speed: 10.0,
+            flash_intensity: 50_000.0, flash_range: 20.0, flash_time: 150,
+            fireball_size: 0.5, fireball_time: 400,
+            shockwave_radius: 0.0,
+            smoke_time: 3000, smoke_size: 0.8,
+        },
+        ExplosionType::MEDIUM => ExplosionConfig {
+            giblets: 30, giblet_size: 0.15, giblet_speed: 15.0,
+            flash_intensity: 200_000.0, flash_range: 50.0, flash_time: 200,
+            fireball_size: 1.5, fireball_time: 700,
+            shockwave_radius: 4.0,
+            smoke_time: 20_000, smoke_size: 2.0,
+        },
+        ExplosionType::LARGE => ExplosionConfig {
+            giblets: 40, giblet_size: 0.25, giblet_speed: 20.0,
+            flash_intensity: 1_000_000.0, flash_range: 120.0, flash_time: 300,
+            fireball_size: 4.0, fireball_time: 1200,
+            shockwave_radius: 12.0,
+            smoke_time: 60_000, smoke_size: 5.0,
+        },
+        ExplosionType::HUGE => ExplosionConfig {
+            giblets: 60, giblet_size: 0.4, giblet_speed: 30.0,
+            flash_intensity: 5_000_000.0, flash_range: 300.0, flash_time: 400,
+            fireball_size: 10.0, fireball_time: 2000,
+            shockwave_radius: 40.0,
+            smoke_time: 180_000, smoke_size: 12.0,
+        },
+    }
+}
+
+/// Pick the explosion that fits a warhead's damage rating
+pub fn explosion_type_for_warhead(warhead_damage: f32) -> ExplosionType {
+    if warhead_damage < 150.0 {
+        ExplosionType::SMALL
+    } else if warhead_damage < 400.0 {
+        ExplosionType::MEDIUM
+    } else if warhead_damage < 1000.0 {
+        ExplosionType::LARGE
+    } else {
+        ExplosionType::HUGE
+    }
+}
+
+pub fn setup_explosion_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.insert_resource(ExplosionAssets {
+        fireball_mesh: meshes.add(Sphere::new(1.0)),
+        shockwave_mesh: meshes.add(Sphere::new(1.0)),
+        smoke_mesh: meshes.add(Rectangle::new(1.0, 1.0)),
+        smoke_texture: asset_server.load("mfd/SmokeTrail.png"),
+        sounds_small: [asset_server.load("sounds/xplgmn1.wav"), asset_server.load("sounds/xplgmn2.ogg")],
+        sound_medium: asset_server.load("sounds/xplg1b.wav"),
+        sound_large: asset_server.load("sounds/xplg3.wav"),
+        sound_huge: asset_server.load("sounds/xplg4.wav"),
+        sound_water: asset_server.load("sounds/xpluwsm.wav"),
+    });
+}
+
 pub fn spawn_explosion(
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
     explosion_type: ExplosionType,
     position: &Vec3,
 ) {
-    match explosion_type {
-        ExplosionType::SMALL => {
-            for _ in 0..20 {
-                spawn_explosion_giblet(commands, meshes, materials, position, random_f32(0.05, 0.1), random_u64(2000, 5000));
+    commands.spawn((
+        PendingExplosion { explosion_type, detonation_time: get_time_millis() },
+        Transform::from_translation(*position),
+    ));
+}
+
+/// Queue the cook-off of a destroyed target's ammunition or fuel
+pub fn spawn_secondary_explosions(
+    commands: &mut Commands,
+    cargo: VolatileCargo,
+    position: &Vec3,
+) {
+    let now = get_time_millis();
+    match cargo {
+        VolatileCargo::AMMUNITION => {
+            for _ in 0..random_u64(3, 7) {
+                let offset = random_vec3(1.5) * Vec3::new(1.0, 0.3, 1.0);
+                let explosion_type = if random_f32(0.0, 1.0) < 0.7 { ExplosionType::SMALL } else { ExplosionType::MEDIUM };
+                commands.spawn((
+                    PendingExplosion { explosion_type, detonation_time: now + random_u64(300, 4000) },
+                    Transform::from_translation(*position + offset),
+                ));
             }
         },
-        ExplosionType::MEDIUM => {
-
+        VolatileCargo::FUEL => {
+            commands.spawn((
+                PendingExplosion { explosion_type: ExplosionType::LARGE, detonation_time: now + random_u64(200, 800) },
+                Transform::from_translation(*position + Vec3::new(0.0, 0.5, 0.0)),
+            ));
         },
-        ExplosionType::LARGE => {
+    }
+}
 
-        },
-        ExplosionType::HUGE => {
+pub fn update_pending_explosions(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Res<ExplosionAssets>,
+    terrain: Option<Res<TerrainData>>,
+    pending_explosions: Query<(Entity, &PendingExplosion, &Transform)>,
+) {
+    let time = get_time_millis();
+    for (entity, pending_explosion, transform) in pending_explosions.iter() {
+        if time < pending_explosion.detonation_time {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        let position = transform.translation;
+        let config = explosion_config(pending_explosion.explosion_type);
 
-        },
-    };
+        for _ in 0..config.giblets {
+            spawn_explosion_giblet(&mut commands, &mut meshes, &mut materials, &position,
+                random_f32(config.giblet_size * 0.5, config.giblet_size), random_u64(2000, 5000), config.giblet_speed);
+        }
+
+        // Flash
+        commands.spawn((
+            PointLight {
+                color: Color::srgb(1.0, 0.9, 0.6),
+                intensity: config.flash_intensity,
+                range: config.flash_range,
+                shadows_enabled: false,
+                ..default()
+            },
+            ExplosionFlash { start_time: time, life_time: config.flash_time, intensity: config.flash_intensity },
+            Transform::from_translation(position),
+        ));
+
+        // Fireball
+        commands.spawn((
+            Mesh3d(explosion_assets.fireball_mesh.clone()),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::srgba(1.0, 0.9, 0.5, 1.0),
+                emissive: LinearRgba::rgb(8.0, 5.0, 1.0),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })),
+            Fireball { start_time: time, life_time: config.fireball_time, max_size: config.fireball_size },
+            Transform::from_translation(position).with_scale(Vec3::splat(0.01)),
+        ));
+
+        // Shockwave
+        if config.shockwave_radius > 0.0 {
+            commands.spawn((
+                Mesh3d(explosion_assets.shockwave_mesh.clone()),
+                MeshMaterial3d(materials.add(StandardMaterial {
+                    base_color: Color::srgba(1.0, 1.0, 1.0, 0.25),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    double_sided: true,
+                    cull_mode: None,
+                    ..default()
+                })),
+                Shockwave { start_time: time, life_time: config.fireball_time, max_radius: config.shockwave_radius },
+                Transform::from_translation(position).with_scale(Vec3::splat(0.01)),
+            ));
+        }
+
+        // Smoke rises from wherever the explosion happened
+        commands.spawn((
+            SmokeColumn {
+                end_time: time + config.smoke_time,
+                emit_interval: 250,
+                last_emit_time: 0,
+                puff_size: config.smoke_size,
+                puff_life_time: (config.smoke_time / 2).clamp(2000, 20_000),
+                rise_speed: config.smoke_size * 0.4,
+            },
+            Transform::from_translation(position),
+        ));
 
+        let over_water = match &terrain {
+            Some(t) => t.is_water(position.x, position.z) && position.y - t.get_height_world(position.x, position.z) < config.fireball_size,
+            None => false,
+        };
+        let sound = if over_water {
+            explosion_assets.sound_water.clone()
+        } else {
+            match pending_explosion.explosion_type {
+                ExplosionType::SMALL => explosion_assets.sounds_small[random_u64(0, 2) as usize].clone(),
+                ExplosionType::MEDIUM => explosion_assets.sound_medium.clone(),
+                ExplosionType::LARGE => explosion_assets.sound_large.clone(),
+                ExplosionType::HUGE => explosion_assets.sound_huge.clone(),
+            }
+        };
+        commands.spawn((AudioPlayer::new(sound), PlaybackSettings::DESPAWN));
+    }
 }
 
 fn spawn_explosion_giblet(
@@ -50,6 +326,7 @@ fn spawn_explosion_giblet(
     position: &Vec3,
     size: f32,
     life_time: u64,
+    speed: f32,
 ) {
     let explosion_handle = meshes.add(Cuboid::new(size, size, size));
     commands
@@ -71,7 +348,7 @@ fn spawn_explosion_giblet(
                 COLLISION_MASK_TERRAIN
             )))
         .insert(RigidBody::Dynamic)
-        .insert(Velocity{linvel: random_vec3(10.0), angvel: random_vec3(10.0)})
+        .insert(Velocity{linvel: random_vec3(speed), angvel: random_vec3(10.0)})
         .insert(ColliderMassProperties::Density(100.0))
         .insert(PointLight {
             color: Color::srgb(1.0, 1.0, 0.3),
@@ -87,21 +364,20 @@ fn spawn_explosion_giblet(
 pub fn handle_explosion_test(
     mut commands: Commands,
     player: Query<(Entity, &Transform), With<Player>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     input: Res<ButtonInput<KeyCode>>,
 ) {
     if input.just_pressed(KeyCode::KeyO) {
         for p in player.iter() {
             let position = p.1.translation;
-            spawn_explosion(&mut commands, &mut meshes, &mut materials, ExplosionType::SMALL, &position);
+            spawn_explosion(&mut commands, ExplosionType::SMALL, &position);
         }
     }
 }
 
 pub fn update_explosion_effects(
     mut commands: Commands,
-    mut explosion_effects: Query<(Entity, &ExplosionEffect, &mut PointLight)>,
+    mut explosion_effects: Query<(Entity, &ExplosionEffect, &mut PointLight), Without<ExplosionFlash>>,
+    mut flashes: Query<(Entity, &ExplosionFlash, &mut PointLight), Without<ExplosionEffect>>,
 ) {
     let time = get_time_millis();
     for (entity, explosion_effect, mut point_light) in explosion_effects.iter_mut() {
@@ -110,4 +386,117 @@ pub fn update_explosion_effects(
             commands.entity(entity).despawn();
         }
     }
+    for (entity, flash, mut point_light) in flashes.iter_mut() {
+        let age = (time - flash.start_time) as f32 / flash.life_time as f32;
+        point_light.intensity = flash.intensity * (1.0 - age).max(0.0).powi(2);
+        if time - flash.start_time > flash.life_time {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn update_fireballs(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut fireballs: Query<(Entity, &Fireball, &mut Transform, &MeshMaterial3d<StandardMaterial>), Without<Shockwave>>,
+    mut shockwaves: Query<(Entity, &Shockwave, &mut Transform, &MeshMaterial3d<StandardMaterial>), Without<Fireball>>,
+) {
+    let time = get_time_millis();
+    for (entity, fireball, mut transform, material) in fireballs.iter_mut() {
+        let age = (time - fireball.start_time) as f32 / fireball.life_time as f32;
+        if age > 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        // Expand quickly, then collapse while cooling from white-yellow to dark red
+        let size = if age < 0.3 { age / 0.3 } else { 1.0 - (age - 0.3) / 0.7 * 0.5 };
+        transform.scale = Vec3::splat((fireball.max_size * size).max(0.01));
+        if let Some(m) = materials.get_mut(&material.0) {
+            m.base_color = Color::srgba(1.0, 0.9 - age * 0.7, 0.5 - age * 0.5, 1.0 - age);
+            m.emissive = LinearRgba::rgb(8.0 * (1.0 - age), 5.0 * (1.0 - age) * (1.0 - age), 0.0);
+        }
+    }
+    for (entity, shockwave, mut transform, material) in shockwaves.iter_mut() {
+        let age = (time - shockwave.start_time) as f32 / shockwave.life_time as f32;
+        if age > 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.scale = Vec3::splat((shockwave.max_radius * age.sqrt()).max(0.01));
+        if let Some(m) = materials.get_mut(&material.0) {
+            m.base_color = Color::srgba(1.0, 1.0, 1.0, 0.25 * (1.0 - age));
+        }
+    }
+}
+
+pub fn update_smoke(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Res<ExplosionAssets>,
+    wind: Res<Wind>,
+    mut smoke_columns: Query<(Entity, &mut SmokeColumn, &Transform), Without<SmokePuff>>,
+    mut smoke_puffs: Query<(Entity, &SmokePuff, &mut Transform, &MeshMaterial3d<StandardMaterial>), Without<SmokeColumn>>,
+    time: Res<Time>,
+) {
+    let now = get_time_millis();
+    for (entity, mut column, transform) in smoke_columns.iter_mut() {
+        if now > column.end_time {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        if now - column.last_emit_time < column.emit_interval {
+            continue;
+        }
+        column.last_emit_time = now;
+        let horizontal_drift = random_vec3(column.rise_speed * 0.2) * Vec3::new(1.0, 0.0, 1.0);
+        spawn_smoke_puff(&mut commands, &mut materials, &explosion_assets,
+            transform.translation + random_vec3(column.puff_size * 0.2),
+            SmokePuff {
+                start_time: now,
+                life_time: column.puff_life_time,
+                start_size: column.puff_size,
+                end_size: column.puff_size * 4.0,
+                velocity: Vec3::new(0.0, column.rise_speed, 0.0) + horizontal_drift,
+                opacity: 0.6,
+            });
+    }
+
+    let dt = time.delta_secs();
+    for (entity, puff, mut transform, material) in smoke_puffs.iter_mut() {
+        let age = (now - puff.start_time) as f32 / puff.life_time as f32;
+        if age > 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += (puff.velocity + wind.velocity) * dt;
+        // Billboard sets the rotation, we only control the size
+        transform.scale = Vec3::splat(puff.start_size + (puff.end_size - puff.start_size) * age);
+        if let Some(m) = materials.get_mut(&material.0) {
+            m.base_color = Color::srgba(0.45, 0.43, 0.40, puff.opacity * (1.0 - age));
+        }
+    }
+}
+
+pub fn spawn_smoke_puff(
+    commands: &mut Commands,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    explosion_assets: &ExplosionAssets,
+    position: Vec3,
+    puff: SmokePuff,
+) {
+    commands.spawn((
+        Mesh3d(explosion_assets.smoke_mesh.clone()),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgba(0.45, 0.43, 0.40, puff.opacity),
+            base_color_texture: Some(explosion_assets.smoke_texture.clone()),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            double_sided: true,
+            cull_mode: None,
+            ..default()
+        })),
+        Transform::from_translation(position).with_scale(Vec3::splat(puff.start_size)),
+        Billboard,
+        puff,
+    ));
 }
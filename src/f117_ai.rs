@@ -3,6 +3,8 @@ use bevy::prelude::*;
 use bevy::reflect::TypePath;
 use ::serde::Deserialize;
 
+use crate::player::Player;
+use crate::radar::{RadarEmitter, RadarEmitterType, RadarEvent, RadarTransition};
 use crate::util::random_u64;
 
 /* Someone installed an experimental AI in your stealth jet. 
@@ -25,6 +27,7 @@ pub enum F117AIEvent {
     MissilesDefeated,
     Damaged,
     EngineDamage,
+    ScudLaunch,
 }
 
 #[derive(Deserialize, Asset, TypePath)]
@@ -44,6 +47,7 @@ pub struct F117AI {
     lines_missiles_defeated: Vec<String>,
     lines_damaged: Vec<String>,
     lines_engine_damage: Vec<String>,
+    lines_scud_launch: Vec<String>,
 }
 
 #[derive(Resource)]
@@ -66,6 +70,7 @@ pub struct F117AIState {
     pub cooldown_missiles_defeated: f32,
     pub cooldown_damaged: f32,
     pub cooldown_engine_damage: f32,
+    pub cooldown_scud_launch: f32,
     pub selected_line: F117AIEvent,
     pub display_line: String,
     pub active_time: f32,
@@ -89,6 +94,7 @@ impl Default for F117AIState {
             cooldown_missiles_defeated: 0.0,
             cooldown_damaged: 0.0,
             cooldown_engine_damage: 0.0,
+            cooldown_scud_launch: 0.0,
             active_time: 0.0,
             selected_line: F117AIEvent::None,
             display_line: String::from(""),
@@ -131,6 +137,7 @@ pub fn update_f117_ai(
     f117_ai_state.cooldown_missiles_defeated = (f117_ai_state.cooldown_missiles_defeated - deltatime).max(0.0);
     f117_ai_state.cooldown_damaged = (f117_ai_state.cooldown_damaged - deltatime).max(0.0);
     f117_ai_state.cooldown_engine_damage = (f117_ai_state.cooldown_engine_damage - deltatime).max(0.0);
+    f117_ai_state.cooldown_scud_launch = (f117_ai_state.cooldown_scud_launch - deltatime).max(0.0);
     
     if f117_ai_state.display_line != "" {
         f117_ai_state.active_time += deltatime;
@@ -195,6 +202,9 @@ pub fn update_f117_ai(
                     F117AIEvent::EngineDamage => {
                         lines = f117_ai_lines.lines_engine_damage.clone();
                     }
+                    F117AIEvent::ScudLaunch => {
+                        lines = f117_ai_lines.lines_scud_launch.clone();
+                    }
                 }
                 // Select a random line out of the given possibilities
                 f117_ai_state.selected_line = F117AIEvent::None;
@@ -221,3 +231,44 @@ pub fn activate_f117_ai (
     f117_ai_state.selected_line = event_type;
 }
 
+/// Comment on enemy radars taking an interest in us
+pub fn handle_radar_events_f117_ai(
+    mut f117_ai_state: ResMut<F117AIState>,
+    mut radar_events: MessageReader<RadarEvent>,
+    players: Query<(), With<Player>>,
+    emitters: Query<&RadarEmitter>,
+) {
+    for radar_event in radar_events.read() {
+        if !players.contains(radar_event.target) {
+            continue;
+        }
+        match radar_event.transition {
+            RadarTransition::DETECTED => {
+                if f117_ai_state.cooldown_detected <= 0.0 {
+                    f117_ai_state.cooldown_detected = 60.0;
+                    activate_f117_ai(&mut f117_ai_state, F117AIEvent::Detected);
+                }
+            },
+            RadarTransition::TRACK_ESTABLISHED => {
+                match emitters.get(radar_event.emitter).map(|e| e.radar_type) {
+                    Ok(RadarEmitterType::PULSE) if f117_ai_state.cooldown_sam_pulse_nearby <= 0.0 => {
+                        f117_ai_state.cooldown_sam_pulse_nearby = 60.0;
+                        activate_f117_ai(&mut f117_ai_state, F117AIEvent::SAMPulseNearby);
+                    },
+                    Ok(RadarEmitterType::DOPPLER) if f117_ai_state.cooldown_sam_doppler_nearby <= 0.0 => {
+                        f117_ai_state.cooldown_sam_doppler_nearby = 60.0;
+                        activate_f117_ai(&mut f117_ai_state, F117AIEvent::SAMDopplerNearby);
+                    },
+                    _ => {},
+                }
+            },
+            RadarTransition::LAUNCH => {
+                if f117_ai_state.cooldown_sam_missiles_incoming <= 0.0 {
+                    f117_ai_state.cooldown_sam_missiles_incoming = 20.0;
+                    activate_f117_ai(&mut f117_ai_state, F117AIEvent::SAMMissilesIncoming);
+                }
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::aircraft::{rcs_table_file, Aircraft, AircraftType, MAXFORCES_PITCH, MAXFORCES_ROLL, MAXFORCES_YAW};
+use crate::coalition::{Coalition, CoalitionType};
+use crate::definitions::*;
+use crate::gun::Gun;
+use crate::radar::{RadarBand, RadarDetectable, RadarEmitter, RadarEmitterType};
+use crate::targeting::Targetable;
+use crate::terrain::TerrainData;
+
+/* Enemy fighters. A fighter flies a combat air patrol, circling a point near the airbase, until
+   an enemy aircraft comes within reach. Then it chases it, pointing the nose at where its
+   target is going to be, and at the gunsight's lead point once inside gun range, which is where
+   the gun fires from. The flight model is the same one the player flies, the AI only works the
+   controls. */
+
+/// Patrol points, in terrain coordinates around the airbase
+const PATROL_POINTS: [Vec2; 1] = [Vec2::new(8000.0, -6000.0)];
+/// Radius of the patrol orbit
+const PATROL_RADIUS: f32 = 1500.0;
+/// Patrol height above the ground
+const PATROL_HEIGHT: f32 = 300.0;
+/// Enemy aircraft closer than this are engaged
+const ENGAGE_RANGE: f32 = 10000.0;
+/// The fighter pulls up below this height above the ground
+const MIN_HEIGHT: f32 = 80.0;
+/// Speed the fighter is put in the air with
+const PATROL_SPEED: f32 = 45.0;
+/// Control forces per radian of error
+const ROLL_GAIN: f32 = 6.0;
+const PITCH_GAIN: f32 = 8.0;
+const YAW_GAIN: f32 = 4.0;
+/// Detection range of the fighter's radar
+const RADAR_RANGE_KM: f32 = 40.0;
+/// Within this angle of the aim point the fighter stops banking and aims with pitch and yaw
+const FINE_AIM_ANGLE: f32 = 0.15;
+
+#[derive(Component)]
+pub struct Fighter {
+    pub patrol_center: Vec2, // Terrain coordinates
+    pub target: Option<Entity>,
+}
+
+/// Put the fighters on patrol
+pub fn spawn_enemy_fighters(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let fuselage_mesh = meshes.add(Cuboid::new(1.8, 0.25, 0.35));
+    let wing_mesh = meshes.add(Cuboid::new(0.7, 0.04, 2.4));
+    let fin_mesh = meshes.add(Cuboid::new(0.35, 0.45, 0.04));
+    let material = materials.add(StandardMaterial { base_color: Color::srgb(0.45, 0.5, 0.55), ..default() });
+
+    for (i, patrol_center) in PATROL_POINTS.iter().enumerate() {
+        let start = *patrol_center + Vec2::new(PATROL_RADIUS, 0.0);
+        let ground = terrain.as_deref().map(|t| t.get_height_world(start.x, start.y)).unwrap_or(0.0);
+        let position = Vec3::new(start.x, ground + PATROL_HEIGHT, start.y);
+        // Heading along the orbit, the nose is the local X axis
+        let direction = Vec3::NEG_Z;
+        let rotation = Quat::from_rotation_arc(Vec3::X, direction);
+        let name = format!("Fulcrum {}", i + 1);
+
+        let fighter = commands.spawn((Mesh3d(fuselage_mesh.clone()), MeshMaterial3d(material.clone())))
+            .insert(Transform::from_translation(position).with_rotation(rotation))
+            .insert(Name::new(name.clone()))
+            .insert(Fighter { patrol_center: *patrol_center, target: None })
+            .insert(Aircraft { name, aircraft_type: AircraftType::MIG29, throttle: 0.7, ..default() })
+            .insert(Coalition{side: CoalitionType::RED})
+            .insert(RadarDetectable {
+                base_radar_cross_section: 5.0,
+                rcs_table: asset_server.load(rcs_table_file(&AircraftType::MIG29)),
+                ..default()
+            })
+            .insert(RadarEmitter {
+                radar_type: RadarEmitterType::DOPPLER,
+                band: RadarBand::X,
+                max_detect_range_km: RADAR_RANGE_KM,
+                scan_interval: 2.0,
+                // Only the gun to aim, so the radar never goes to launch
+                launch_delay: f32::INFINITY,
+                rwr_code: String::from("29"),
+                ..default()
+            })
+            .insert(ExternalForce { ..default() })
+            .insert(Velocity { linvel: direction * PATROL_SPEED, ..default() })
+            .insert(Collider::cuboid(0.9, 0.15, 0.9))
+            .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_AIRCRAFT),
+                Group::from_bits_truncate(
+                    COLLISION_MASK_TERRAIN |
+                    COLLISION_MASK_AIRCRAFT |
+                    COLLISION_MASK_PLAYER |
+                    COLLISION_MASK_MISSILE
+                )))
+            .insert(RigidBody::Dynamic)
+            .insert(GravityScale(0.0))
+            .insert(Damping { linear_damping: 0.08, angular_damping: 6.0 })
+            .insert(ColliderMassProperties::Density(35.0))
+            .insert(Targetable)
+            .id();
+        commands.spawn((Mesh3d(wing_mesh.clone()), MeshMaterial3d(material.clone()),
+            Transform::from_xyz(-0.2, 0.0, 0.0), ChildOf(fighter)));
+        for side in [-0.12, 0.12] {
+            commands.spawn((Mesh3d(fin_mesh.clone()), MeshMaterial3d(material.clone()),
+                Transform::from_xyz(-0.75, 0.3, side), ChildOf(fighter)));
+        }
+        info!("Fighter on patrol at {:?}", patrol_center);
+    }
+}
+
+/// Control forces that bank towards the aim point and pull the nose onto it: (pitch, roll, yaw)
+fn steer_towards(rotation: Quat, position: Vec3, aim_point: Vec3, max_pitch: f32, max_roll: f32, max_yaw: f32) -> (f32, f32, f32) {
+    // Airframe coordinates: +X forward, +Y up, +Z starboard
+    let local = rotation.inverse() * (aim_point - position).normalize_or(rotation * Vec3::X);
+    let off_nose = local.x.clamp(-1.0, 1.0).acos();
+    // Put the aim point above the canopy, then pull
+    let bank_error = if off_nose < FINE_AIM_ANGLE { 0.0 } else { local.z.atan2(local.y) };
+    let pitch = (local.y.atan2(local.x) * PITCH_GAIN).clamp(-max_pitch * 0.3, max_pitch);
+    let roll = (bank_error * ROLL_GAIN).clamp(-max_roll, max_roll);
+    let yaw = (-local.z.atan2(local.x) * YAW_GAIN).clamp(-max_yaw, max_yaw);
+    (pitch, roll, yaw)
+}
+
+/// Fly the fighters: orbit the patrol point, or chase the closest enemy aircraft within reach
+pub fn update_fighters(
+    mut fighters: Query<(Entity, &mut Fighter, &mut Aircraft, &Transform, &Coalition, Option<&Gun>)>,
+    targets: Query<(Entity, &Transform, &Coalition), With<Aircraft>>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let Some(terrain) = terrain else { return };
+    for (entity, mut fighter, mut aircraft, transform, coalition, gun) in fighters.iter_mut() {
+        let position = transform.translation;
+        fighter.target = targets.iter()
+            .filter(|(e, t, c)| *e != entity && c.side != coalition.side && t.translation.distance(position) < ENGAGE_RANGE)
+            .min_by(|a, b| a.1.translation.distance(position).total_cmp(&b.1.translation.distance(position)))
+            .map(|(e, _, _)| e);
+
+        let ground = terrain.get_height_world(position.x, position.z);
+        let mut aim_point = match fighter.target.and_then(|t| targets.get(t).ok()) {
+            Some((_, target_transform, _)) => {
+                aircraft.throttle = 1.0;
+                gun.and_then(|g| g.lead_point).unwrap_or(target_transform.translation)
+            },
+            None => {
+                aircraft.throttle = 0.7;
+                // A point a little further along the orbit
+                let center = fighter.patrol_center - Vec2::new(terrain.origin_shift.x, terrain.origin_shift.z);
+                let around = (Vec2::new(position.x, position.z) - center).normalize_or(Vec2::X);
+                let ahead = center + Vec2::from_angle(-0.3).rotate(around) * PATROL_RADIUS;
+                Vec3::new(ahead.x, terrain.get_height_world(ahead.x, ahead.y) + PATROL_HEIGHT, ahead.y)
+            },
+        };
+        if position.y - ground < MIN_HEIGHT {
+            aim_point.y = aim_point.y.max(position.y + MIN_HEIGHT);
+        }
+
+        let (pitch, roll, yaw) = steer_towards(transform.rotation, position, aim_point,
+            *MAXFORCES_PITCH.get(&aircraft.aircraft_type).unwrap(),
+            *MAXFORCES_ROLL.get(&aircraft.aircraft_type).unwrap(),
+            *MAXFORCES_YAW.get(&aircraft.aircraft_type).unwrap());
+        aircraft.pitch_force = pitch;
+        aircraft.roll_force = roll;
+        aircraft.yaw_force = yaw;
+    }
+}
@@ -0,0 +1,258 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::aircraft::{Aircraft, AircraftType};
+use crate::coalition::Coalition;
+use crate::definitions::*;
+use crate::health::{DamageEvent, DestroyedEvent, Health};
+use crate::line_of_sight::check_line_of_sight;
+use crate::player::Player;
+use crate::terrain::TerrainData;
+use crate::util::{get_time_millis, random_vec3};
+
+/* Aircraft guns. Projectiles are simulated ballistically and raycast against the world
+   every frame, since they are too fast and too numerous for rigid bodies. They use the
+   missile collision group, so anything a missile can hit can also be shot. */
+
+/// Gravity acting on projectiles, same as the physics engine default
+pub const PROJECTILE_GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+/// Rounds that have flown this long without hitting anything are removed
+const PROJECTILE_LIFE_TIME: u64 = 3000;
+/// The AI only pulls the trigger if the nose is within this angle of the lead point (radians)
+const GUNSIGHT_TOLERANCE: f32 = 0.02;
+/// Fraction of the maximum projectile range the AI will open fire at
+const GUN_EFFECTIVE_RANGE_FACTOR: f32 = 0.5;
+
+#[derive(Component)]
+pub struct Gun {
+    pub rate_of_fire: f32, // Rounds per minute
+    pub muzzle_velocity: f32, // World units per second
+    pub dispersion: f32, // Radians
+    pub damage: f32, // Per round
+    pub tracer_interval: u32, // Every n-th round is a tracer
+    pub ammo: u32,
+    pub trigger: bool,
+    pub lead_point: Option<Vec3>, // Where to point the nose to hit the current target
+    pub last_shot_time: u64,
+    pub rounds_fired: u32,
+}
+
+impl Default for Gun {
+    fn default() -> Self {
+        // GSh-30-1, 30mm
+        Gun {
+            rate_of_fire: 1500.0,
+            muzzle_velocity: 280.0,
+            dispersion: 0.004,
+            damage: 12.0,
+            tracer_interval: 4,
+            ammo: 150,
+            trigger: false,
+            lead_point: None,
+            last_shot_time: 0,
+            rounds_fired: 0,
+        }
+    }
+}
+
+impl Gun {
+    /// The internal gun an aircraft type is equipped with, if any
+    pub fn for_aircraft_type(aircraft_type: &AircraftType) -> Option<Gun> {
+        match aircraft_type {
+            AircraftType::F117A => None,
+            AircraftType::MIG29 => Some(Gun { ..default() }),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Projectile {
+    pub shooter: Entity,
+    pub velocity: Vec3,
+    pub damage: f32,
+    pub expire_time: u64,
+}
+
+#[derive(Resource)]
+pub struct GunAssets {
+    tracer_mesh: Handle<Mesh>,
+    tracer_material: Handle<StandardMaterial>,
+}
+
+pub fn setup_gun_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(GunAssets {
+        tracer_mesh: meshes.add(Cuboid::new(0.03, 0.03, 1.5)),
+        tracer_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(1.0, 0.7, 0.3),
+            emissive: LinearRgba::rgb(6.0, 3.0, 0.8),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Lead-computing gunsight: returns the point to aim at so a round fired now meets the target,
+/// and the projectile's time of flight. The shooter's own velocity is added to the round's.
+/// There is no solution if the round would fly longer than max_time_of_flight (seconds).
+pub fn compute_gun_lead(
+    shooter_position: Vec3,
+    shooter_velocity: Vec3,
+    target_position: Vec3,
+    target_velocity: Vec3,
+    muzzle_velocity: f32,
+    max_time_of_flight: f32,
+) -> Option<(Vec3, f32)> {
+    let relative_velocity = target_velocity - shooter_velocity;
+    let mut time_of_flight = (target_position - shooter_position).length() / muzzle_velocity;
+    let mut intercept = target_position;
+    // A few iterations converge well within gun range
+    for _ in 0..4 {
+        intercept = target_position + relative_velocity * time_of_flight;
+        time_of_flight = (intercept - shooter_position).length() / muzzle_velocity;
+    }
+    if !time_of_flight.is_finite() || time_of_flight > max_time_of_flight {
+        return None;
+    }
+    // Aim above the intercept to make up for bullet drop
+    let aim_point = intercept - PROJECTILE_GRAVITY * 0.5 * time_of_flight * time_of_flight;
+    Some((aim_point, time_of_flight))
+}
+
+/// Give newly spawned aircraft their internal gun
+pub fn equip_aircraft_guns(
+    mut commands: Commands,
+    aircrafts: Query<(Entity, &Aircraft), (Added<Aircraft>, Without<Gun>)>,
+) {
+    for (entity, aircraft) in aircrafts.iter() {
+        if let Some(gun) = Gun::for_aircraft_type(&aircraft.aircraft_type) {
+            commands.entity(entity).insert(gun);
+        }
+    }
+}
+
+/// AI gunnery: track the closest enemy aircraft with the gunsight and fire when the solution is good
+pub fn update_ai_gunnery(
+    mut shooters: Query<(Entity, &mut Gun, &Transform, &Velocity, &Coalition), Without<Player>>,
+    targets: Query<(Entity, &Transform, &Velocity, &Coalition), With<Aircraft>>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    for (shooter_entity, mut gun, transform, velocity, coalition) in shooters.iter_mut() {
+        let max_range = gun.muzzle_velocity * PROJECTILE_LIFE_TIME as f32 / 1000.0 * GUN_EFFECTIVE_RANGE_FACTOR;
+        let target = targets.iter()
+            .filter(|(e, _, _, c)| *e != shooter_entity && c.side != coalition.side)
+            .map(|(e, t, v, _)| (e, t.translation, v.linvel, t.translation.distance(transform.translation)))
+            .filter(|(_, _, _, distance)| *distance < max_range)
+            .min_by(|a, b| a.3.total_cmp(&b.3))
+            .filter(|(e, target_position, _, _)| check_line_of_sight(None, terrain.as_deref(), *e, transform.translation, *target_position))
+            .map(|(_, target_position, target_velocity, distance)| (target_position, target_velocity, distance));
+
+        gun.lead_point = target.and_then(|(target_position, target_velocity, _)| {
+            compute_gun_lead(transform.translation, velocity.linvel, target_position, target_velocity, gun.muzzle_velocity,
+                PROJECTILE_LIFE_TIME as f32 / 1000.0)
+                .map(|(aim_point, _)| aim_point)
+        });
+        gun.trigger = match gun.lead_point {
+            Some(aim_point) => {
+                let nose = transform.rotation * Vec3::X;
+                nose.angle_between(aim_point - transform.translation) < GUNSIGHT_TOLERANCE
+            },
+            None => false,
+        };
+    }
+}
+
+pub fn update_guns(
+    mut commands: Commands,
+    gun_assets: Res<GunAssets>,
+    mut guns: Query<(Entity, &mut Gun, &Transform, &Velocity)>,
+) {
+    let current_time = get_time_millis();
+    for (entity, mut gun, transform, velocity) in guns.iter_mut() {
+        if !gun.trigger || gun.ammo == 0 {
+            continue;
+        }
+        let shot_interval = (60_000.0 / gun.rate_of_fire) as u64;
+        if current_time - gun.last_shot_time < shot_interval {
+            continue;
+        }
+        gun.last_shot_time = current_time;
+        gun.ammo -= 1;
+        gun.rounds_fired += 1;
+
+        let nose = transform.rotation * Vec3::X;
+        let direction = (nose + random_vec3(gun.dispersion)).normalize();
+        let projectile_velocity = velocity.linvel + direction * gun.muzzle_velocity;
+        let muzzle_position = transform.translation + nose * 1.0;
+        let projectile = Projectile {
+            shooter: entity,
+            velocity: projectile_velocity,
+            damage: gun.damage,
+            expire_time: current_time + PROJECTILE_LIFE_TIME,
+        };
+        let projectile_transform = Transform::from_translation(muzzle_position).looking_to(projectile_velocity, Vec3::Y);
+        if gun.rounds_fired % gun.tracer_interval == 0 {
+            commands.spawn((
+                Mesh3d(gun_assets.tracer_mesh.clone()),
+                MeshMaterial3d(gun_assets.tracer_material.clone()),
+                projectile_transform,
+                projectile,
+            ));
+        } else {
+            commands.spawn((projectile_transform, projectile));
+        }
+    }
+}
+
+/// Move projectiles along their ballistic path and check what they hit on the way
+pub fn update_projectiles(
+    mut commands: Commands,
+    rapier_context: ReadRapierContext,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+    healths: Query<(), With<Health>>,
+    mut aircrafts: Query<(&mut Aircraft, &Transform), Without<Projectile>>,
+    mut damage_events: MessageWriter<DamageEvent>,
+    mut destroyed_events: MessageWriter<DestroyedEvent>,
+    time: Res<Time>,
+) {
+    let Ok(rapier_context) = rapier_context.single() else { return };
+    let current_time = get_time_millis();
+    let dt = time.delta_secs();
+    for (entity, mut projectile, mut transform) in projectiles.iter_mut() {
+        if current_time > projectile.expire_time {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let step = projectile.velocity * dt;
+        let filter = QueryFilter::default()
+            .groups(CollisionGroups::new(
+                Group::from_bits_truncate(COLLISION_MASK_MISSILE),
+                Group::from_bits_truncate(
+                    COLLISION_MASK_TERRAIN |
+                    COLLISION_MASK_AIRCRAFT |
+                    COLLISION_MASK_GROUNDVEHICLE |
+                    COLLISION_MASK_PLAYER
+                )))
+            .exclude_rigid_body(projectile.shooter);
+        if let Some((hit_entity, _)) = rapier_context.cast_ray(transform.translation, step, 1.0, true, filter) {
+            if healths.contains(hit_entity) {
+                damage_events.write(DamageEvent { target: hit_entity, damage: projectile.damage });
+            } else if let Ok((mut aircraft, aircraft_transform)) = aircrafts.get_mut(hit_entity) {
+                let was_intact = aircraft.health > 0.0;
+                aircraft.health = (aircraft.health - projectile.damage).max(0.0);
+                info!("{} hit, health {}", aircraft.name, aircraft.health);
+                if was_intact && aircraft.health <= 0.0 {
+                    destroyed_events.write(DestroyedEvent { entity: hit_entity, position: aircraft_transform.translation });
+                }
+            }
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += step;
+        projectile.velocity += PROJECTILE_GRAVITY * dt;
+        transform.look_to(projectile.velocity, Vec3::Y);
+    }
+}
@@ -1,6 +1,109 @@
 use bevy::prelude::*;
 
+use crate::aircraft::Aircraft;
+use crate::explosion::{spawn_explosion, spawn_secondary_explosions, ExplosionType, Volatile};
+use crate::player::Player;
+use crate::scenery::Destructible;
+use crate::targeting::Targetable;
+
 #[derive(Component)]
 pub struct Health {
     pub health: f32,
 }
+
+/// Direct damage to a single entity, e.g. a contact hit
+#[derive(Message)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub damage: f32,
+}
+
+/// Area damage from a detonating warhead. Also used to leave marks on the ground.
+#[derive(Message)]
+pub struct BlastEvent {
+    pub position: Vec3,
+    pub radius: f32,
+    pub damage: f32,
+}
+
+/// Sent once when an entity's health drops to zero
+#[derive(Message)]
+pub struct DestroyedEvent {
+    pub entity: Entity,
+    pub position: Vec3,
+}
+
+pub fn apply_blast_damage(
+    mut blast_events: MessageReader<BlastEvent>,
+    mut damage_events: MessageWriter<DamageEvent>,
+    mut destroyed_events: MessageWriter<DestroyedEvent>,
+    damageables: Query<(Entity, &Transform), With<Health>>,
+    mut aircrafts: Query<(Entity, &mut Aircraft, &Transform), Without<Health>>,
+    fragile_targets: Query<(Entity, &Transform), (With<Targetable>, Without<Health>, Without<Aircraft>)>,
+) {
+    for blast in blast_events.read() {
+        for (entity, transform) in damageables.iter() {
+            let distance = (transform.translation - blast.position).length();
+            if distance < blast.radius {
+                // Damage falls off linearly towards the edge of the blast
+                let damage = blast.damage * (1.0 - distance / blast.radius);
+                damage_events.write(DamageEvent { target: entity, damage });
+            }
+        }
+        // Aircraft keep their own damage state, shared with gun hits
+        for (entity, mut aircraft, transform) in aircrafts.iter_mut() {
+            let distance = (transform.translation - blast.position).length();
+            if distance < blast.radius && aircraft.health > 0.0 {
+                let damage = blast.damage * (1.0 - distance / blast.radius);
+                aircraft.health = (aircraft.health - damage).max(0.0);
+                info!("{} caught in a blast, health {}", aircraft.name, aircraft.health);
+                if aircraft.health <= 0.0 {
+                    destroyed_events.write(DestroyedEvent { entity, position: transform.translation });
+                }
+            }
+        }
+        // Targets without a health model (e.g. other missiles) don't survive a blast
+        for (entity, transform) in fragile_targets.iter() {
+            if (transform.translation - blast.position).length() < blast.radius {
+                destroyed_events.write(DestroyedEvent { entity, position: transform.translation });
+            }
+        }
+    }
+}
+
+pub fn apply_damage(
+    mut damage_events: MessageReader<DamageEvent>,
+    mut destroyed_events: MessageWriter<DestroyedEvent>,
+    mut healths: Query<(&mut Health, &Transform)>,
+) {
+    for damage_event in damage_events.read() {
+        if let Ok((mut health, transform)) = healths.get_mut(damage_event.target) {
+            if health.health <= 0.0 {
+                continue; // Already destroyed this frame
+            }
+            health.health -= damage_event.damage;
+            if health.health <= 0.0 {
+                destroyed_events.write(DestroyedEvent { entity: damage_event.target, position: transform.translation });
+            }
+        }
+    }
+}
+
+/// Default handling for destroyed entities: blow them up and remove them.
+/// Scenery is handled separately and turns into rubble instead, the player is shot down.
+pub fn handle_destroyed_entities(
+    mut commands: Commands,
+    mut destroyed_events: MessageReader<DestroyedEvent>,
+    entities: Query<Option<&Volatile>, (Without<Destructible>, Without<Player>)>,
+) {
+    for destroyed in destroyed_events.read() {
+        if let Ok(volatile) = entities.get(destroyed.entity) {
+            info!("Target destroyed");
+            spawn_explosion(&mut commands, ExplosionType::MEDIUM, &destroyed.position);
+            if let Some(volatile) = volatile {
+                spawn_secondary_explosions(&mut commands, volatile.cargo, &destroyed.position);
+            }
+            commands.entity(destroyed.entity).try_despawn();
+        }
+    }
+}
@@ -4,7 +4,12 @@ use crate::definitions::COLOR_GREEN;
 use crate::CameraSettings;
 use crate::aircraft::*;
 use crate::definitions::RENDERLAYER_COCKPIT;
+use crate::emcon::{Emcon, EmitterType};
 use crate::player::*;
+use crate::terrain::TerrainData;
+
+/// Without the radar altimeter, altitude is shown in steps of this many feet
+const BAROMETRIC_ALTITUDE_STEP: f32 = 100.0;
 
 
 #[derive(Component)]
@@ -13,6 +18,9 @@ pub struct LabelCurrentSpeed;
 #[derive(Component)]
 pub struct LabelCurrentAltitude;
 
+#[derive(Component)]
+pub struct LabelEmcon;
+
 pub fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
     let font = asset_server.load("fonts/Brickshapers-eXPx.ttf");
     commands.spawn((
@@ -43,6 +51,20 @@ pub fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
     .insert(RenderLayers::layer(RENDERLAYER_COCKPIT))
     .insert(LabelCurrentAltitude);
 
+    commands.spawn((
+        Text2d::new(""),
+        TextFont {
+            font: font.clone(),
+            font_size: 20.0,
+            ..default()
+        },
+        TextColor(COLOR_GREEN),
+        TextLayout::new_with_justify(Justify::Left),
+        Transform::from_translation(Vec3::new(-470.0, -230.0, 0.0)),
+    ))
+    .insert(RenderLayers::layer(RENDERLAYER_COCKPIT))
+    .insert(LabelEmcon);
+
 }
 
 fn draw_vertical_ladder(gizmos: &mut Gizmos, value : f32, xpos : f32, hud_size_y : i32, tick_direction : f32) {
@@ -76,24 +98,39 @@ fn draw_vertical_ladder(gizmos: &mut Gizmos, value : f32, xpos : f32, hud_size_y
     }
 }
 
-pub fn update_hud(mut aircrafts: Query<&Aircraft, With<Player>>,
+pub fn update_hud(mut aircrafts: Query<(&Aircraft, &Transform, Option<&Emcon>), With<Player>>,
     mut speedlabels: Query<&mut Text2d, (With<LabelCurrentSpeed>, Without<LabelCurrentAltitude>)>,
     mut altitudelabels: Query<&mut Text2d, (With<LabelCurrentAltitude>, Without<LabelCurrentSpeed>)>,
+    mut emconlabels: Query<&mut Text2d, (With<LabelEmcon>, Without<LabelCurrentSpeed>, Without<LabelCurrentAltitude>)>,
     camera_settings: ResMut<CameraSettings>,
+    terrain: Option<Res<TerrainData>>,
     mut gizmos: Gizmos,
     ) {
     let mut speedlabel = speedlabels.single_mut().unwrap();
     let mut altitudelabel = altitudelabels.single_mut().unwrap();
+    let mut emconlabel = emconlabels.single_mut().unwrap();
     if camera_settings.render_hud == true {
-        for aircraft in aircrafts.iter_mut() {
+        for (aircraft, transform, emcon) in aircrafts.iter_mut() {
             speedlabel.0 = format!("{:.0}", aircraft.speed_knots);
             draw_vertical_ladder(&mut gizmos, aircraft.speed_knots * 2.0, -500.0, 400, -1.0);
 
-            altitudelabel.0 = format!("{:.0}", aircraft.altitude);
+            // The radar altimeter gives exact height above ground, otherwise there's only the coarse barometric altitude
+            let radar_altitude = match (emcon, terrain.as_deref()) {
+                (Some(emcon), Some(terrain)) if emcon.is_active(EmitterType::RADAR_ALTIMETER) =>
+                    Some((transform.translation.y - terrain.get_height_world(transform.translation.x, transform.translation.z)) * 10.0),
+                _ => None,
+            };
+            altitudelabel.0 = match radar_altitude {
+                Some(radar_altitude) => format!("{:.0}R", radar_altitude),
+                None => format!("{:.0}", (aircraft.altitude / BAROMETRIC_ALTITUDE_STEP).round() * BAROMETRIC_ALTITUDE_STEP),
+            };
             draw_vertical_ladder(&mut gizmos, aircraft.altitude, 500.0, 400, 1.0);
+
+            emconlabel.0 = emcon.map(|e| e.label().to_string()).unwrap_or_default();
         }
     } else {
         speedlabel.0 = "".to_string();
         altitudelabel.0 = "".to_string();
+        emconlabel.0 = "".to_string();
     }
 }
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::coalition::{Coalition, CoalitionType};
+use crate::definitions::*;
+use crate::emcon::ElintReceiver;
+use crate::health::Health;
+use crate::line_of_sight::LineOfSightCache;
+use crate::radar::{RadarEmitter, RadarEmitterType, TrackState};
+use crate::scenery::{Destructible, StructureType, structure_health};
+use crate::targeting::Targetable;
+use crate::util::get_time_millis;
+use crate::vehicle::Vehicle;
+
+/* Integrated air defence system. Early warning radars report their tracks to the command post
+   of their sector, and command posts cue the SAM sites in their sector, which keep their own
+   radars dark until they are handed a track. Command posts with a working comm tower nearby
+   share their air picture with the rest of the coalition. Without a command post, SAM sites
+   fall back to searching on their own, which makes them much easier to find. */
+
+/// A command post is on the network if a comm tower within this distance is still standing
+const COMM_TOWER_RANGE: f32 = 12000.0;
+/// Reported tracks are dropped if no radar has updated them for this long (ms)
+const IADS_TRACK_TIMEOUT: u64 = 15000;
+/// SAM sites go dark again after this long without a cue or a track of their own (ms)
+const SAM_DARK_DELAY: u64 = 20000;
+/// SAM sites are cued on tracks slightly beyond their own radar range
+const CUE_RANGE_FACTOR: f32 = 1.2;
+
+#[derive(Component)]
+pub struct EarlyWarningRadar;
+
+#[derive(Component)]
+pub struct CommandPost {
+    pub sector_radius: f32,
+    pub networked: bool, // Linked to the rest of the coalition through a comm tower
+    pub tracks: HashMap<Entity, IadsTrack>,
+}
+
+impl Default for CommandPost {
+    fn default() -> Self {
+        CommandPost {
+            sector_radius: 15000.0,
+            networked: false,
+            tracks: HashMap::new(),
+        }
+    }
+}
+
+/// A SAM site taking orders from the IADS
+#[derive(Component)]
+pub struct IadsSamSite {
+    pub autonomous: bool, // No command post left to cue the site
+    pub last_cue_time: u64,
+}
+
+impl Default for IadsSamSite {
+    fn default() -> Self {
+        IadsSamSite {
+            autonomous: false,
+            last_cue_time: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IadsTrack {
+    pub position: Vec3,
+    pub update_time: u64,
+}
+
+/// Tracks shared between all networked command posts of a coalition
+#[derive(Resource, Default)]
+pub struct AirPicture {
+    pub tracks: HashMap<CoalitionType, HashMap<Entity, IadsTrack>>,
+}
+
+pub fn spawn_early_warning_radar(
+    commands: &mut Commands,
+    mesh: &Handle<Mesh>,
+    material: &Handle<StandardMaterial>,
+    position: Vec3,
+    side: CoalitionType,
+) {
+    commands.spawn((Mesh3d(mesh.clone()), MeshMaterial3d(material.clone())))
+    .insert(Transform::from_translation(position + Vec3::Y * 1.0).with_scale(Vec3::new(0.6, 2.0, 0.6)))
+    .insert(Vehicle{..default()})
+    .insert(Coalition{side: side})
+    .insert(EarlyWarningRadar)
+    .insert(Collider::cuboid(0.5, 0.5, 0.5))
+    .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_GROUNDVEHICLE),
+        Group::from_bits_truncate(
+            COLLISION_MASK_AIRCRAFT |
+            COLLISION_MASK_MISSILE |
+            COLLISION_MASK_PLAYER
+        )))
+    .insert(RigidBody::Fixed)
+    .insert(Targetable)
+    .insert(Health{health: 80.0})
+    .insert(RadarEmitter{
+        radar_type: RadarEmitterType::PULSE,
+        radar_gain: 20.0,
+        max_detect_range_km: 60.0,
+        // A slowly rotating antenna, no faster updates while tracking
+        scan_interval: 10.0,
+        track_interval: 10.0,
+        coast_time: 25.0,
+        // Early warning radars can't guide missiles
+        lock_quality: 2.0,
+        rwr_code: String::from("EW"),
+        ..default()
+    })
+    .insert(ElintReceiver{..default()})
+    .insert(LineOfSightCache::default());
+}
+
+pub fn spawn_command_post(
+    commands: &mut Commands,
+    mesh: &Handle<Mesh>,
+    material: &Handle<StandardMaterial>,
+    position: Vec3,
+    side: CoalitionType,
+) {
+    commands.spawn((Mesh3d(mesh.clone()), MeshMaterial3d(material.clone())))
+    .insert(Transform::from_translation(position + Vec3::Y * 0.4).with_scale(Vec3::new(3.0, 0.8, 2.0)))
+    .insert(Vehicle{..default()})
+    .insert(Coalition{side: side})
+    .insert(CommandPost{..default()})
+    .insert(Destructible{structure_type: StructureType::COMMAND_POST, light_radius: 5.0})
+    .insert(Health{health: structure_health(StructureType::COMMAND_POST)})
+    .insert(Targetable);
+}
+
+/// Pass tracks from early warning radars through the command posts to the SAM sites
+pub fn update_iads(
+    mut command_posts: Query<(Entity, &mut CommandPost, &Transform, &Coalition), With<Destructible>>,
+    structures: Query<(&Destructible, &Transform), Without<CommandPost>>,
+    early_warning_radars: Query<(&RadarEmitter, &Transform, &Coalition), With<EarlyWarningRadar>>,
+    elint_receivers: Query<(&ElintReceiver, &Transform, &Coalition)>,
+    mut sam_sites: Query<(&mut IadsSamSite, &mut RadarEmitter, &Transform, &Coalition), Without<EarlyWarningRadar>>,
+    mut air_picture: ResMut<AirPicture>,
+) {
+    let milliseconds = get_time_millis();
+
+    // Command posts that lost their comm tower are on their own
+    for (_, mut command_post, transform, _) in command_posts.iter_mut() {
+        command_post.networked = structures.iter().any(|(destructible, tower_transform)| {
+            destructible.structure_type == StructureType::COMM_TOWER
+                && tower_transform.translation.distance(transform.translation) < COMM_TOWER_RANGE
+        });
+        command_post.tracks.retain(|_, track| milliseconds - track.update_time < IADS_TRACK_TIMEOUT);
+    }
+    let sectors: Vec<(Entity, Vec3, f32, CoalitionType)> = command_posts.iter()
+        .map(|(entity, command_post, transform, coalition)| (entity, transform.translation, command_post.sector_radius, coalition.side))
+        .collect();
+    let sector_of = |position: Vec3, side: CoalitionType| -> Option<Entity> {
+        sectors.iter()
+            .filter(|(_, post_position, radius, post_side)| *post_side == side && post_position.distance(position) < *radius)
+            .min_by(|a, b| a.1.distance(position).total_cmp(&b.1.distance(position)))
+            .map(|(entity, _, _, _)| *entity)
+    };
+
+    // Early warning radars report to the command post of their sector
+    for (radar_emitter, transform, coalition) in early_warning_radars.iter() {
+        let Some(post_entity) = sector_of(transform.translation, coalition.side) else { continue };
+        let Ok((_, mut command_post, _, _)) = command_posts.get_mut(post_entity) else { continue };
+        for track in radar_emitter.tracks.iter().filter(|t| t.state != TrackState::DETECTED) {
+            command_post.tracks.insert(track.target, IadsTrack { position: track.last_position, update_time: track.last_detection_time });
+        }
+    }
+
+    // So do ELINT receivers, with the rough positions of the emitters they picked up
+    for (receiver, transform, coalition) in elint_receivers.iter() {
+        let Some(post_entity) = sector_of(transform.translation, coalition.side) else { continue };
+        let Ok((_, mut command_post, _, _)) = command_posts.get_mut(post_entity) else { continue };
+        for (target, intercept) in receiver.intercepts.iter() {
+            if command_post.tracks.get(target).is_none_or(|t| t.update_time < intercept.time) {
+                command_post.tracks.insert(*target, IadsTrack { position: intercept.position, update_time: intercept.time });
+            }
+        }
+    }
+
+    // Networked command posts share their tracks with the whole coalition
+    for tracks in air_picture.tracks.values_mut() {
+        tracks.retain(|_, track| milliseconds - track.update_time < IADS_TRACK_TIMEOUT);
+    }
+    for (_, command_post, _, coalition) in command_posts.iter().filter(|(_, c, _, _)| c.networked) {
+        let coalition_tracks = air_picture.tracks.entry(coalition.side).or_default();
+        for (target, track) in command_post.tracks.iter() {
+            if coalition_tracks.get(target).is_none_or(|t| t.update_time < track.update_time) {
+                coalition_tracks.insert(*target, *track);
+            }
+        }
+    }
+
+    // Cue SAM sites on the closest track they can engage
+    for (mut sam_site, mut radar_emitter, transform, coalition) in sam_sites.iter_mut() {
+        let Some(post_entity) = sector_of(transform.translation, coalition.side) else {
+            if !sam_site.autonomous {
+                info!("SAM site lost its command post, searching autonomously");
+            }
+            sam_site.autonomous = true;
+            radar_emitter.emitting = true;
+            continue;
+        };
+        sam_site.autonomous = false;
+        let Ok((_, command_post, _, _)) = command_posts.get(post_entity) else { continue };
+        let shared_tracks = if command_post.networked { air_picture.tracks.get(&coalition.side) } else { None };
+        let cue_range = radar_emitter.max_detect_range_km * 1000.0 * CUE_RANGE_FACTOR;
+        let cue = command_post.tracks.iter()
+            .chain(shared_tracks.into_iter().flat_map(|tracks| tracks.iter()))
+            .map(|(target, track)| (*target, track.position, track.position.distance(transform.translation)))
+            .filter(|(_, _, distance)| *distance < cue_range)
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+
+        match cue {
+            Some((target, position, _)) => {
+                if !radar_emitter.emitting {
+                    info!("SAM site cued, radar on");
+                    radar_emitter.emitting = true;
+                }
+                radar_emitter.cue(target, position, milliseconds);
+                sam_site.last_cue_time = milliseconds;
+            },
+            None => {
+                if radar_emitter.tracks.is_empty() && milliseconds - sam_site.last_cue_time > SAM_DARK_DELAY {
+                    radar_emitter.emitting = false;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+
+use crate::aircraft::{Aircraft, AircraftType};
+use crate::missile::Missile;
+use crate::util::{get_time_millis, random_f32};
+
+/* Infrared signatures and heat seekers. Every heat source has an IrSignature made up of the
+   engine, which is mostly seen from behind, and the airframe heated by airspeed, which is seen
+   from all aspects. IR seekers look at everything hot in their field of view and may be
+   seduced by a brighter source, such as a flare. */
+
+/// Speed in knots at which skin heating reaches its nominal intensity
+const SKIN_HEATING_SPEED: f32 = 660.0;
+/// Fraction of the engine heat still visible from the front quarter
+const ENGINE_FRONT_ASPECT: f32 = 0.15;
+
+#[derive(Component)]
+pub struct IrSignature {
+    pub max_engine_intensity: f32, // Engine intensity at full throttle
+    pub skin_heating: f32, // Skin intensity at SKIN_HEATING_SPEED
+    pub exhaust_shielding: f32, // 0..1, fraction of the engine heat hidden by the airframe
+    pub engine_intensity: f32,
+    pub skin_intensity: f32,
+}
+
+impl Default for IrSignature {
+    fn default() -> Self {
+        IrSignature {
+            max_engine_intensity: 10.0,
+            skin_heating: 1.0,
+            exhaust_shielding: 0.0,
+            engine_intensity: 0.0,
+            skin_intensity: 0.0,
+        }
+    }
+}
+
+impl IrSignature {
+    pub fn for_aircraft_type(aircraft_type: &AircraftType) -> IrSignature {
+        match aircraft_type {
+            // Slot exhausts with tiles on the platypus lip, mixed with bypass air
+            AircraftType::F117A => IrSignature { max_engine_intensity: 6.0, exhaust_shielding: 0.75, ..default() },
+            // Two afterburning turbofans
+            AircraftType::MIG29 => IrSignature { max_engine_intensity: 14.0, exhaust_shielding: 0.0, ..default() },
+        }
+    }
+
+    /// Intensity of this source as seen from the viewer's position, before distance falloff.
+    /// The engine is assumed to exhaust towards the local -X axis.
+    pub fn apparent_intensity(&self, transform: &Transform, viewer_position: Vec3) -> f32 {
+        let to_viewer = (viewer_position - transform.translation).normalize_or_zero();
+        let tail = transform.rotation * Vec3::NEG_X;
+        let rear_aspect = tail.dot(to_viewer).max(0.0);
+        let aspect_factor = ENGINE_FRONT_ASPECT + (1.0 - ENGINE_FRONT_ASPECT) * rear_aspect;
+        self.engine_intensity * (1.0 - self.exhaust_shielding) * aspect_factor + self.skin_intensity
+    }
+}
+
+/// A heat seeking missile head
+#[derive(Component)]
+pub struct IrSeeker {
+    pub field_of_view: f32, // Half angle in radians
+    pub flare_rejection: f32, // 0..1, chance of ignoring a brighter source on each look
+    pub look_interval: u64,
+    pub last_look_time: u64,
+}
+
+impl Default for IrSeeker {
+    fn default() -> Self {
+        // Early rear-aspect seeker, easily decoyed
+        IrSeeker {
+            field_of_view: 0.2,
+            flare_rejection: 0.3,
+            look_interval: 250,
+            last_look_time: 0,
+        }
+    }
+}
+
+/// Chance of a seeker switching from its target to a brighter source on one look. Nothing dimmer
+/// than the target can pull it off.
+pub fn decoy_chance(target_irradiance: f32, source_irradiance: f32, flare_rejection: f32) -> f32 {
+    if source_irradiance <= target_irradiance {
+        return 0.0;
+    }
+    (1.0 - target_irradiance / source_irradiance) * (1.0 - flare_rejection)
+}
+
+/// Give newly spawned aircraft their heat signature
+pub fn add_ir_signatures(
+    mut commands: Commands,
+    aircrafts: Query<(Entity, &Aircraft), (Added<Aircraft>, Without<IrSignature>)>,
+) {
+    for (entity, aircraft) in aircrafts.iter() {
+        commands.entity(entity).insert(IrSignature::for_aircraft_type(&aircraft.aircraft_type));
+    }
+}
+
+pub fn update_ir_signatures(
+    mut aircrafts: Query<(&mut IrSignature, &Aircraft)>,
+) {
+    for (mut signature, aircraft) in aircrafts.iter_mut() {
+        // A spooled down engine is still hot
+        signature.engine_intensity = signature.max_engine_intensity * (0.2 + 0.8 * aircraft.throttle);
+        signature.skin_intensity = signature.skin_heating * (aircraft.speed_knots / SKIN_HEATING_SPEED).powi(2);
+    }
+}
+
+/// Let IR seekers look for the brightest source in their field of view. A brighter source than
+/// the current target may steal the seeker, the more likely the brighter it is.
+pub fn update_ir_seekers(
+    mut missiles: Query<(&mut Missile, &mut IrSeeker, &Transform)>,
+    sources: Query<(Entity, &IrSignature, &Transform)>,
+) {
+    let current_time = get_time_millis();
+    for (mut missile, mut seeker, missile_transform) in missiles.iter_mut() {
+        if current_time - seeker.last_look_time < seeker.look_interval {
+            continue;
+        }
+        seeker.last_look_time = current_time;
+
+        let seeker_position = missile_transform.translation;
+        let boresight = missile_transform.forward();
+        let mut brightest: Option<(Entity, f32)> = None;
+        let mut target_irradiance = 0.0;
+        for (source_entity, signature, source_transform) in sources.iter() {
+            let line_of_sight = source_transform.translation - seeker_position;
+            if line_of_sight.angle_between(*boresight) > seeker.field_of_view {
+                continue;
+            }
+            let irradiance = signature.apparent_intensity(source_transform, seeker_position) / line_of_sight.length_squared().max(1.0);
+            if source_entity == missile.target {
+                target_irradiance = irradiance;
+            }
+            if brightest.is_none_or(|(_, b)| irradiance > b) {
+                brightest = Some((source_entity, irradiance));
+            }
+        }
+
+        let Some((brightest_entity, brightest_irradiance)) = brightest else { continue };
+        if brightest_entity == missile.target {
+            continue;
+        }
+        if random_f32(0.0, 1.0) < decoy_chance(target_irradiance, brightest_irradiance, seeker.flare_rejection) {
+            info!("IR seeker switched to a brighter source");
+            missile.target = brightest_entity;
+            // The proximity fuze starts over with the new target
+            missile.last_target_distance = f32::MAX;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use bevy_rapier3d::prelude::ExternalForce;
+
+    use super::*;
+    use crate::health::BlastEvent;
+    use crate::missile::{update_missiles, SeekerState};
+    use crate::sam::SAMType;
+    use crate::threats::threat_data;
+
+    /// A heat seeker 2 km behind the target, looking at it
+    fn chase_geometry() -> (Transform, Transform) {
+        let target = Transform::from_xyz(0.0, 300.0, 0.0);
+        let seeker = Transform::from_xyz(-2000.0, 300.0, 0.0).looking_at(target.translation, Vec3::Y);
+        (seeker, target)
+    }
+
+    fn hot_engine() -> IrSignature {
+        IrSignature { engine_intensity: 10.0, skin_intensity: 0.5, ..default() }
+    }
+
+    fn burning_flare() -> IrSignature {
+        IrSignature { max_engine_intensity: 0.0, skin_heating: 0.0, skin_intensity: 40.0, ..default() }
+    }
+
+    #[test]
+    fn brighter_sources_decoy_more_often() {
+        assert_eq!(decoy_chance(1.0, 0.5, 0.0), 0.0);
+        assert_eq!(decoy_chance(1.0, 1.0, 0.0), 0.0);
+        assert_eq!(decoy_chance(1.0, 2.0, 0.0), 0.5);
+        assert_eq!(decoy_chance(1.0, 4.0, 0.0), 0.75);
+        // A seeker that rejects every flare can't be decoyed
+        assert_eq!(decoy_chance(1.0, 4.0, 1.0), 0.0);
+        assert_eq!(decoy_chance(1.0, 4.0, 0.5), 0.375);
+    }
+
+    #[test]
+    fn engine_is_hotter_from_behind() {
+        let (seeker, target) = chase_geometry();
+        let signature = hot_engine();
+        let from_behind = signature.apparent_intensity(&target, seeker.translation);
+        let from_front = signature.apparent_intensity(&target, Vec3::new(2000.0, 300.0, 0.0));
+        assert!(from_behind > from_front * 3.0, "rear {} front {}", from_behind, from_front);
+    }
+
+    #[test]
+    fn flare_brighter_than_target_pulls_seeker() {
+        let mut world = World::new();
+        let (seeker_transform, target_transform) = chase_geometry();
+        let target = world.spawn((hot_engine(), target_transform)).id();
+        // Just behind the target, within the seeker's field of view
+        let flare = world.spawn((burning_flare(), Transform::from_xyz(-30.0, 295.0, 0.0))).id();
+        let missile = world.spawn((
+            Missile { target, ..default() },
+            IrSeeker { flare_rejection: 0.0, ..default() },
+            seeker_transform,
+        )).id();
+
+        // Seen from behind the flare outshines the engine, the seeker switches on one of the first looks
+        for _ in 0..20 {
+            world.entity_mut(missile).get_mut::<IrSeeker>().unwrap().last_look_time = 0;
+            world.run_system_once(update_ir_seekers).unwrap();
+            if world.get::<Missile>(missile).unwrap().target == flare {
+                return;
+            }
+        }
+        panic!("The seeker stayed on the target");
+    }
+
+    #[test]
+    fn flare_rejecting_seeker_stays_on_target() {
+        let mut world = World::new();
+        let (seeker_transform, target_transform) = chase_geometry();
+        let target = world.spawn((hot_engine(), target_transform)).id();
+        world.spawn((burning_flare(), Transform::from_xyz(-30.0, 295.0, 0.0)));
+        let missile = world.spawn((
+            Missile { target, ..default() },
+            IrSeeker { flare_rejection: 1.0, ..default() },
+            seeker_transform,
+        )).id();
+
+        for _ in 0..20 {
+            world.entity_mut(missile).get_mut::<IrSeeker>().unwrap().last_look_time = 0;
+            world.run_system_once(update_ir_seekers).unwrap();
+        }
+        assert_eq!(world.get::<Missile>(missile).unwrap().target, target);
+    }
+
+    #[test]
+    fn sam_heat_seeker_tracks_untargetable_aircraft() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Messages<BlastEvent>>();
+        let (seeker_transform, target_transform) = chase_geometry();
+        // Like the player, the target is not Targetable
+        let target = world.spawn((hot_engine(), target_transform)).id();
+        let missile_data = threat_data(&SAMType::SA13).missile.as_ref().unwrap();
+        let missile = world.spawn((
+            Missile { target, ..default() },
+            IrSeeker { flare_rejection: missile_data.flare_rejection.unwrap(), ..default() },
+            ExternalForce::default(),
+            seeker_transform,
+        )).id();
+
+        world.run_system_once(update_missiles).unwrap();
+        let missile = world.get::<Missile>(missile).unwrap();
+        assert_ne!(missile.seeker_state, SeekerState::TARGET_LOST);
+        assert_eq!(missile.target_transform.translation, target_transform.translation);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::terrain::TerrainData;
+use crate::util::get_time_millis;
+
+/* Cached terrain line of sight. Sensors check the same targets over and over, and the
+   answer only changes once either end has moved a noticeable distance. */
+
+/// Cached results are reused for this long (ms)
+const LOS_CACHE_TIME: u64 = 1000;
+/// ...as long as neither end has moved further than this
+const LOS_CACHE_DISTANCE: f32 = 20.0;
+
+struct CachedLineOfSight {
+    visible: bool,
+    from: Vec3,
+    to: Vec3,
+    time: u64,
+}
+
+/// Per-sensor cache of line of sight results, keyed by target
+#[derive(Component, Default)]
+pub struct LineOfSightCache {
+    entries: HashMap<Entity, CachedLineOfSight>,
+}
+
+impl LineOfSightCache {
+    pub fn check(&mut self, terrain: &TerrainData, target: Entity, from: Vec3, to: Vec3) -> bool {
+        let time = get_time_millis();
+        if let Some(entry) = self.entries.get(&target) {
+            if time - entry.time < LOS_CACHE_TIME
+                && entry.from.distance(from) < LOS_CACHE_DISTANCE
+                && entry.to.distance(to) < LOS_CACHE_DISTANCE {
+                return entry.visible;
+            }
+        }
+        let visible = terrain.line_of_sight(from, to);
+        self.entries.insert(target, CachedLineOfSight { visible, from, to, time });
+        // Forget targets that haven't been asked about in a while
+        if self.entries.len() > 64 {
+            self.entries.retain(|_, entry| time - entry.time < LOS_CACHE_TIME);
+        }
+        visible
+    }
+}
+
+/// Check line of sight through the cache if the sensor has one, directly otherwise.
+/// After an origin shift both ends have moved, so stale entries are recomputed automatically.
+pub fn check_line_of_sight(
+    cache: Option<&mut LineOfSightCache>,
+    terrain: Option<&TerrainData>,
+    target: Entity,
+    from: Vec3,
+    to: Vec3,
+) -> bool {
+    match (cache, terrain) {
+        (Some(cache), Some(terrain)) => cache.check(terrain, target, from, to),
+        (None, Some(terrain)) => terrain.line_of_sight(from, to),
+        (_, None) => true,
+    }
+}
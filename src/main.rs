@@ -15,7 +15,7 @@ use bevy_scene_hook::HookPlugin;
 use bevy_third_person_camera::*;
 use bevy_common_assets::toml::TomlAssetPlugin;
 use definitions::{RENDERLAYER_WORLD, RENDERLAYER_POINTLIGHTS, RENDERLAYER_COCKPIT, RENDERLAYER_AIRCRAFT};
-use radar::{update_rcs, update_radar};
+use radar::{update_rcs, update_radar, RadarEvent, RcsTable};
 
 mod bevy_scene_hook;
 mod billboard;
@@ -40,6 +40,23 @@ mod coalition;
 mod radar;
 mod rwr;
 mod f117_ai;
+mod telemetry;
+mod gun;
+mod line_of_sight;
+mod countermeasures;
+mod infrared;
+mod iads;
+mod emcon;
+mod threats;
+mod aaa;
+mod emitter_tactics;
+mod convoy;
+mod road_network;
+mod ship;
+mod mission;
+mod scud;
+mod arm;
+mod fighter;
 
 use crate::aircraft::*;
 use crate::billboard::BillboardPlugin;
@@ -55,6 +72,24 @@ use crate::explosion::*;
 use crate::rwr::*;
 use crate::f117_ai::*;
 use crate::dialog_ui::*;
+use crate::telemetry::*;
+use crate::health::*;
+use crate::scenery::*;
+use crate::gun::*;
+use crate::countermeasures::*;
+use crate::infrared::*;
+use crate::iads::*;
+use crate::emcon::*;
+use crate::sam::*;
+use crate::aaa::*;
+use crate::emitter_tactics::*;
+use crate::convoy::*;
+use crate::ship::*;
+use crate::mission::*;
+use crate::scud::*;
+use crate::arm::*;
+use crate::fighter::*;
+use crate::threats::Difficulty;
 
 fn main() {
     App::new()
@@ -69,7 +104,17 @@ fn main() {
             BillboardPlugin,
             HookPlugin,
             TomlAssetPlugin::<F117AI>::new(&["toml"]),
+            TomlAssetPlugin::<RcsTable>::new(&["rcs.toml"]),
         ))
+        .add_message::<DamageEvent>()
+        .add_message::<BlastEvent>()
+        .add_message::<DestroyedEvent>()
+        .add_message::<RadarEvent>()
+        .init_resource::<Wind>()
+        .init_resource::<AirPicture>()
+        .insert_resource(Difficulty::from_args())
+        .insert_resource(MissionType::from_args())
+        .insert_resource(TelemetrySettings::from_args())
         .add_systems(
             PreStartup,
             (
@@ -88,12 +133,19 @@ fn main() {
                 setup_flir,
                 setup_sounds,
                 setup_rwr,
+                setup_destruction_assets,
+                setup_explosion_assets,
+                setup_gun_assets,
                 prepare_takeoff,
             ),
         )
         .add_systems(
             Startup,
-            map_mfd::setup_map_mfd.after(setup_procedural_world),
+            (
+                map_mfd::setup_map_mfd.after(setup_procedural_world),
+                setup_mission.after(setup_procedural_world),
+                spawn_enemy_fighters.after(setup_procedural_world),
+            ),
         )
         .add_systems(
             Update,
@@ -126,8 +178,76 @@ fn main() {
                 update_dialog_ui,
                 origin_shift,
                 map_mfd::update_map_mfd,
+                record_missile_telemetry.after(update_missiles),
+                update_pending_explosions,
+                update_fireballs,
+                update_smoke,
+                update_missile_trails.after(update_missiles),
+                update_rwr_scope.after(update_radar),
+                handle_radar_events_f117_ai.after(update_radar),
+            )
+        )
+        .add_systems(
+            Update,
+            (
+                handle_countermeasure_controls,
+                update_chaff_dispensers.after(handle_countermeasure_controls),
+                update_chaff,
+                update_flare_dispensers.after(handle_countermeasure_controls),
+                update_flares,
+                (add_ir_signatures, update_ir_signatures, update_ir_seekers).chain().before(update_missiles),
             )
         )
+        .add_systems(
+            Update,
+            (
+                handle_emcon_controls,
+                handle_arm_controls,
+                update_arm_seekers.before(update_missiles),
+                update_elint.after(handle_emcon_controls).before(update_iads),
+                update_iads.after(update_radar),
+            )
+        )
+        .add_systems(
+            Update,
+            (
+                update_sam_batteries.after(update_iads),
+                remove_destroyed_sites.after(handle_destroyed_entities),
+                update_emitter_tactics.after(update_sam_batteries),
+                update_convoys,
+                update_ships,
+                update_mission,
+                update_scud_launchers,
+                update_scud_missiles,
+                update_time_critical_targets,
+                update_sam_launchers.after(update_radar),
+                update_radar_guided_missiles.after(update_sam_launchers).before(update_missiles),
+                update_flak_guns.after(update_radar),
+                update_flak_bursts.before(apply_blast_damage),
+            )
+        )
+        .add_systems(
+            Update,
+            (
+                apply_blast_damage,
+                apply_damage,
+                handle_destroyed_entities,
+                handle_player_destroyed,
+                handle_destroyed_scenery,
+                spawn_impact_decals,
+            ).chain().after(handle_collision_events).after(update_missiles)
+        )
+        .add_systems(
+            Update,
+            (
+                equip_aircraft_guns,
+                update_ai_gunnery,
+                update_fighters,
+                update_guns,
+                update_projectiles,
+            ).chain().after(update_aircraft_forces).before(apply_damage)
+        )
+        .add_observer(report_missile_telemetry)
         .run();
 }
 
@@ -8,6 +8,9 @@ use bevy::{
 };
 
 use crate::{definitions::{COLOR_GREEN, RENDERLAYER_COCKPIT, RENDERLAYER_MFD, RENDERLAYER_WORLD}, player::Player, targeting::SensorTarget};
+use crate::{line_of_sight::{check_line_of_sight, LineOfSightCache}, terrain::TerrainData};
+use crate::emcon::{Emcon, EmitterType};
+use crate::ship::Ship;
 
 #[derive(Component)]
 pub struct FlirCamera;
@@ -20,6 +23,18 @@ pub struct FlirImage {
 #[derive(Component)]
 pub struct MfdSprite;
 
+/// Shown on the FLIR when the sensor target is hidden behind terrain
+#[derive(Component)]
+pub struct FlirMaskIndicator;
+
+/// Shown on the FLIR when the sensor target is a ship
+#[derive(Component)]
+pub struct FlirShipSymbol;
+
+/// Slant range to the sensor target, only known while the laser rangefinder may fire
+#[derive(Component)]
+pub struct FlirRangeLabel;
+
 //Set up the MFD displaying the correct texture
 pub fn update_mfd(
     mut commands: Commands,
@@ -28,23 +43,50 @@ pub fn update_mfd(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     query: Query<Entity, With<MfdSprite>>,
-    player_transform: Query<&Transform, (With<Player>, Without<FlirCamera>, Without<SensorTarget>)>,
-    mut flir_cameras: Query<&mut Transform, (With<FlirCamera>, Without<Player>, Without<SensorTarget>)>,
-    sensor_target: Query<&Transform, (With<SensorTarget>, Without<Player>, Without<FlirCamera>)>,
+    player_transform: Query<(&Transform, Option<&Emcon>), (With<Player>, Without<FlirCamera>, Without<SensorTarget>)>,
+    mut flir_cameras: Query<(&mut Transform, Option<&mut LineOfSightCache>), (With<FlirCamera>, Without<Player>, Without<SensorTarget>)>,
+    sensor_target: Query<(Entity, &Transform, Has<Ship>), (With<SensorTarget>, Without<Player>, Without<FlirCamera>)>,
+    mut mask_indicators: Query<&mut Visibility, With<FlirMaskIndicator>>,
+    mut ship_symbols: Query<&mut Visibility, (With<FlirShipSymbol>, Without<FlirMaskIndicator>)>,
+    mut range_labels: Query<&mut Text2d, With<FlirRangeLabel>>,
+    terrain: Option<Res<TerrainData>>,
 ) {
     match query.single() {
         Ok(_) => {
-            for mut transform in flir_cameras.iter_mut() {
-                transform.translation = player_transform.single().unwrap().translation;
+            let mut masked = false;
+            let mut ship = false;
+            let mut range: Option<f32> = None;
+            let (player_transform, emcon) = player_transform.single().unwrap();
+            let laser_armed = emcon.is_some_and(|e| e.is_active(EmitterType::LASER));
+            for (mut transform, mut los_cache) in flir_cameras.iter_mut() {
+                transform.translation = player_transform.translation;
                 match sensor_target.single() {
-                    Ok(target_transform) => {
+                    Ok((target_entity, target_transform, is_ship)) => {
+                        ship = is_ship;
                         let los = target_transform.translation - transform.translation;
                         *transform = transform.looking_to(los.normalize(), Vec3::Y);
+                        masked = !check_line_of_sight(los_cache.as_deref_mut(), terrain.as_deref(), target_entity,
+                            transform.translation, target_transform.translation);
+                        if laser_armed && !masked {
+                            range = Some(los.length());
+                        }
                     },
                     Err(_) => {
                     }
                 }
             }
+            for mut visibility in mask_indicators.iter_mut() {
+                *visibility = if masked { Visibility::Visible } else { Visibility::Hidden };
+            }
+            for mut visibility in ship_symbols.iter_mut() {
+                *visibility = if ship { Visibility::Visible } else { Visibility::Hidden };
+            }
+            for mut range_label in range_labels.iter_mut() {
+                range_label.0 = match range {
+                    Some(range) => format!("RNG {:.1}", range / 1000.0),
+                    None => "RNG ---".to_string(),
+                };
+            }
         },
         Err(_) => {
             match image_handles {
@@ -71,6 +113,48 @@ pub fn update_mfd(
                         Transform::from_translation(Vec3::new(-100.0, -100.0, 0.0)),
                     )).insert(RenderLayers::layer(RENDERLAYER_MFD));
 
+                    commands.spawn((
+                        Text2d::new("MASK"),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: 30.0,
+                            ..default()
+                        },
+                        TextColor(COLOR_GREEN),
+                        TextLayout::new_with_justify(Justify::Left),
+                        Transform::from_translation(Vec3::new(100.0, -100.0, 0.0)),
+                        Visibility::Hidden,
+                    ))
+                    .insert(RenderLayers::layer(RENDERLAYER_MFD))
+                    .insert(FlirMaskIndicator);
+
+                    commands.spawn((
+                        Text2d::new("RNG ---"),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: 30.0,
+                            ..default()
+                        },
+                        TextColor(COLOR_GREEN),
+                        TextLayout::new_with_justify(Justify::Left),
+                        Transform::from_translation(Vec3::new(100.0, 100.0, 0.0)),
+                    ))
+                    .insert(RenderLayers::layer(RENDERLAYER_MFD))
+                    .insert(FlirRangeLabel);
+
+                    commands.spawn((
+                        Sprite {
+                            image: asset_server.load("mfd/symbology-ship.png"),
+                            custom_size: Some(Vec2::splat(40.0)),
+                            color: COLOR_GREEN,
+                            ..default()
+                        },
+                        Transform::from_translation(Vec3::new(-100.0, 100.0, 0.0)),
+                        Visibility::Hidden,
+                    ))
+                    .insert(RenderLayers::layer(RENDERLAYER_MFD))
+                    .insert(FlirShipSymbol);
+
                     draw_crosshair(&mut commands, &mut meshes, &mut materials);
 
                 }
@@ -163,6 +247,7 @@ pub fn setup_flir(
                 .looking_at(Vec3::ZERO, Vec3::Y),
         ))
         .insert(FlirCamera)
+        .insert(LineOfSightCache::default())
         .insert(RenderLayers::layer(RENDERLAYER_WORLD));
 
         // HUD camera
@@ -1,7 +1,24 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::{util::*, targeting::Targetable, explosion::{spawn_explosion, ExplosionType}};
+use crate::{util::*, targeting::Targetable, explosion::{spawn_explosion, spawn_smoke_puff, explosion_type_for_warhead, ExplosionAssets, SmokePuff}, health::BlastEvent};
+use crate::sam::RadarGuidance;
+use crate::arm::ArmSeeker;
+use crate::infrared::IrSeeker;
+use crate::pointlight::{LightBillboard, LightBillboardToBeAdded, LightColor, LightType, LightSourceType};
+
+/// Distance between two smoke puffs of a missile trail
+const TRAIL_PUFF_SPACING: f32 = 0.5;
+/// Upper bound on puffs per missile and frame, in case of a frame rate hitch
+const TRAIL_MAX_PUFFS_PER_FRAME: usize = 10;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekerState {
+    IGNITION,
+    TRACKING,
+    TARGET_LOST,
+}
 
 #[derive(Component)]
 pub struct Missile {
@@ -18,12 +35,17 @@ pub struct Missile {
     pub turn_ramp: f32,
     pub gain: f32,
     pub ignition_delay: u64,
+    pub motor_burn_time: u64, // Milliseconds of powered flight after ignition
     pub proximity_fuse_distance: f32,
     pub proximity_fuse_arm_time: u64,
+    pub warhead_radius: f32,
+    pub warhead_damage: f32,
     pub last_target_distance: f32,
     pub last_position: Vec3,
     pub line_of_sight: Vec3,
+    pub los_rate: f32, // Line of sight rotation rate in rad/s, for telemetry
     pub acceleration: Vec3,
+    pub seeker_state: SeekerState,
 }
 
 impl Default for Missile {
@@ -39,12 +61,17 @@ impl Default for Missile {
             turn_ramp: 0.2,
             gain: 3.0,
             ignition_delay: 300,
+            motor_burn_time: 15000,
             proximity_fuse_distance: 1.0,
             proximity_fuse_arm_time: 5000,
+            warhead_radius: 2.0,
+            warhead_damage: 200.0,
             last_target_distance: 9999999999.9,
             last_position: Vec3::new(0.0, 0.0, 0.0),
             line_of_sight: Vec3::new(0.0, 0.0, 0.0),
+            los_rate: 0.0,
             acceleration: Vec3::new(0.0, 0.0, 0.0),
+            seeker_state: SeekerState::IGNITION,
             launching_vehicle: Entity::PLACEHOLDER,
             target: Entity::PLACEHOLDER,
             target_transform: Transform::from_xyz(0.0, 0.0, 0.0)
@@ -52,24 +79,56 @@ impl Default for Missile {
     }
 }
 
+impl Missile {
+    pub fn motor_burning(&self, current_time: u64) -> bool {
+        let flight_time = current_time.saturating_sub(self.start_time);
+        flight_time >= self.ignition_delay && flight_time < self.ignition_delay + self.motor_burn_time
+    }
+}
+
+/// Smoke trail and plume light of a missile whose motor is burning
+#[derive(Component)]
+pub struct MissileTrail {
+    pub last_puff_position: Vec3,
+}
+
 #[allow(unused_mut)]
 pub fn update_missiles(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    mut missiles: Query<(Entity, &mut ExternalForce, &mut Transform, &mut Missile)>,
+    mut missiles: Query<(Entity, &mut ExternalForce, &mut Transform, &mut Missile, Has<RadarGuidance>, Has<ArmSeeker>, Has<IrSeeker>)>,
     missile_targets: Query<&Transform, (With<Targetable>, Without<Missile>)>,
-    all_targets: Query<(Entity, &Transform), (With<Targetable>, Without<Missile>)>,
+    heat_sources: Query<&Transform, Without<Missile>>,
+    mut blast_events: MessageWriter<BlastEvent>,
     time: Res<Time>,
 ) {
-    for (missile_entity, missile_force, mut missile_transform, mut missile ) in missiles.iter_mut() {
-        let target_transform = missile_targets.get(missile.target);
+    for (missile_entity, missile_force, mut missile_transform, mut missile, radar_guided, anti_radiation, heat_seeking) in missiles.iter_mut() {
+        // Radar guided missiles get their target from the fire-control radar instead,
+        // anti-radiation missiles from their own seeker
+        if radar_guided || anti_radiation {
+            update_single_missile(missile_entity, &mut commands, missile, time.clone(), missile_transform, missile_force, &mut blast_events);
+            continue;
+        }
+        // Heat seekers home on whatever their seeker has chosen, targetable or not
+        let target_transform = if heat_seeking {
+            heat_sources.get(missile.target)
+        } else {
+            missile_targets.get(missile.target)
+        };
         match target_transform {
-            Ok(t) => missile.target_transform = *t,
-            Err(e) => info!("Missile targeting error: {}", e),
+            Ok(t) => {
+                missile.target_transform = *t;
+                if missile.seeker_state == SeekerState::TARGET_LOST {
+                    missile.seeker_state = SeekerState::TRACKING;
+                }
+            },
+            Err(e) => {
+                if missile.seeker_state != SeekerState::TARGET_LOST {
+                    info!("Missile targeting error: {}", e);
+                }
+                missile.seeker_state = SeekerState::TARGET_LOST;
+            },
         }
-        update_single_missile(missile_entity, &mut commands, &mut meshes, &mut materials, &asset_server, missile, time.clone(), missile_transform, missile_force, &all_targets);
+        update_single_missile(missile_entity, &mut commands, missile, time.clone(), missile_transform, missile_force, &mut blast_events);
 
     }
 
@@ -78,20 +137,20 @@ pub fn update_missiles(
 fn update_single_missile(
     missile_entity: Entity,
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    asset_server: &Res<AssetServer>,
     mut missile: Mut<Missile>,
     time: Time,
     mut missile_transform: Mut<Transform>,
     mut missile_force: Mut<ExternalForce>,
-    all_targets: &Query<(Entity, &Transform), (With<Targetable>, Without<Missile>)>,
+    blast_events: &mut MessageWriter<BlastEvent>,
 ) {
 
     let current_time = get_time_millis();
     if current_time - missile.start_time < missile.ignition_delay {
         return;
     }
+    if missile.seeker_state == SeekerState::IGNITION {
+        missile.seeker_state = SeekerState::TRACKING;
+    }
 
     missile.target_position = missile.target_transform.translation;
 
@@ -101,17 +160,9 @@ fn update_single_missile(
      if current_time - missile.start_time > missile.proximity_fuse_arm_time {
         if target_distance > missile.last_target_distance {
             if missile.last_target_distance < missile.proximity_fuse_distance {
-                for (prox_target_entity, prox_target_transform) in all_targets.iter() {
-                    let prox_target_distance = (prox_target_transform.translation - missile_transform.translation).length();
-                    //Damage all targets within proximity fuse distance
-                    if prox_target_distance < missile.proximity_fuse_distance {
-                        info!("Missile proximity detonation");
-                        commands.entity(prox_target_entity).despawn();
-                        let position = prox_target_transform.translation;
-                        spawn_explosion(commands, meshes, materials, ExplosionType::SMALL, &position);
-                    }
-                }
-                commands.spawn(AudioPlayer::new(asset_server.load("sounds/xplgmn2.ogg")));
+                info!("Missile proximity detonation");
+                //Damage all targets within the warhead's blast radius
+                detonate_missile(commands, &missile, &missile_transform.translation, blast_events);
                 commands.entity(missile_entity).despawn();
             }
         }
@@ -121,8 +172,10 @@ fn update_single_missile(
     missile.last_position = missile_transform.translation;
 
 
-    // Increase thrust over time
-    if missile.thrust < missile.max_thrust {
+    // Increase thrust over time, until the motor burns out
+    if !missile.motor_burning(current_time) {
+        missile.thrust = 0.0;
+    } else if missile.thrust < missile.max_thrust {
         // don't go over in case thrustRamp is very small
         let increase = time.delta_secs() * missile.max_thrust / missile.thrust_ramp;
         missile.thrust = (missile.thrust + increase).min(missile.max_thrust);
@@ -139,6 +192,9 @@ fn update_single_missile(
     let prev_los = missile.line_of_sight;
     missile.line_of_sight = missile.target_position - missile_transform.translation;
     let mut d_los = missile.line_of_sight - prev_los;
+    if prev_los != Vec3::ZERO && time.delta_secs() > 0.0 {
+        missile.los_rate = prev_los.angle_between(missile.line_of_sight) / time.delta_secs();
+    }
 
     // we only want the component perpendicular to the line of sight
     d_los = d_los - d_los.project_onto(missile.line_of_sight);
@@ -147,38 +203,93 @@ fn update_single_missile(
     // acceleration = time.delta_secs() * missile.line_of_sight + dLos * nc;
 
     // Augmented PN takes acceleration into account
-    missile.acceleration = time.delta_secs() * missile.line_of_sight + d_los * missile.gain + time.delta_secs() * missile.acceleration * missile.gain / 2.0;
+    let guidance = time.delta_secs() * missile.line_of_sight + d_los * missile.gain + time.delta_secs() * missile.acceleration * missile.gain / 2.0;
     // Acceleration can't be larger than the maximum thrust
-    missile.acceleration = (missile.acceleration * missile.thrust).clamp_length_max(missile.thrust);
+    missile.acceleration = (guidance * missile.thrust).clamp_length_max(missile.thrust);
 
     // Accelerate towards target
     missile_force.force = missile.acceleration;
 
     // Turn towards target
-    let mut target_transform:Transform = Transform::default();
-    target_transform = target_transform.looking_to(missile.acceleration.normalize(), Vec3::Y);
-    missile_transform.rotation = missile_transform.rotation.lerp(target_transform.rotation, time.delta_secs() * missile.turn_rate);
+    // The fins keep steering after burnout, so this doesn't depend on thrust
+    if guidance.length_squared() > 0.0 {
+        let mut target_transform:Transform = Transform::default();
+        target_transform = target_transform.looking_to(guidance.normalize(), Vec3::Y);
+        missile_transform.rotation = missile_transform.rotation.lerp(target_transform.rotation, time.delta_secs() * missile.turn_rate);
+    }
 
 }
 
 
+/// Leave a smoke trail and light up the motor plume while the motor burns
+pub fn update_missile_trails(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Res<ExplosionAssets>,
+    mut missiles: Query<(Entity, &Missile, &Transform, Option<&mut MissileTrail>)>,
+    plumes: Query<(Entity, &ChildOf), With<LightBillboard>>,
+) {
+    let current_time = get_time_millis();
+    for (entity, missile, transform, trail) in missiles.iter_mut() {
+        let nozzle_position = transform.translation - transform.forward() * 0.3;
+        let burning = missile.motor_burning(current_time);
+        match trail {
+            None if burning => {
+                commands.entity(entity)
+                    .insert(MissileTrail { last_puff_position: nozzle_position })
+                    .insert(LightBillboardToBeAdded {
+                        light_color: LightColor::YELLOW,
+                        light_type: LightType::SOLID,
+                        lightsource_type: LightSourceType::POINT,
+                    });
+            },
+            Some(mut trail) if burning => {
+                // Fill the distance travelled since the last puff, so the trail has no gaps at high speed
+                let travelled = nozzle_position - trail.last_puff_position;
+                let puffs = ((travelled.length() / TRAIL_PUFF_SPACING) as usize).min(TRAIL_MAX_PUFFS_PER_FRAME);
+                for i in 1..=puffs {
+                    let position = trail.last_puff_position + travelled * (i as f32 / puffs as f32);
+                    spawn_smoke_puff(&mut commands, &mut materials, &explosion_assets, position, SmokePuff {
+                        start_time: current_time,
+                        life_time: random_u64(5000, 8000),
+                        start_size: 0.15,
+                        end_size: random_f32(1.0, 1.6),
+                        velocity: random_vec3(0.1),
+                        opacity: 0.5,
+                    });
+                }
+                if puffs > 0 {
+                    trail.last_puff_position = nozzle_position;
+                }
+            },
+            Some(_) => {
+                // Burnout: the trail stops and the plume goes dark
+                for (plume, child_of) in plumes.iter() {
+                    if child_of.parent() == entity {
+                        commands.entity(plume).despawn();
+                    }
+                }
+                commands.entity(entity).remove::<MissileTrail>();
+            },
+            None => {},
+        }
+    }
+}
+
 /* A system that displays the events. */
 #[allow(unused_mut)]
 pub fn handle_collision_events(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
     mut collision_events: MessageReader<CollisionEvent>,
     mut missiles: Query<(Entity, &mut ExternalForce, &mut Transform, &mut Collider, &mut Missile)>,
-    all_targets: Query<(Entity, &Transform), (With<Targetable>, Without<Missile>)>,
+    mut blast_events: MessageWriter<BlastEvent>,
 ) {
     for collision_event in collision_events.read() {
         println!("Received collision event: {:?}", collision_event);
         match collision_event {
             CollisionEvent::Started(entity1, entity2, _) => {
-                handle_collision_entity(&missiles, entity1, &all_targets, &mut commands, &mut meshes, &mut materials, &asset_server);
-                handle_collision_entity(&missiles, entity2, &all_targets, &mut commands, &mut meshes, &mut materials, &asset_server);
+                handle_collision_entity(&missiles, entity1, &mut blast_events, &mut commands);
+                handle_collision_entity(&missiles, entity2, &mut blast_events, &mut commands);
             },
             CollisionEvent::Stopped(_, _, _) => {
                 // Do nothing
@@ -189,11 +300,8 @@ pub fn handle_collision_events(
 fn handle_collision_entity(
     missiles: &Query<'_, '_, (Entity, &mut ExternalForce, &mut Transform, &mut Collider, &mut Missile)>,
     entity: &Entity,
-    all_targets: &Query<'_, '_, (Entity, &Transform), (With<Targetable>, Without<Missile>)>,
+    blast_events: &mut MessageWriter<BlastEvent>,
     commands: &mut Commands<'_, '_>,
-    meshes: &mut ResMut<'_, Assets<Mesh>>,
-    materials: &mut ResMut<'_, Assets<StandardMaterial>>,
-    asset_server: &Res<AssetServer>,
 ) {
     if missiles.get(*entity).is_ok() {
         let missile_transform_result = missiles.get(*entity);
@@ -202,20 +310,25 @@ fn handle_collision_entity(
                 info!("Missile contact detonation");
                 let missile_transform = *t.2;
                 let missile = t.4;
-                for (prox_target_entity, prox_target_transform) in all_targets.iter() {
-                    let prox_target_distance = (prox_target_transform.translation - missile_transform.translation).length();
-                    //Damage all targets within proximity fuse distance
-                    if prox_target_distance < missile.proximity_fuse_distance {
-                        commands.entity(prox_target_entity).despawn();
-                        let position = prox_target_transform.translation;
-                        spawn_explosion(commands, meshes, materials, ExplosionType::SMALL, &position);
-                    }
-                }
+                detonate_missile(commands, missile, &missile_transform.translation, blast_events);
                     },
             Err(e) => info!("Collision handling error: {}", e),
         }
-        commands.spawn(AudioPlayer::new(asset_server.load("sounds/xplgmn2.ogg")));
-
         commands.entity(*entity).despawn();
         }
 }
+
+/// Set off the warhead: damage everything within the blast radius and show the explosion
+fn detonate_missile(
+    commands: &mut Commands,
+    missile: &Missile,
+    position: &Vec3,
+    blast_events: &mut MessageWriter<BlastEvent>,
+) {
+    blast_events.write(BlastEvent {
+        position: *position,
+        radius: missile.warhead_radius,
+        damage: missile.warhead_damage,
+    });
+    spawn_explosion(commands, explosion_type_for_warhead(missile.warhead_damage), position);
+}
@@ -0,0 +1,79 @@
+use bevy::prelude::*;
+
+use crate::ship::{Ship, TaskGroup};
+
+/* Missions. Without one the player flies free over the island. An anti-ship strike sends the
+   player after the enemy task group, warships sailing together, and is accomplished once all
+   of them have been sunk. */
+
+#[allow(non_camel_case_types)]
+#[derive(Resource, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MissionType {
+    #[default]
+    FREE_FLIGHT,
+    ANTI_SHIP_STRIKE,
+}
+
+impl MissionType {
+    /// Picked on the command line with `--mission free|anti-ship`
+    pub fn from_args() -> MissionType {
+        let args: Vec<String> = std::env::args().collect();
+        match args.iter().position(|a| a == "--mission").and_then(|i| args.get(i + 1)).map(|s| s.as_str()) {
+            Some("anti-ship") => MissionType::ANTI_SHIP_STRIKE,
+            _ => MissionType::default(),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Mission {
+    pub mission_type: MissionType,
+    pub targets: Vec<Entity>,
+    pub remaining: usize,
+    pub accomplished: bool,
+}
+
+/// Brief the mission once the world has been laid out
+pub fn setup_mission(
+    mut commands: Commands,
+    mission_type: Res<MissionType>,
+    ships: Query<(Entity, &Transform, &Name), With<TaskGroup>>,
+) {
+    let targets: Vec<Entity> = match *mission_type {
+        MissionType::FREE_FLIGHT => Vec::new(),
+        MissionType::ANTI_SHIP_STRIKE => ships.iter()
+            .map(|(entity, transform, name)| {
+                // The airbase is at the origin before any origin shift
+                info!("Target: {}, {:.0} km from the airbase", name, transform.translation.length() / 1000.0);
+                entity
+            })
+            .collect(),
+    };
+    info!("Mission: {:?}, {} targets", *mission_type, targets.len());
+    commands.insert_resource(Mission {
+        mission_type: *mission_type,
+        remaining: targets.len(),
+        targets,
+        accomplished: false,
+    });
+}
+
+/// Keep count of the targets and report when the last one is gone
+pub fn update_mission(
+    mission: Option<ResMut<Mission>>,
+    targets: Query<(), With<Ship>>,
+) {
+    let Some(mut mission) = mission else { return };
+    if mission.accomplished || mission.mission_type == MissionType::FREE_FLIGHT {
+        return;
+    }
+    let remaining = mission.targets.iter().filter(|t| targets.contains(**t)).count();
+    if remaining < mission.remaining {
+        info!("Target destroyed, {} remaining", remaining);
+        mission.remaining = remaining;
+    }
+    if remaining == 0 {
+        info!("Mission accomplished, return to base");
+        mission.accomplished = true;
+    }
+}
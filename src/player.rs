@@ -4,22 +4,30 @@ use bevy_rapier3d::prelude::*;
 use crate::bevy_scene_hook::SceneHook;
 use crate::coalition::Coalition;
 use crate::coalition::CoalitionType;
+use crate::arm::ArmLauncher;
+use crate::countermeasures::{ChaffDispenser, FlareDispenser, NoiseJammer};
 use crate::definitions::*;
+use crate::emcon::Emcon;
+use crate::explosion::{spawn_explosion, ExplosionType};
 use crate::aircraft::*;
 use crate::f117_ai::F117AIEvent;
 use crate::f117_ai::F117AIState;
 use crate::f117_ai::activate_f117_ai;
+use crate::health::DestroyedEvent;
 use crate::pointlight::LightBillboardToBeAdded;
 use crate::pointlight::get_light_color_from_name;
 use crate::pointlight::get_light_type_from_name;
 use crate::pointlight::get_lightsource_type_from_name;
 use crate::radar::RadarDetectable;
 use crate::vehicle::*;
-use crate::sam::*;
 
 #[derive(Component)]
 pub struct Player;
 
+/// The player has been shot down, the controls are dead
+#[derive(Component)]
+pub struct ShotDown;
+
 
 pub fn spawn_player(mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -45,8 +53,14 @@ pub fn spawn_player(mut commands: Commands,
     .insert(Coalition{side: CoalitionType::BLUE})
     .insert(RadarDetectable {
         base_radar_cross_section: 0.2,
+        rcs_table: asset_server.load(rcs_table_file(&AircraftType::F117A)),
         ..default()
     })
+    .insert(ChaffDispenser{..default()})
+    .insert(FlareDispenser{..default()})
+    .insert(NoiseJammer{..default()})
+    .insert(Emcon{..default()})
+    .insert(ArmLauncher{..default()})
     .insert(Vehicle{..default()})
     .insert(Aircraft{name: String::from("GHOST 1-1"), aircraft_type: AircraftType::F117A, fuel: 35500.0, ..default() })
     .insert(ExternalImpulse {
@@ -74,8 +88,6 @@ pub fn spawn_player(mut commands: Commands,
     // Player airplane is layer 3 so it can be skipped when rendering cockpit view
     .insert(RenderLayers::layer(RENDERLAYER_AIRCRAFT));
 
-    spawn_sam(&mut commands, &asset_server, 3000.0, 10.0, CoalitionType::RED);
-
 }
 
 pub fn prepare_takeoff(
@@ -84,3 +96,58 @@ pub fn prepare_takeoff(
     println!("Prepare Takeoff");
     activate_f117_ai (&mut f117_ai_state,F117AIEvent::Takeoff);
 }
+
+/// A destroyed player aircraft isn't removed: it catches fire, the engine dies and it falls out of the sky
+pub fn handle_player_destroyed(
+    mut commands: Commands,
+    mut destroyed_events: MessageReader<DestroyedEvent>,
+    mut players: Query<&mut Aircraft, (With<Player>, Without<ShotDown>)>,
+) {
+    for destroyed in destroyed_events.read() {
+        if let Ok(mut aircraft) = players.get_mut(destroyed.entity) {
+            info!("{} shot down", aircraft.name);
+            spawn_explosion(&mut commands, ExplosionType::MEDIUM, &destroyed.position);
+            aircraft.throttle = 0.0;
+            aircraft.pitch_force = 0.0;
+            aircraft.roll_force = 0.0;
+            aircraft.yaw_force = 0.0;
+            commands.entity(destroyed.entity).insert(ShotDown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::health::{apply_blast_damage, BlastEvent, DamageEvent};
+    use crate::sam::SAMType;
+    use crate::threats::threat_data;
+
+    #[test]
+    fn sam_proximity_burst_shoots_down_player() {
+        let mut world = World::new();
+        world.init_resource::<Messages<BlastEvent>>();
+        world.init_resource::<Messages<DamageEvent>>();
+        world.init_resource::<Messages<DestroyedEvent>>();
+        let player = world.spawn((
+            Player,
+            Aircraft { aircraft_type: AircraftType::F117A, ..default() },
+            Transform::from_xyz(0.0, 300.0, 0.0),
+        )).id();
+
+        // An SA-6 round bursting close by
+        let missile_data = threat_data(&SAMType::SA6).missile.as_ref().unwrap();
+        world.write_message(BlastEvent {
+            position: Vec3::new(1.0, 300.0, 0.0),
+            radius: missile_data.warhead_radius,
+            damage: missile_data.warhead_damage,
+        });
+        world.run_system_once(apply_blast_damage).unwrap();
+        world.run_system_once(handle_player_destroyed).unwrap();
+
+        assert_eq!(world.get::<Aircraft>(player).unwrap().health, 0.0);
+        assert!(world.get::<ShotDown>(player).is_some());
+    }
+}
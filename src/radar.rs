@@ -1,16 +1,104 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
+use bevy::reflect::TypePath;
+use bevy_rapier3d::prelude::Velocity;
+use ::serde::Deserialize;
+
+use crate::{aircraft::Aircraft, coalition::Coalition, util::get_time_millis, definitions::RADAR_PULSE_TIMEOUT};
+use crate::{countermeasures::NoiseJammer, line_of_sight::{check_line_of_sight, LineOfSightCache}, terrain::TerrainData};
 
-use crate::{coalition::Coalition, util::get_time_millis, definitions::RADAR_PULSE_TIMEOUT};
+/// RCS added while the weapon bay doors are open
+const BAY_DOOR_RCS: f32 = 0.6;
+/// RCS added per store carried on external pylons
+const EXTERNAL_STORE_RCS: f32 = 0.2;
+/// RCS added by battle damage at zero health
+const DAMAGE_RCS: f32 = 0.5;
+/// RCS added while the chaff dispenser is open
+const DISPENSER_RCS: f32 = 0.3;
+/// Radar energy is picked up by a RWR further out than the radar can see a return
+const ILLUMINATION_RANGE_FACTOR: f32 = 1.5;
+/// Illumination records older than this are dropped (ms)
+const ILLUMINATION_MEMORY: u64 = 10_000;
+/// Radar gain at which distance_attenuation gives the detection range of a target with an RCS of 1
+const REFERENCE_GAIN: f32 = 10.0;
+/// Doppler returns fade in over this fraction of the notch width beyond its edge
+const NOTCH_EDGE: f32 = 0.5;
+/// Below this height above the ground, pulse radar returns drown in ground clutter
+const CLUTTER_HEIGHT: f32 = 50.0;
+/// What a pulse radar still gets out of the clutter from a target on the ground
+const MIN_CLUTTER_FACTOR: f32 = 0.15;
 
 #[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
 pub enum RadarEmitterType {
     PULSE,
     DOPPLER,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum RadarBand {
+    VHF,
+    UHF,
+    S,
+    C,
+    X,
+    KU,
+}
+
+impl RadarBand {
+    /// Long wavelengths resonate with airframe features of about their size, which shaping
+    /// can't hide. A stealth airframe never looks smaller than this to a radar of this band.
+    pub fn resonance_rcs(&self) -> f32 {
+        match self {
+            RadarBand::VHF => 0.5,
+            RadarBand::UHF => 0.2,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Radar cross section of an airframe by aspect, loaded from assets/rcs
+#[derive(Deserialize, Asset, TypePath)]
+pub struct RcsTable {
+    azimuths: Vec<f32>, // Degrees from the nose, 0 to 180
+    elevations: Vec<f32>, // Degrees, -90 (emitter below) to 90 (emitter above)
+    values: Vec<Vec<f32>>, // One row per elevation, one column per azimuth
+}
+
+impl RcsTable {
+    /// Bilinear interpolation between the table entries around the given aspect
+    pub fn lookup(&self, azimuth: f32, elevation: f32) -> f32 {
+        let (a0, a1, at) = interpolation_index(&self.azimuths, azimuth.abs());
+        let (e0, e1, et) = interpolation_index(&self.elevations, elevation);
+        let value = |e: usize, a: usize| self.values.get(e).and_then(|row| row.get(a)).copied().unwrap_or(0.0);
+        let low = value(e0, a0) + (value(e0, a1) - value(e0, a0)) * at;
+        let high = value(e1, a0) + (value(e1, a1) - value(e1, a0)) * at;
+        low + (high - low) * et
+    }
+}
+
+fn interpolation_index(steps: &[f32], x: f32) -> (usize, usize, f32) {
+    if steps.is_empty() {
+        return (0, 0, 0.0);
+    }
+    for i in 0..steps.len() - 1 {
+        if x <= steps[i + 1] {
+            let t = ((x - steps[i]) / (steps[i + 1] - steps[i])).clamp(0.0, 1.0);
+            return (i, i + 1, t);
+        }
+    }
+    (steps.len() - 1, steps.len() - 1, 0.0)
+}
+
 #[derive(Component)]
 pub struct RadarDetectable {
-    pub base_radar_cross_section: f32, // This is the basic visibility value
+    pub base_radar_cross_section: f32, // This is the basic visibility value, used if there is no RCS table
+    pub rcs_table: Handle<RcsTable>,
+    pub rcs_modifier: f32, // Added RCS from damage, open bay doors and external stores
+    pub bay_doors_open_until: u64,
+    pub dispenser_open_until: u64,
+    pub external_stores: u32,
     pub radar_cross_section: f32, // Calculated radar visibility based on orientation, for RWR display
     pub reflected_energy: f32, // Calculated radar return energy, for RWR display
     pub last_impulse_time: u64, // Time of last radar pulse, for RWR display
@@ -20,6 +108,11 @@ impl Default for RadarDetectable {
     fn default() -> Self {
          RadarDetectable {
             base_radar_cross_section: 0.2,
+            rcs_table: Handle::default(),
+            rcs_modifier: 0.0,
+            bay_doors_open_until: 0,
+            dispenser_open_until: 0,
+            external_stores: 0,
             radar_cross_section: 0.0,
             reflected_energy: 0.0,
             last_impulse_time: 0,
@@ -27,13 +120,125 @@ impl Default for RadarDetectable {
     }
 }
 
+impl RadarDetectable {
+    pub fn open_bay_doors(&mut self, duration: u64) {
+        self.bay_doors_open_until = get_time_millis() + duration;
+    }
+}
+
+/// RCS of a target as seen from the emitter position, from the airframe's table and current modifiers
+pub fn aspect_rcs(
+    detectable: &RadarDetectable,
+    rcs_table: Option<&RcsTable>,
+    detectable_transform: &Transform,
+    emitter_position: Vec3,
+) -> f32 {
+    // Direction to the emitter in airframe coordinates: +X forward, +Y up, +Z starboard
+    let to_emitter = detectable_transform.rotation.inverse() * (emitter_position - detectable_transform.translation).normalize_or_zero();
+    let azimuth = to_emitter.z.atan2(to_emitter.x).to_degrees();
+    let elevation = to_emitter.y.clamp(-1.0, 1.0).asin().to_degrees();
+    let table_rcs = match rcs_table {
+        Some(table) => table.lookup(azimuth, elevation),
+        None => detectable.base_radar_cross_section,
+    };
+
+    // Altitude is shown in feet, at ten feet per world unit
+    table_rcs * altitude_factor(detectable_transform.translation.y * 10.0) + detectable.rcs_modifier
+}
+
+/// Radial velocity of a target relative to the emitter, positive when moving away
+pub fn radial_velocity(emitter_position: Vec3, emitter_velocity: Vec3, target_position: Vec3, target_velocity: Vec3) -> f32 {
+    let line_of_sight = (target_position - emitter_position).normalize_or_zero();
+    (target_velocity - emitter_velocity).dot(line_of_sight)
+}
+
+/// Fraction of the return a doppler radar keeps after filtering out everything that moves
+/// slower than the notch width along the line of sight. This also rejects ground clutter,
+/// chaff and anything flying perpendicular to the beam.
+pub fn doppler_filter(radial_velocity: f32, notch_width: f32) -> f32 {
+    if notch_width <= 0.0 {
+        return 1.0;
+    }
+    ((radial_velocity.abs() - notch_width) / (notch_width * NOTCH_EDGE)).clamp(0.0, 1.0)
+}
+
+/// Fraction of the return a pulse radar can tell apart from the ground clutter
+pub fn clutter_factor(height_above_ground: f32) -> f32 {
+    (height_above_ground / CLUTTER_HEIGHT).clamp(MIN_CLUTTER_FACTOR, 1.0)
+}
+
+/// What the radar as a whole is doing, driven by the state of its best track
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RadarMode {
+    SEARCH,
+    TRACK,
+    LOCK,
+    LAUNCH,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrackState {
+    DETECTED, // Seen, but not enough returns yet to build a track
+    TRACKING,
+    COASTING, // Lost contact, extrapolating until the track times out
+    LOCKED,
+}
+
+#[derive(Debug, Clone)]
+pub struct RadarTrack {
+    pub target: Entity,
+    pub state: TrackState,
+    pub quality: f32, // 0.0 to 1.0, builds up over several scans
+    pub last_detection_time: u64,
+    pub last_position: Vec3,
+    pub last_signal: f32,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RadarTransition {
+    DETECTED,
+    TRACK_ESTABLISHED,
+    TRACK_COASTING,
+    TRACK_LOST,
+    LOCKED,
+    LOCK_BROKEN,
+    LAUNCH,
+}
+
+/// Sent whenever one of an emitter's tracks changes state, for RWR, SAM launchers and AI chatter
+#[derive(Message)]
+pub struct RadarEvent {
+    pub emitter: Entity,
+    pub target: Entity,
+    pub transition: RadarTransition,
+}
+
 #[derive(Component)]
 pub struct RadarEmitter {
     pub radar_type: RadarEmitterType,
     pub radar_gain: f32, // Affects how difficult it is to hide from this radar
+    pub band: RadarBand,
     pub max_detect_range_km: f32, // Maximum detection range in km
     pub scan_interval: f32, // Radar sweep interval in seconds
+    pub track_interval: f32, // Update interval in seconds while tracking
+    pub detection_threshold: f32, // Minimum return signal to count as a detection
+    pub track_quality_gain: f32, // Track quality added per detection
+    pub track_quality_decay: f32, // Track quality lost per scan without detection
+    pub lock_quality: f32, // Track quality needed to lock on
+    pub coast_time: f32, // Seconds a track survives without detections
+    pub launch_delay: f32, // Seconds a lock must be held before launch
+    pub resolution_cell: f32, // Returns closer together than this can't be told apart
+    pub home_on_jam: bool, // Can track a noise jammer by its jamming
+    pub notch_width: f32, // Doppler radars filter out targets with less radial velocity than this
+    pub emitting: bool, // A dark radar neither scans nor shows up on RWRs
+    pub rwr_code: String, // Shown on the RWR scope of the targets
+    pub illuminated: HashMap<Entity, u64>, // Time each target was last painted by this radar
     pub last_scan_time: u64,
+    pub mode: RadarMode,
+    pub tracks: Vec<RadarTrack>,
+    pub locked_target: Option<Entity>,
+    pub lock_time: u64,
 }
 
 impl Default for RadarEmitter {
@@ -41,104 +246,602 @@ impl Default for RadarEmitter {
          RadarEmitter {
             radar_type: RadarEmitterType::PULSE,
             radar_gain: 100.0,
+            band: RadarBand::S,
             scan_interval: 3.0,
+            track_interval: 0.5,
             max_detect_range_km: 100.0,
+            detection_threshold: 1.0,
+            track_quality_gain: 0.35,
+            track_quality_decay: 0.2,
+            lock_quality: 0.9,
+            coast_time: 6.0,
+            launch_delay: 4.0,
+            resolution_cell: 300.0,
+            home_on_jam: true,
+            notch_width: 6.0,
+            emitting: true,
+            rwr_code: String::from("U"),
+            illuminated: HashMap::new(),
             last_scan_time: 0,
+            mode: RadarMode::SEARCH,
+            tracks: Vec::new(),
+            locked_target: None,
+            lock_time: 0,
          }
     }
 }
 
+impl RadarEmitter {
+    /// Start a track on a target reported by someone else, so the radar doesn't have to find it first
+    pub fn cue(&mut self, target: Entity, position: Vec3, milliseconds: u64) {
+        if self.tracks.iter().any(|t| t.target == target) {
+            return;
+        }
+        self.tracks.push(RadarTrack {
+            target,
+            state: TrackState::DETECTED,
+            quality: self.track_quality_gain,
+            last_detection_time: milliseconds,
+            last_position: position,
+            last_signal: 0.0,
+        });
+        // Look right away
+        self.last_scan_time = 0;
+    }
+}
+
+/// Track quality at which a detection becomes a firm track
+const TRACK_ESTABLISHED_QUALITY: f32 = 0.5;
+
 pub fn update_rcs (
-    mut detectables: Query<(&mut RadarDetectable, &Transform)>,
+    mut detectables: Query<(&mut RadarDetectable, &Transform, &Coalition, Option<&Aircraft>)>,
+    emitters: Query<(&Transform, &Coalition), With<RadarEmitter>>,
+    rcs_tables: Res<Assets<RcsTable>>,
 ) {
-    for (mut detectable, detectable_transform) in detectables.iter_mut() {
-        // Update the radar cross-section based on the pitch/roll angle of the aircraft.
-        // A level flying aircraft is a stealthy aircraft
-        let roll_factor = detectable_transform.rotation.x.sin().abs();
-        let pitch_factor =  detectable_transform.rotation.z.sin().abs();
+    let milliseconds = get_time_millis();
+    for (mut detectable, detectable_transform, detectable_coalition, aircraft) in detectables.iter_mut() {
+        let mut modifier = detectable.external_stores as f32 * EXTERNAL_STORE_RCS;
+        if milliseconds < detectable.bay_doors_open_until {
+            modifier += BAY_DOOR_RCS;
+        }
+        if milliseconds < detectable.dispenser_open_until {
+            modifier += DISPENSER_RCS;
+        }
+        if let Some(aircraft) = aircraft {
+            modifier += (1.0 - aircraft.health / 100.0).clamp(0.0, 1.0) * DAMAGE_RCS;
+        }
+        detectable.rcs_modifier = modifier;
+
+        // For the RWR display, show the RCS as seen by the hostile emitter we are most visible to
+        let rcs_table = rcs_tables.get(&detectable.rcs_table);
+        detectable.radar_cross_section = emitters.iter()
+            .filter(|(_, coalition)| coalition.side != detectable_coalition.side)
+            .map(|(emitter_transform, _)| aspect_rcs(&detectable, rcs_table, detectable_transform, emitter_transform.translation))
+            .fold(0.0, f32::max);
+    }
+}
+
+/// What a single scan sees of a single target
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScanResult {
+    pub illuminated: bool, // Close enough for the target's RWR to pick up the radar
+    pub signal: f32, // Strength of the echo
+    pub detection: Option<f32>, // Signal the radar detected the target with, echo or jamming
+}
+
+/// Targets of the same coalition are never scanned
+pub fn is_hostile(radar_coalition: &Coalition, target_coalition: &Coalition) -> bool {
+    radar_coalition.side != target_coalition.side
+}
+
+/// Radar equation: the echo falls off with the fourth power of distance. Normalised so a target
+/// with an RCS of 1 is right at the detection threshold at maximum range, for a radar with the
+/// reference gain.
+pub fn distance_attenuation(distance: f32, max_range: f32) -> f32 {
+    (max_range / distance.max(1.0)).powi(4)
+}
+
+/// Strength of the echo of a target, relative to the detection threshold
+pub fn return_signal(effective_gain: f32, radar_cross_section: f32, distance: f32, max_range: f32) -> f32 {
+    effective_gain / REFERENCE_GAIN * radar_cross_section * distance_attenuation(distance, max_range)
+}
+
+/// Radar returns rise with altitude until 1000 feet, remain strong until 8000 feet,
+/// then get weaker with rising altitude (but never below 0.4)
+pub fn altitude_factor(altitude_feet: f32) -> f32 {
+    let low_altitude_curve = (altitude_feet / 1000.0).clamp(0.0, 1.0);
+    let high_altitude_curve = (1.0 - (altitude_feet - 8000.0).max(0.0) / 20000.0).clamp(0.4, 1.0);
+    low_altitude_curve * high_altitude_curve
+}
+
+/// Gain left after the radar type's filtering. Pulse radars lose low flying targets in the
+/// ground clutter, doppler radars lose targets that don't move towards or away from them.
+pub fn effective_gain(radar_emitter: &RadarEmitter, height_above_ground: f32, radial_velocity: f32) -> f32 {
+    match radar_emitter.radar_type {
+        RadarEmitterType::PULSE => radar_emitter.radar_gain * clutter_factor(height_above_ground),
+        RadarEmitterType::DOPPLER => radar_emitter.radar_gain * doppler_filter(radial_velocity, radar_emitter.notch_width),
+    }
+}
+
+/// Noise jamming raises the noise floor. The echo gets stronger faster than the noise when
+/// closing in, so the radar burns through the jamming at short range.
+pub fn jamming_level(jammer: Option<&NoiseJammer>, distance: f32, max_range: f32) -> f32 {
+    jammer.filter(|j| j.active)
+        .map(|j| j.power * (distance / max_range).clamp(0.1, 1.0))
+        .unwrap_or(0.0)
+}
 
-   		// Radar returns rise with altitude until 1000 feet, remain strong until 8000 feet, then get weaker with rising altitude (but never below 0.4f)
-    	let low_altitude_curve = (detectable_transform.translation.y / 1000.0).clamp(0.0, 1.0);
-   		let high_altitude_curve = 1.0 - ((detectable_transform.translation.y-8000.0).clamp(0.0, 900000.0) / 20000.0).clamp(0.4, 1.0);
-	    let altitude_factor = low_altitude_curve * high_altitude_curve;
+/// Decide whether a return counts as a detection. Returns the signal the detection is made with.
+pub fn detection_outcome(radar_emitter: &RadarEmitter, distance: f32, signal: f32, jamming: f32) -> Option<f32> {
+    let max_range = radar_emitter.max_detect_range_km * 1000.0;
+    if distance <= max_range && signal >= radar_emitter.detection_threshold + jamming {
+        Some(signal)
+    } else if jamming > 0.0 && radar_emitter.home_on_jam {
+        // The jammer itself is a beacon
+        Some(jamming)
+    } else {
+        None
+    }
+}
 
-//		info!("RFactor: {} PFactor: {}", roll_factor, pitch_factor);
-        detectable.radar_cross_section = (detectable.base_radar_cross_section * altitude_factor) + (roll_factor * 0.4) + (pitch_factor * 0.4);
+/// Only remember the strongest impulse, unless it's older than the pulse timeout.
+/// This prevents weaker signals that arrive later from obscuring important strong returns.
+pub fn record_impulse(detectable: &mut RadarDetectable, signal: f32, milliseconds: u64) {
+    if signal > detectable.reflected_energy || milliseconds - detectable.last_impulse_time > RADAR_PULSE_TIMEOUT {
+        detectable.reflected_energy = signal;
+        detectable.last_impulse_time = milliseconds;
+    }
+}
 
+/// Scan one target from one emitter. Line of sight and coalition are checked by the caller.
+#[allow(clippy::too_many_arguments)]
+pub fn scan_target(
+    radar_emitter: &RadarEmitter,
+    emitter_position: Vec3,
+    emitter_velocity: Vec3,
+    detectable: &RadarDetectable,
+    rcs_table: Option<&RcsTable>,
+    target_transform: &Transform,
+    target_velocity: Vec3,
+    ground_height: f32,
+    jammer: Option<&NoiseJammer>,
+) -> ScanResult {
+    let distance = (target_transform.translation - emitter_position).length();
+    let max_range = radar_emitter.max_detect_range_km * 1000.0;
+    if distance > max_range * ILLUMINATION_RANGE_FACTOR {
+        return ScanResult { illuminated: false, signal: 0.0, detection: None };
+    }
+    let resonance_rcs = radar_emitter.band.resonance_rcs() * altitude_factor(target_transform.translation.y * 10.0);
+    let radar_cross_section = aspect_rcs(detectable, rcs_table, target_transform, emitter_position).max(resonance_rcs);
+    let radial_velocity = radial_velocity(emitter_position, emitter_velocity, target_transform.translation, target_velocity);
+    let gain = effective_gain(radar_emitter, target_transform.translation.y - ground_height, radial_velocity);
+    let signal = return_signal(gain, radar_cross_section, distance, max_range);
+    let jamming = jamming_level(jammer, distance, max_range);
+    ScanResult {
+        illuminated: true,
+        signal,
+        detection: detection_outcome(radar_emitter, distance, signal, jamming),
     }
 }
 
-#[allow(unused_assignments)]
 pub fn update_radar(
-    mut radars: Query<(&mut RadarEmitter, &Transform, &Coalition)>,
-    mut detectables: Query<(&mut RadarDetectable, &Transform, &Coalition)>,
+    mut radars: Query<(Entity, &mut RadarEmitter, &Transform, &Coalition, Option<&mut LineOfSightCache>, Option<&Velocity>)>,
+    mut detectables: Query<(Entity, &mut RadarDetectable, &Transform, &Coalition, Option<&NoiseJammer>, Option<&Velocity>)>,
+    mut radar_events: MessageWriter<RadarEvent>,
+    rcs_tables: Res<Assets<RcsTable>>,
+    terrain: Option<Res<TerrainData>>,
 ) {
-    for (mut radar_emitter, radar_transform, radar_coalition) in radars.iter_mut() {
+    for (radar_entity, mut radar_emitter, radar_transform, radar_coalition, mut los_cache, radar_velocity) in radars.iter_mut() {
         let milliseconds = get_time_millis();
-        
-        //Skip this radar if it's not time to scan yet
-        if milliseconds - radar_emitter.last_scan_time < (radar_emitter.scan_interval * 1000.0) as u64 {
+
+        // A radar that goes dark loses all its tracks
+        if !radar_emitter.emitting {
+            for track in radar_emitter.tracks.drain(..) {
+                radar_events.write(RadarEvent { emitter: radar_entity, target: track.target, transition: RadarTransition::TRACK_LOST });
+            }
+            radar_emitter.locked_target = None;
+            radar_emitter.mode = RadarMode::SEARCH;
+            continue;
+        }
+
+        //Skip this radar if it's not time to scan yet. Tracking radars update faster.
+        let interval = if radar_emitter.mode == RadarMode::SEARCH { radar_emitter.scan_interval } else { radar_emitter.track_interval };
+        if milliseconds - radar_emitter.last_scan_time < (interval * 1000.0) as u64 {
             continue;
         }
         radar_emitter.last_scan_time = milliseconds;
-        for (mut detectable, detectable_transform, detectable_coalition) in detectables.iter_mut() {
-            // Skip target if it's a friendly
-            if radar_coalition.side == detectable_coalition.side {
+        let radar_velocity = radar_velocity.map(|v| v.linvel).unwrap_or(Vec3::ZERO);
+        let mut detections: Vec<(Entity, Vec3, f32)> = Vec::new();
+        for (detectable_entity, mut detectable, detectable_transform, detectable_coalition, jammer, detectable_velocity) in detectables.iter_mut() {
+            if !is_hostile(radar_coalition, detectable_coalition) {
                 continue;
             }
 
-            
-		    // Calculate return signal strength based on signal strength, distance, own status, altitude and attitude
-            let target_distance: f32 = (detectable_transform.translation - radar_transform.translation).length();
-
-		    // Radar returns attenuate over distance
-		    let distance_factor = (target_distance.clamp(0.0, 900000.0) / (radar_emitter.max_detect_range_km*1000.0)).clamp(0.0, 1.0);
-
-		    let signal_strength_at_target = radar_emitter.radar_gain * distance_factor;
-            
-		    // if signal_strength + radar_cross_section > 1 then we are visible
-		    let raw_return_signal = signal_strength_at_target + detectable.radar_cross_section;
-            info!("raw return: {}", raw_return_signal);
-
-            // Now check our orientation relative to the radar emitter, 
-            // and attenuate the return signal depending on radar type and our orientation
-			let mut angular_difference = (detectable_transform.translation - radar_transform.translation).angle_between(detectable_transform.forward().as_vec3()).to_degrees();
-			if angular_difference > 90.0 {
-				angular_difference = (detectable_transform.translation - radar_transform.translation).angle_between(-detectable_transform.forward().as_vec3()).to_degrees();
-			}
-            angular_difference = 90.0 - angular_difference;
-            info!("angular difference: {}", angular_difference);
-			angular_difference = angular_difference / 90.0; //Make this range from 0.0 to 1.0
-			
-			let mut effective_gain = radar_emitter.radar_gain;
-
-            match radar_emitter.radar_type {
-                RadarEmitterType::PULSE => {
-    				//For pulse radar, flying straight towards it is advisable to not be seen
-				    effective_gain = radar_emitter.radar_gain * angular_difference;
-                }
-                RadarEmitterType::DOPPLER => {
-    				//Doppler radar can't see you if you fly perpendicular to it
-	    			effective_gain = radar_emitter.radar_gain * (1.0 - angular_difference);
-                }
+            // Terrain masking: no return at all if the target is behind a ridge or below the horizon
+            if !check_line_of_sight(los_cache.as_deref_mut(), terrain.as_deref(), detectable_entity,
+                radar_transform.translation, detectable_transform.translation) {
+                continue;
             }
-            info!("effective gain: {}", effective_gain);
 
-            let final_return_signal = raw_return_signal * effective_gain;
-            info!("Final return signal: {}", final_return_signal);
+            let ground_height = terrain.as_deref()
+                .map(|t| t.get_height_world(detectable_transform.translation.x, detectable_transform.translation.z))
+                .unwrap_or(0.0);
+            let scan = scan_target(&radar_emitter, radar_transform.translation, radar_velocity,
+                &detectable, rcs_tables.get(&detectable.rcs_table), detectable_transform,
+                detectable_velocity.map(|v| v.linvel).unwrap_or(Vec3::ZERO), ground_height, jammer);
+            if !scan.illuminated {
+                continue;
+            }
+            radar_emitter.illuminated.insert(detectable_entity, milliseconds);
+            record_impulse(&mut detectable, scan.signal, milliseconds);
+            if let Some(signal) = scan.detection {
+                detections.push((detectable_entity, detectable_transform.translation, signal));
+            }
+        }
+        resolve_detections(&mut radar_emitter, &mut detections);
+        radar_emitter.illuminated.retain(|_, time| milliseconds - *time < ILLUMINATION_MEMORY);
+        update_tracks(radar_entity, &mut radar_emitter, &detections, milliseconds, &mut radar_events);
+    }
+}
 
-            // Only remember the strongest impulse, unless it's older than 300ms
-            // This prevents weaker signals that arrive later from obscuring important strong returns
-            if (final_return_signal > detectable.reflected_energy) || (milliseconds - detectable.last_impulse_time > RADAR_PULSE_TIMEOUT) {
-                detectable.reflected_energy = final_return_signal;
-                detectable.last_impulse_time = milliseconds;
+/// Returns within one resolution cell merge into one, and the strongest of them captures
+/// any track that was held on the others. This is how chaff pulls a track off an aircraft.
+fn resolve_detections(
+    radar_emitter: &mut RadarEmitter,
+    detections: &mut Vec<(Entity, Vec3, f32)>,
+) {
+    detections.sort_by(|a, b| b.2.total_cmp(&a.2));
+    let mut resolved: Vec<(Entity, Vec3, f32)> = Vec::new();
+    for detection in detections.iter() {
+        let Some(stronger) = resolved.iter().find(|r| r.1.distance(detection.1) < radar_emitter.resolution_cell) else {
+            resolved.push(*detection);
+            continue;
+        };
+        let stronger_entity = stronger.0;
+        if radar_emitter.tracks.iter().any(|t| t.target == stronger_entity) {
+            continue;
+        }
+        if let Some(track) = radar_emitter.tracks.iter_mut().find(|t| t.target == detection.0) {
+            track.target = stronger_entity;
+            if radar_emitter.locked_target == Some(detection.0) {
+                radar_emitter.locked_target = Some(stronger_entity);
             }
+        }
+    }
+    *detections = resolved;
+}
+
+/// Run the track state machine of one emitter after a scan
+fn update_tracks(
+    radar_entity: Entity,
+    radar_emitter: &mut RadarEmitter,
+    detections: &[(Entity, Vec3, f32)],
+    milliseconds: u64,
+    radar_events: &mut MessageWriter<RadarEvent>,
+) {
+    let mut events: Vec<(Entity, RadarTransition)> = Vec::new();
 
-            //TODO: Tracking and targeting
+    // Detections build up track quality
+    for (target, position, signal) in detections.iter() {
+        match radar_emitter.tracks.iter_mut().find(|t| t.target == *target) {
+            Some(track) => {
+                track.quality = (track.quality + radar_emitter.track_quality_gain).min(1.0);
+                track.last_detection_time = milliseconds;
+                track.last_position = *position;
+                track.last_signal = *signal;
+                if (track.state == TrackState::DETECTED || track.state == TrackState::COASTING) && track.quality >= TRACK_ESTABLISHED_QUALITY {
+                    track.state = TrackState::TRACKING;
+                    events.push((*target, RadarTransition::TRACK_ESTABLISHED));
+                }
+            },
+            None => {
+                radar_emitter.tracks.push(RadarTrack {
+                    target: *target,
+                    state: TrackState::DETECTED,
+                    quality: radar_emitter.track_quality_gain,
+                    last_detection_time: milliseconds,
+                    last_position: *position,
+                    last_signal: *signal,
+                });
+                events.push((*target, RadarTransition::DETECTED));
+            },
+        }
+    }
 
+    // Tracks without a detection this scan coast, and are dropped once they time out
+    let coast_time = (radar_emitter.coast_time * 1000.0) as u64;
+    for track in radar_emitter.tracks.iter_mut() {
+        if track.last_detection_time == milliseconds {
+            continue;
+        }
+        track.quality = (track.quality - radar_emitter.track_quality_decay).max(0.0);
+        if track.state == TrackState::LOCKED {
+            events.push((track.target, RadarTransition::LOCK_BROKEN));
+        }
+        if track.state == TrackState::TRACKING || track.state == TrackState::LOCKED {
+            track.state = TrackState::COASTING;
+            events.push((track.target, RadarTransition::TRACK_COASTING));
+        }
+        if track.quality <= 0.0 || milliseconds - track.last_detection_time > coast_time {
+            events.push((track.target, RadarTransition::TRACK_LOST));
         }
     }
+    radar_emitter.tracks.retain(|t| t.quality > 0.0 && milliseconds - t.last_detection_time <= coast_time);
 
+    // Lock on to the best track, and clear for launch once the lock has been held long enough
+    if let Some(locked_target) = radar_emitter.locked_target {
+        if !radar_emitter.tracks.iter().any(|t| t.target == locked_target && t.state == TrackState::LOCKED) {
+            radar_emitter.locked_target = None;
+        }
+    }
+    if radar_emitter.locked_target.is_none() {
+        let lock_quality = radar_emitter.lock_quality;
+        if let Some(track) = radar_emitter.tracks.iter_mut()
+            .filter(|t| t.state == TrackState::TRACKING && t.quality >= lock_quality)
+            .max_by(|a, b| a.quality.total_cmp(&b.quality)) {
+            track.state = TrackState::LOCKED;
+            events.push((track.target, RadarTransition::LOCKED));
+            radar_emitter.locked_target = Some(track.target);
+            radar_emitter.lock_time = milliseconds;
+        }
+    }
+
+    let previous_mode = radar_emitter.mode;
+    radar_emitter.mode = if let Some(locked_target) = radar_emitter.locked_target {
+        if previous_mode == RadarMode::LAUNCH || milliseconds - radar_emitter.lock_time >= (radar_emitter.launch_delay * 1000.0) as u64 {
+            if previous_mode != RadarMode::LAUNCH {
+                events.push((locked_target, RadarTransition::LAUNCH));
+            }
+            RadarMode::LAUNCH
+        } else {
+            RadarMode::LOCK
+        }
+    } else if radar_emitter.tracks.iter().any(|t| t.state != TrackState::DETECTED) {
+        RadarMode::TRACK
+    } else {
+        RadarMode::SEARCH
+    };
+
+    for (target, transition) in events {
+        radar_events.write(RadarEvent { emitter: radar_entity, target, transition });
+    }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coalition::CoalitionType;
+
+    const SAM_RANGE_KM: f32 = 100.0;
+    /// Comfortably inside the band where altitude doesn't change the return
+    const CRUISE_HEIGHT: f32 = 300.0;
+
+    fn sam_radar(radar_type: RadarEmitterType) -> RadarEmitter {
+        RadarEmitter {
+            radar_type,
+            radar_gain: 10.0,
+            max_detect_range_km: SAM_RANGE_KM,
+            ..default()
+        }
+    }
+
+    fn target(rcs: f32) -> RadarDetectable {
+        RadarDetectable { base_radar_cross_section: rcs, ..default() }
+    }
+
+    /// Small nose-on, large broadside, like a faceted stealth airframe
+    fn stealth_table() -> RcsTable {
+        RcsTable {
+            azimuths: vec![0.0, 45.0, 90.0, 135.0, 180.0],
+            elevations: vec![-90.0, 0.0, 90.0],
+            values: vec![
+                vec![1.0, 1.0, 1.0, 1.0, 1.0],
+                vec![0.03, 0.04, 0.94, 0.05, 0.13],
+                vec![0.8, 0.8, 0.8, 0.8, 0.8],
+            ],
+        }
+    }
+
+    /// Target on the +X axis of a radar at the origin, with its nose turned by heading (radians)
+    fn target_at(distance: f32, height: f32, heading: f32) -> Transform {
+        Transform::from_xyz(distance, height, 0.0).with_rotation(Quat::from_rotation_y(heading))
+    }
+
+    fn scan(radar: &RadarEmitter, detectable: &RadarDetectable, table: Option<&RcsTable>, transform: &Transform, velocity: Vec3) -> ScanResult {
+        scan_target(radar, Vec3::ZERO, Vec3::ZERO, detectable, table, transform, velocity, 0.0, None)
+    }
+
+    /// Furthest distance at which the target is detected, in 500 m steps
+    fn detection_range(radar: &RadarEmitter, detectable: &RadarDetectable, table: Option<&RcsTable>, height: f32, heading: f32) -> f32 {
+        let mut range = 0.0;
+        let mut distance = 500.0;
+        while distance <= SAM_RANGE_KM * 1000.0 * ILLUMINATION_RANGE_FACTOR {
+            if scan(radar, detectable, table, &target_at(distance, height, heading), Vec3::ZERO).detection.is_some() {
+                range = distance;
+            }
+            distance += 500.0;
+        }
+        range
+    }
+
+    fn flat_terrain() -> TerrainData {
+        let size = 1024;
+        TerrainData {
+            heights: vec![0.0; size * size],
+            width: size,
+            depth: size,
+            origin_shift: Vec3::ZERO,
+            city_positions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn head_on_is_seen_later_than_beam() {
+        let radar = sam_radar(RadarEmitterType::PULSE);
+        let table = stealth_table();
+        let detectable = target(0.0);
+        // Nose towards the radar, and flying across its line of sight
+        let head_on = detection_range(&radar, &detectable, Some(&table), CRUISE_HEIGHT, std::f32::consts::PI);
+        let beam = detection_range(&radar, &detectable, Some(&table), CRUISE_HEIGHT, -std::f32::consts::FRAC_PI_2);
+        assert!((40_000.0..=45_000.0).contains(&head_on), "head-on detection range {}", head_on);
+        assert!((97_000.0..=100_000.0).contains(&beam), "beam detection range {}", beam);
+    }
+
+    #[test]
+    fn detection_range_follows_radar_equation() {
+        let radar = sam_radar(RadarEmitterType::PULSE);
+        // An RCS of 1 is detected out to maximum range, and never beyond it
+        assert_eq!(detection_range(&radar, &target(1.0), None, CRUISE_HEIGHT, 0.0), SAM_RANGE_KM * 1000.0);
+        assert_eq!(detection_range(&radar, &target(50.0), None, CRUISE_HEIGHT, 0.0), SAM_RANGE_KM * 1000.0);
+        // Range scales with the fourth root of RCS
+        let small = detection_range(&radar, &target(0.1), None, CRUISE_HEIGHT, 0.0);
+        assert!((55_000.0..=57_000.0).contains(&small), "detection range {}", small);
+    }
+
+    #[test]
+    fn signal_falls_off_with_distance() {
+        let radar = sam_radar(RadarEmitterType::PULSE);
+        let detectable = target(0.2);
+        let mut last_signal = f32::MAX;
+        for distance in (5..=150).step_by(5) {
+            let result = scan(&radar, &detectable, None, &target_at(distance as f32 * 1000.0, CRUISE_HEIGHT, 0.0), Vec3::ZERO);
+            assert!(result.signal < last_signal, "signal at {} km", distance);
+            last_signal = result.signal;
+        }
+    }
+
+    #[test]
+    fn illumination_reaches_beyond_detection_range() {
+        let radar = sam_radar(RadarEmitterType::PULSE);
+        let detectable = target(1.0);
+        let beyond_range = scan(&radar, &detectable, None, &target_at(120_000.0, CRUISE_HEIGHT, 0.0), Vec3::ZERO);
+        assert!(beyond_range.illuminated);
+        assert_eq!(beyond_range.detection, None);
+        let out_of_reach = scan(&radar, &detectable, None, &target_at(160_000.0, CRUISE_HEIGHT, 0.0), Vec3::ZERO);
+        assert!(!out_of_reach.illuminated);
+    }
+
+    #[test]
+    fn long_wavelengths_see_stealth_further() {
+        let table = stealth_table();
+        let stealth = target(0.0);
+        let s_band = sam_radar(RadarEmitterType::PULSE);
+        let vhf = RadarEmitter { band: RadarBand::VHF, ..sam_radar(RadarEmitterType::PULSE) };
+        let head_on = std::f32::consts::PI;
+        let s_band_range = detection_range(&s_band, &stealth, Some(&table), CRUISE_HEIGHT, head_on);
+        let vhf_range = detection_range(&vhf, &stealth, Some(&table), CRUISE_HEIGHT, head_on);
+        assert!(vhf_range > s_band_range * 1.5, "VHF {} vs S band {}", vhf_range, s_band_range);
+        // Conventional airframes are no easier to see
+        assert_eq!(detection_range(&vhf, &target(0.8), None, CRUISE_HEIGHT, 0.0),
+            detection_range(&s_band, &target(0.8), None, CRUISE_HEIGHT, 0.0));
+    }
+
+    #[test]
+    fn altitude_bands() {
+        assert_eq!(altitude_factor(0.0), 0.0);
+        assert_eq!(altitude_factor(500.0), 0.5);
+        assert_eq!(altitude_factor(1000.0), 1.0);
+        assert_eq!(altitude_factor(8000.0), 1.0);
+        assert_eq!(altitude_factor(18000.0), 0.5);
+        assert_eq!(altitude_factor(40000.0), 0.4);
+
+        // Low flying targets are lost in the clutter by a pulse radar
+        let radar = sam_radar(RadarEmitterType::PULSE);
+        let detectable = target(0.2);
+        let high = detection_range(&radar, &detectable, None, CRUISE_HEIGHT, 0.0);
+        let low = detection_range(&radar, &detectable, None, 20.0, 0.0);
+        assert!(low < high * 0.6, "low level {} vs cruise {}", low, high);
+    }
+
+    #[test]
+    fn doppler_notch() {
+        let radar = sam_radar(RadarEmitterType::DOPPLER);
+        let detectable = target(1.0);
+        let position = target_at(30_000.0, CRUISE_HEIGHT, 0.0);
+        // Closing, beaming and hovering targets
+        assert!(scan(&radar, &detectable, None, &position, Vec3::new(-50.0, 0.0, 0.0)).detection.is_some());
+        assert!(scan(&radar, &detectable, None, &position, Vec3::new(0.0, 0.0, 50.0)).detection.is_none());
+        assert!(scan(&radar, &detectable, None, &position, Vec3::ZERO).detection.is_none());
+        // Just outside the notch the target fades back in
+        assert_eq!(doppler_filter(radar.notch_width, radar.notch_width), 0.0);
+        assert_eq!(doppler_filter(radar.notch_width * (1.0 + NOTCH_EDGE), radar.notch_width), 1.0);
+        // Pulse radars don't care about radial velocity
+        let pulse = sam_radar(RadarEmitterType::PULSE);
+        assert!(scan(&pulse, &detectable, None, &position, Vec3::new(0.0, 0.0, 50.0)).detection.is_some());
+    }
+
+    #[test]
+    fn noise_jamming_and_burn_through() {
+        let mut radar = sam_radar(RadarEmitterType::PULSE);
+        radar.home_on_jam = false;
+        let detectable = target(1.0);
+        let jammer = NoiseJammer { active: true, ..default() };
+        let far = target_at(60_000.0, CRUISE_HEIGHT, 0.0);
+        let near = target_at(20_000.0, CRUISE_HEIGHT, 0.0);
+        let jammed = |transform: &Transform, radar: &RadarEmitter| {
+            scan_target(radar, Vec3::ZERO, Vec3::ZERO, &detectable, None, transform, Vec3::ZERO, 0.0, Some(&jammer)).detection
+        };
+        assert!(scan(&radar, &detectable, None, &far, Vec3::ZERO).detection.is_some());
+        assert!(jammed(&far, &radar).is_none());
+        assert!(jammed(&near, &radar).is_some());
+        // Home-on-jam radars see the jammer anyway
+        radar.home_on_jam = true;
+        assert!(jammed(&far, &radar).is_some());
+    }
+
+    #[test]
+    fn coalition_filtering() {
+        let red = Coalition { side: CoalitionType::RED };
+        let blue = Coalition { side: CoalitionType::BLUE };
+        assert!(is_hostile(&red, &blue));
+        assert!(is_hostile(&blue, &red));
+        assert!(!is_hostile(&red, &Coalition { side: CoalitionType::RED }));
+    }
+
+    #[test]
+    fn pulse_timeout() {
+        let mut detectable = target(1.0);
+        record_impulse(&mut detectable, 5.0, 1000);
+        // A weaker pulse arriving shortly after doesn't hide the strong one
+        record_impulse(&mut detectable, 1.0, 1000 + RADAR_PULSE_TIMEOUT / 2);
+        assert_eq!(detectable.reflected_energy, 5.0);
+        assert_eq!(detectable.last_impulse_time, 1000);
+        // A stronger one always replaces it
+        record_impulse(&mut detectable, 6.0, 1100);
+        assert_eq!(detectable.reflected_energy, 6.0);
+        // After the timeout, any pulse replaces it
+        record_impulse(&mut detectable, 0.5, 1100 + RADAR_PULSE_TIMEOUT + 1);
+        assert_eq!(detectable.reflected_energy, 0.5);
+    }
+
+    #[test]
+    fn terrain_masking() {
+        let mut terrain = flat_terrain();
+        let radar_position = Vec3::new(0.0, 5.0, 0.0);
+        let low_target = Vec3::new(20_000.0, 30.0, 0.0);
+        let high_target = Vec3::new(20_000.0, 900.0, 0.0);
+        assert!(terrain.line_of_sight(radar_position, low_target));
+
+        // Raise a 100 m ridge across the path, halfway to the target
+        let cell_size = 100_000.0 / terrain.width as f32;
+        let ridge = ((10_000.0 + 50_000.0) / cell_size) as usize;
+        for gz in 0..terrain.depth {
+            for gx in ridge - 2..=ridge + 2 {
+                terrain.heights[gz * terrain.width + gx] = 100.0;
+            }
+        }
+        assert!(!terrain.line_of_sight(radar_position, low_target));
+        assert!(terrain.line_of_sight(radar_position, high_target));
+    }
+
+    #[test]
+    fn earth_curvature_hides_low_targets() {
+        let terrain = flat_terrain();
+        let radar_position = Vec3::new(-45_000.0, 5.0, 0.0);
+        // Over 90 km, a target needs a few hundred metres of height to clear the horizon
+        assert!(!terrain.line_of_sight(radar_position, Vec3::new(45_000.0, 20.0, 0.0)));
+        assert!(terrain.line_of_sight(radar_position, Vec3::new(45_000.0, 600.0, 0.0)));
+    }
+}
@@ -0,0 +1,235 @@
+use bevy::prelude::*;
+
+/* Road network. The roads built by the terrain generator are kept as a graph, with nodes at the
+   cities, the airbase, the bridges and wherever two roads cross, and edges along the roads between them.
+   Vehicles path-find on it, and anything else that needs to know where the roads go can look it
+   up instead of guessing from the terrain. Positions are original terrain coordinates. */
+
+/// Crossings this close to a node are taken to be that node
+const JUNCTION_MERGE_DISTANCE: f32 = 100.0;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RoadNodeType {
+    CITY(usize), // Index of the city in TerrainData::city_positions
+    AIRBASE,
+    JUNCTION,
+    BRIDGE,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoadNode {
+    pub position: Vec2,
+    pub node_type: RoadNodeType,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoadEdge {
+    pub from: usize,
+    pub to: usize,
+    pub length: f32,
+    pub waypoints: Vec<Vec2>, // From the position of the first node to the position of the second
+}
+
+#[derive(Resource, Default)]
+pub struct RoadNetwork {
+    pub nodes: Vec<RoadNode>,
+    pub edges: Vec<RoadEdge>,
+}
+
+/// A road as generated, with the nodes it starts and ends at
+pub struct RoadPolyline {
+    pub waypoints: Vec<Vec2>,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Crossing point of two segments, with the fraction along each of them
+fn segment_intersection(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> Option<(f32, f32)> {
+    let da = a1 - a0;
+    let db = b1 - b0;
+    let det = da.perp_dot(db);
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let t = (b0 - a0).perp_dot(db) / det;
+    let u = (b0 - a0).perp_dot(da) / det;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some((t, u))
+}
+
+fn polyline_length(waypoints: &[Vec2]) -> f32 {
+    waypoints.windows(2).map(|s| (s[1] - s[0]).length()).sum()
+}
+
+impl RoadNetwork {
+    /// Build the graph from the generated roads. Roads that cross are split at a junction node.
+    pub fn new(mut nodes: Vec<RoadNode>, roads: &[RoadPolyline]) -> RoadNetwork {
+        // Every road gets a list of cuts: (segment index, fraction along it, node)
+        let mut cuts: Vec<Vec<(usize, f32, usize)>> = roads.iter()
+            .map(|r| vec![(0, 0.0, r.from), (r.waypoints.len() - 2, 1.0, r.to)])
+            .collect();
+        // Bridges sit on the road they carry, which is cut there
+        for (node_index, node) in nodes.iter().enumerate().filter(|(_, n)| n.node_type == RoadNodeType::BRIDGE) {
+            for (road, road_cuts) in roads.iter().zip(cuts.iter_mut()) {
+                let on_road = road.waypoints.windows(2).enumerate().find_map(|(si, s)| {
+                    let length_squared = (s[1] - s[0]).length_squared();
+                    if length_squared == 0.0 {
+                        return None;
+                    }
+                    let t = ((node.position - s[0]).dot(s[1] - s[0]) / length_squared).clamp(0.0, 1.0);
+                    (s[0].lerp(s[1], t).distance(node.position) < JUNCTION_MERGE_DISTANCE).then_some((si, t))
+                });
+                if let Some((si, t)) = on_road {
+                    road_cuts.push((si, t, node_index));
+                }
+            }
+        }
+        for i in 0..roads.len() {
+            for j in i + 1..roads.len() {
+                for (si, a) in roads[i].waypoints.windows(2).enumerate() {
+                    for (sj, b) in roads[j].waypoints.windows(2).enumerate() {
+                        let Some((t, u)) = segment_intersection(a[0], a[1], b[0], b[1]) else { continue };
+                        let point = a[0].lerp(a[1], t);
+                        let node = match nodes.iter().position(|n| n.position.distance(point) < JUNCTION_MERGE_DISTANCE) {
+                            Some(node) => node,
+                            None => {
+                                nodes.push(RoadNode { position: point, node_type: RoadNodeType::JUNCTION });
+                                nodes.len() - 1
+                            },
+                        };
+                        if !cuts[i].iter().any(|c| c.2 == node) {
+                            cuts[i].push((si, t, node));
+                        }
+                        if !cuts[j].iter().any(|c| c.2 == node) {
+                            cuts[j].push((sj, u, node));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut edges = Vec::new();
+        for (road, mut road_cuts) in roads.iter().zip(cuts) {
+            road_cuts.sort_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap());
+            for pair in road_cuts.windows(2) {
+                let ((s0, _, from), (s1, _, to)) = (pair[0], pair[1]);
+                if from == to {
+                    continue;
+                }
+                // The waypoints between the two cuts, with the ends moved onto the nodes
+                let mut waypoints = vec![nodes[from].position];
+                waypoints.extend(road.waypoints[s0 + 1..=s1].iter().copied());
+                waypoints.push(nodes[to].position);
+                waypoints.dedup();
+                edges.push(RoadEdge { from, to, length: polyline_length(&waypoints), waypoints });
+            }
+        }
+        RoadNetwork { nodes, edges }
+    }
+
+    pub fn city_node(&self, city: usize) -> Option<usize> {
+        self.nodes.iter().position(|n| n.node_type == RoadNodeType::CITY(city))
+    }
+
+    /// Edges of the shortest path between two nodes, in driving order (Dijkstra)
+    pub fn shortest_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let n = self.nodes.len();
+        let mut dist = vec![f32::MAX; n];
+        let mut via: Vec<Option<usize>> = vec![None; n];
+        let mut done = vec![false; n];
+        dist[from] = 0.0;
+        while let Some(node) = (0..n).filter(|i| !done[*i] && dist[*i] < f32::MAX).min_by(|a, b| dist[*a].total_cmp(&dist[*b])) {
+            if node == to {
+                break;
+            }
+            done[node] = true;
+            for (i, edge) in self.edges.iter().enumerate() {
+                let other = if edge.from == node { edge.to } else if edge.to == node { edge.from } else { continue };
+                if dist[node] + edge.length < dist[other] {
+                    dist[other] = dist[node] + edge.length;
+                    via[other] = Some(i);
+                }
+            }
+        }
+        let mut path = Vec::new();
+        let mut node = to;
+        while node != from {
+            let edge = via[node]?;
+            path.push(edge);
+            node = if self.edges[edge].to == node { self.edges[edge].from } else { self.edges[edge].to };
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// The shortest path between two nodes as one polyline to drive along
+    pub fn route(&self, from: usize, to: usize) -> Option<Vec<Vec2>> {
+        let mut route = vec![self.nodes[from].position];
+        let mut node = from;
+        for edge in self.shortest_path(from, to)?.into_iter().map(|e| &self.edges[e]) {
+            if edge.from == node {
+                route.extend(edge.waypoints.iter().skip(1));
+                node = edge.to;
+            } else {
+                route.extend(edge.waypoints.iter().rev().skip(1));
+                node = edge.from;
+            }
+        }
+        (route.len() > 1).then_some(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(x: f32, z: f32, index: usize) -> RoadNode {
+        RoadNode { position: Vec2::new(x, z), node_type: RoadNodeType::CITY(index) }
+    }
+
+    fn straight(from_node: &RoadNode, to_node: &RoadNode, from: usize, to: usize) -> RoadPolyline {
+        let waypoints = (0..=10).map(|i| from_node.position.lerp(to_node.position, i as f32 / 10.0)).collect();
+        RoadPolyline { waypoints, from, to }
+    }
+
+    #[test]
+    fn crossing_roads_meet_at_a_junction() {
+        let nodes = vec![city(-1000.0, 0.0, 0), city(1000.0, 0.0, 1), city(0.0, -1000.0, 2), city(0.0, 1000.0, 3)];
+        let roads = [straight(&nodes[0], &nodes[1], 0, 1), straight(&nodes[2], &nodes[3], 2, 3)];
+        let network = RoadNetwork::new(nodes, &roads);
+
+        assert_eq!(network.nodes.len(), 5);
+        assert_eq!(network.nodes[4].node_type, RoadNodeType::JUNCTION);
+        assert!(network.nodes[4].position.length() < 1.0);
+        assert_eq!(network.edges.len(), 4);
+
+        // West to north turns at the junction instead of going through another city
+        let path = network.shortest_path(0, 3).unwrap();
+        assert_eq!(path.len(), 2);
+        let route = network.route(0, 3).unwrap();
+        assert!((polyline_length(&route) - 2000.0).abs() < 1.0);
+        assert_eq!(*route.last().unwrap(), Vec2::new(0.0, 1000.0));
+    }
+
+    #[test]
+    fn bridges_split_the_road_they_carry() {
+        let mut nodes = vec![city(0.0, 0.0, 0), city(1000.0, 0.0, 1)];
+        nodes.push(RoadNode { position: Vec2::new(400.0, 20.0), node_type: RoadNodeType::BRIDGE });
+        let roads = [straight(&nodes[0], &nodes[1], 0, 1)];
+        let network = RoadNetwork::new(nodes, &roads);
+
+        assert_eq!(network.edges.len(), 2);
+        assert_eq!(network.shortest_path(0, 2).unwrap().len(), 1);
+        assert_eq!(network.shortest_path(0, 1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn no_path_between_separate_networks() {
+        let nodes = vec![city(0.0, 0.0, 0), city(1000.0, 0.0, 1), city(0.0, 5000.0, 2), city(1000.0, 5000.0, 3)];
+        let roads = [straight(&nodes[0], &nodes[1], 0, 1), straight(&nodes[2], &nodes[3], 2, 3)];
+        let network = RoadNetwork::new(nodes, &roads);
+
+        assert!(network.shortest_path(0, 3).is_none());
+        assert_eq!(network.route(1, 0).unwrap().first(), Some(&Vec2::new(1000.0, 0.0)));
+    }
+}
@@ -1,6 +1,21 @@
 use bevy::{prelude::*, camera::visibility::RenderLayers};
 
-use crate::{definitions::{COLOR_ORANGE_RED, COLOR_YELLOW, RADAR_PULSE_TIMEOUT, RENDERLAYER_COCKPIT}, player::Player, radar::RadarDetectable, util::get_time_millis};
+use crate::{definitions::{COLOR_GREEN, COLOR_ORANGE_RED, COLOR_YELLOW, RADAR_PULSE_TIMEOUT, RENDERLAYER_COCKPIT}, player::Player, util::get_time_millis};
+use crate::{coalition::Coalition, radar::{RadarDetectable, RadarEmitter, RadarMode, TrackState}};
+
+/// RWR scope position and size on the HUD
+const RWR_HUD_X: f32 = 0.0;
+const RWR_HUD_Y: f32 = -330.0;
+const RWR_RADIUS: f32 = 110.0;
+/// Ring radii, as a fraction of the scope radius. The more lethal a threat, the closer to the centre.
+const RWR_RING_LAUNCH: f32 = 0.3;
+const RWR_RING_TRACK: f32 = 0.6;
+const RWR_RING_SEARCH: f32 = 0.9;
+/// Only the highest priority threats are shown
+const RWR_MAX_SYMBOLS: usize = 8;
+/// New threats flash for this long (ms)
+const RWR_NEW_THREAT_FLASH_TIME: u64 = 2000;
+const RWR_FLASH_INTERVAL: u64 = 250;
 
 #[derive(Component)]
 pub struct RwrRcsBar;
@@ -8,11 +23,87 @@ pub struct RwrRcsBar;
 #[derive(Component)]
 pub struct RwrReturnEnergyBar;
 
+/// What a hostile emitter is doing to us, in increasing order of danger
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RwrThreatState {
+    SEARCH,
+    TRACK,
+    LOCK,
+    LAUNCH,
+}
+
+/// One threat symbol on the RWR scope
+#[derive(Component)]
+pub struct RwrSymbol {
+    pub emitter: Entity,
+    pub state: RwrThreatState,
+    pub first_seen: u64,
+}
+
+/// Diamond around the highest priority threat
+#[derive(Component)]
+pub struct RwrPriorityMarker;
+
+#[derive(Resource)]
+pub struct RwrAssets {
+    font: Handle<Font>,
+    sound_search_new: Handle<AudioSource>,
+    sound_threat_new: Handle<AudioSource>,
+    sound_lock_new: Handle<AudioSource>,
+    sound_launch: Handle<AudioSource>,
+}
+
+struct RwrContact {
+    emitter: Entity,
+    code: String,
+    state: RwrThreatState,
+    bearing: f32, // Radians relative to the nose, positive to starboard
+    priority: f32,
+}
+
 pub fn setup_rwr(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
 ) {
+    commands.insert_resource(RwrAssets {
+        font: asset_server.load("fonts/Brickshapers-eXPx.ttf"),
+        sound_search_new: asset_server.load("sounds/rwr/SearchNewUS.ogg"),
+        sound_threat_new: asset_server.load("sounds/rwr/ThreatNewUS.ogg"),
+        sound_lock_new: asset_server.load("sounds/rwr/LockNewUS.ogg"),
+        sound_launch: asset_server.load("sounds/rwr/LaunchWarningUS.wav"),
+    });
+
+    // Scope rings and the aircraft symbol in the middle
+    let scope_material = materials.add(ColorMaterial::from(Color::srgba(0.0, 0.6, 0.0, 0.8)));
+    for ring in [RWR_RING_LAUNCH, RWR_RING_TRACK, 1.0] {
+        let radius = RWR_RADIUS * ring;
+        commands.spawn((
+            Mesh2d(meshes.add(Annulus::new(radius - 1.0, radius + 1.0))),
+            MeshMaterial2d(scope_material.clone()),
+            Transform::from_translation(Vec3::new(RWR_HUD_X, RWR_HUD_Y, 0.0)),
+        ))
+        .insert(RenderLayers::layer(RENDERLAYER_COCKPIT));
+    }
+    for (w, h) in [(12.0, 2.0), (2.0, 12.0)] {
+        commands.spawn((
+            Mesh2d(meshes.add(Rectangle::new(w, h))),
+            MeshMaterial2d(scope_material.clone()),
+            Transform::from_translation(Vec3::new(RWR_HUD_X, RWR_HUD_Y, 0.0)),
+        ))
+        .insert(RenderLayers::layer(RENDERLAYER_COCKPIT));
+    }
+
+    commands.spawn((
+        Mesh2d(meshes.add(Annulus::new(15.0, 17.0).mesh().resolution(4))),
+        MeshMaterial2d(materials.add(ColorMaterial::from(COLOR_YELLOW))),
+        Transform::from_translation(Vec3::new(RWR_HUD_X, RWR_HUD_Y, 0.5)),
+        Visibility::Hidden,
+    ))
+    .insert(RwrPriorityMarker)
+    .insert(RenderLayers::layer(RENDERLAYER_COCKPIT));
+
     let rcr_bar = Rectangle::new(600.0, 20.);
     commands.spawn((
         Mesh2d(meshes.add(rcr_bar)),
@@ -59,3 +150,131 @@ pub fn update_rwr(
 
 
 }
+
+fn rwr_threat_state(emitter: &RadarEmitter, target: Entity) -> RwrThreatState {
+    if emitter.locked_target == Some(target) {
+        if emitter.mode == RadarMode::LAUNCH { RwrThreatState::LAUNCH } else { RwrThreatState::LOCK }
+    } else {
+        match emitter.tracks.iter().find(|t| t.target == target).map(|t| t.state) {
+            Some(TrackState::TRACKING) | Some(TrackState::COASTING) | Some(TrackState::LOCKED) => RwrThreatState::TRACK,
+            _ => RwrThreatState::SEARCH,
+        }
+    }
+}
+
+fn rwr_threat_color(state: RwrThreatState) -> Color {
+    match state {
+        RwrThreatState::SEARCH => COLOR_GREEN,
+        RwrThreatState::TRACK => COLOR_YELLOW,
+        RwrThreatState::LOCK | RwrThreatState::LAUNCH => Color::srgb(1.0, 0.2, 0.1),
+    }
+}
+
+/// Position of a threat symbol on the scope: bearing from the nose, ring by lethality
+fn rwr_symbol_position(contact: &RwrContact) -> Vec3 {
+    let ring = match contact.state {
+        RwrThreatState::SEARCH => RWR_RING_SEARCH,
+        RwrThreatState::TRACK => RWR_RING_TRACK,
+        RwrThreatState::LOCK | RwrThreatState::LAUNCH => RWR_RING_LAUNCH,
+    };
+    let radius = RWR_RADIUS * ring;
+    Vec3::new(RWR_HUD_X + radius * contact.bearing.sin(), RWR_HUD_Y + radius * contact.bearing.cos(), 1.0)
+}
+
+/// Build the threat picture from the hostile emitters that are painting the player
+pub fn update_rwr_scope(
+    mut commands: Commands,
+    rwr_assets: Res<RwrAssets>,
+    players: Query<(Entity, &Transform, &Coalition), With<Player>>,
+    emitters: Query<(Entity, &RadarEmitter, &Transform, &Coalition), Without<Player>>,
+    mut symbols: Query<(Entity, &mut RwrSymbol, &mut Text2d, &mut TextColor, &mut Transform, &mut Visibility), (Without<Player>, Without<RadarEmitter>, Without<RwrPriorityMarker>)>,
+    mut priority_markers: Query<(&mut Transform, &mut Visibility), (With<RwrPriorityMarker>, Without<Player>, Without<RadarEmitter>, Without<RwrSymbol>)>,
+) {
+    let Ok((player, player_transform, player_coalition)) = players.single() else { return };
+    let milliseconds = get_time_millis();
+    let nose = player_transform.rotation * Vec3::X;
+    let nose_heading = nose.z.atan2(nose.x);
+
+    let mut contacts: Vec<RwrContact> = Vec::new();
+    for (emitter_entity, emitter, emitter_transform, emitter_coalition) in emitters.iter() {
+        if emitter_coalition.side == player_coalition.side {
+            continue;
+        }
+        let Some(last_painted) = emitter.illuminated.get(&player) else { continue };
+        // A contact is held until the radar's next sweep should have painted us again
+        if milliseconds - *last_painted > (emitter.scan_interval * 1000.0) as u64 + 1000 {
+            continue;
+        }
+        let to_emitter = emitter_transform.translation - player_transform.translation;
+        let bearing = to_emitter.z.atan2(to_emitter.x) - nose_heading;
+        let state = rwr_threat_state(emitter, player);
+        let range_factor = 1.0 - (to_emitter.length() / (emitter.max_detect_range_km * 1000.0)).clamp(0.0, 1.0);
+        contacts.push(RwrContact {
+            emitter: emitter_entity,
+            code: emitter.rwr_code.clone(),
+            state,
+            bearing,
+            priority: state as u32 as f32 + range_factor,
+        });
+    }
+    contacts.sort_by(|a, b| b.priority.total_cmp(&a.priority));
+    contacts.truncate(RWR_MAX_SYMBOLS);
+
+    // Update or remove existing symbols, and warn when a threat gets more dangerous
+    let mut escalation: Option<RwrThreatState> = None;
+    for (symbol_entity, mut symbol, mut text, mut color, mut transform, mut visibility) in symbols.iter_mut() {
+        let Some(contact) = contacts.iter().find(|c| c.emitter == symbol.emitter) else {
+            commands.entity(symbol_entity).despawn();
+            continue;
+        };
+        if contact.state > symbol.state {
+            escalation = escalation.max(Some(contact.state));
+        }
+        symbol.state = contact.state;
+        text.0 = contact.code.clone();
+        color.0 = rwr_threat_color(contact.state);
+        transform.translation = rwr_symbol_position(contact);
+        let flashing = contact.state == RwrThreatState::LAUNCH || milliseconds - symbol.first_seen < RWR_NEW_THREAT_FLASH_TIME;
+        *visibility = if flashing && (milliseconds / RWR_FLASH_INTERVAL) % 2 == 1 { Visibility::Hidden } else { Visibility::Visible };
+    }
+
+    // New threats
+    for contact in contacts.iter() {
+        if symbols.iter().any(|(_, symbol, ..)| symbol.emitter == contact.emitter) {
+            continue;
+        }
+        escalation = escalation.max(Some(contact.state));
+        commands.spawn((
+            Text2d::new(contact.code.clone()),
+            TextFont {
+                font: rwr_assets.font.clone(),
+                font_size: 22.0,
+                ..default()
+            },
+            TextColor(rwr_threat_color(contact.state)),
+            Transform::from_translation(rwr_symbol_position(contact)),
+            RwrSymbol { emitter: contact.emitter, state: contact.state, first_seen: milliseconds },
+        ))
+        .insert(RenderLayers::layer(RENDERLAYER_COCKPIT));
+    }
+
+    if let Some(state) = escalation {
+        let sound = match state {
+            RwrThreatState::SEARCH => rwr_assets.sound_search_new.clone(),
+            RwrThreatState::TRACK => rwr_assets.sound_threat_new.clone(),
+            RwrThreatState::LOCK => rwr_assets.sound_lock_new.clone(),
+            RwrThreatState::LAUNCH => rwr_assets.sound_launch.clone(),
+        };
+        commands.spawn((AudioPlayer::new(sound), PlaybackSettings::DESPAWN));
+    }
+
+    for (mut transform, mut visibility) in priority_markers.iter_mut() {
+        match contacts.first() {
+            Some(contact) => {
+                transform.translation = rwr_symbol_position(contact) - Vec3::new(0.0, 0.0, 0.5);
+                *visibility = Visibility::Visible;
+            },
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
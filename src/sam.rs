@@ -1,44 +1,349 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, camera::visibility::RenderLayers};
 use bevy_rapier3d::prelude::*;
+use ::serde::Deserialize;
 
+use crate::aaa::FlakGun;
 use crate::coalition::{CoalitionType, Coalition};
 use crate::definitions::*;
+use crate::explosion::{Volatile, VolatileCargo};
+use crate::emcon::ElintReceiver;
+use crate::emitter_tactics::DecoyEmitter;
+use crate::health::Health;
+use crate::infrared::IrSeeker;
+use crate::iads::IadsSamSite;
+use crate::line_of_sight::LineOfSightCache;
+use crate::map_mfd::MapMarker;
+use crate::missile::{Missile, SeekerState};
 use crate::radar::*;
 use crate::targeting::Targetable;
+use crate::terrain::TerrainData;
+use crate::threats::{threat_data, MissileData, MissileGuidance};
+use crate::util::{get_time_millis, random_u64, random_vec3};
 use crate::vehicle::*;
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+/* A SAM battery is a group of vehicles working together: an acquisition radar searches for
+   targets and hands them to the fire-control radar, which tracks and locks them. Once the lock
+   has been held long enough for LAUNCH mode and the target is inside the engagement envelope,
+   one of the launchers fires. Loaders carry the spare missiles. Every vehicle can be destroyed
+   on its own, and without its fire-control radar the battery can't engage anything.
+   Self-contained systems carry radar and weapons on one vehicle. What each system is made of
+   comes from the threat catalogue.
+   Radar guided missiles only have guidance as long as the battery keeps its lock, heat seekers
+   are on their own once launched. */
+
+/// Extra climb added to the launch direction, so the missile clears the ground before turning
+const LAUNCH_ELEVATION: f32 = 0.5;
+/// Distance of the acquisition radar from the centre of the site
+const ACQUISITION_RADAR_OFFSET: f32 = 12.0;
+/// Distance of the loaders from the centre of the site
+const LOADER_OFFSET: f32 = 18.0;
+/// Size of the site symbols on the map
+const MAP_SYMBOL_SIZE: f32 = 12.0;
+/// Size of the threat code printed next to a site symbol
+const MAP_LABEL_SIZE: f32 = 10.0;
+/// Steering error of command guidance per unit of distance between radar and target
+const COMMAND_GUIDANCE_ERROR: f32 = 0.0001;
+
+/// Air defence systems in the threat catalogue, guns included
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
 pub enum SAMType {
-    SA6
+    SA2,
+    SA3,
+    SA6,
+    SA8,
+    SA13,
+    SA15,
+    PGZ95,
+}
+
+/// Where and when a battery can engage a target
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct EngagementEnvelope {
+    pub min_range: f32,
+    pub max_range: f32,
+    pub min_altitude: f32, // Feet above ground
+    pub max_altitude: f32, // Feet above ground
+}
+
+impl EngagementEnvelope {
+    pub fn contains(&self, range: f32, altitude_feet: f32) -> bool {
+        range >= self.min_range && range <= self.max_range
+            && altitude_feet >= self.min_altitude && altitude_feet <= self.max_altitude
+    }
 }
 
+/// The battery as a whole, referencing the vehicles it is made of
 #[derive(Component)]
-pub struct SAM {
+pub struct SamBattery {
     pub name: String,
     pub sam_type: SAMType,
-    pub health: f32,
+    pub acquisition_radar: Entity, // The fire-control radar itself on self-contained systems
+    pub fire_control_radar: Entity,
+    pub launchers: Vec<Entity>,
+    pub loaders: Vec<Entity>,
+    pub envelope: EngagementEnvelope,
+    pub salvo_size: u32, // Missiles guided at the same target at once
+    pub salvo_interval: u64, // Milliseconds between two launches
+    pub last_launch_time: u64,
+}
+
+/// Map symbol of a battery or decoy, removed along with it
+#[derive(Component)]
+pub struct SamMapSymbol {
+    pub site: Entity,
+}
+
+/// Missiles on one launcher vehicle
+#[derive(Component)]
+pub struct SamLauncher {
+    pub missiles: u32, // Ready to fire
+    pub capacity: u32, // Missiles on the rails when fully loaded
+    pub reload_time: u64, // Milliseconds to load one missile from a loader
+    pub reload_start_time: Option<u64>,
+}
+
+impl SamLauncher {
+    pub fn for_sam_type(sam_type: &SAMType) -> SamLauncher {
+        let threat = threat_data(sam_type);
+        SamLauncher {
+            missiles: threat.missiles_per_launcher,
+            capacity: threat.missiles_per_launcher,
+            reload_time: (threat.reload_time * 1000.0) as u64,
+            reload_start_time: None,
+        }
+    }
+}
+
+/// Spare missiles carried by a reload vehicle
+#[derive(Component)]
+pub struct SamLoader {
+    pub missiles: u32,
 }
 
-impl Default for SAM {
-    fn default() -> Self {
-        SAM {
-            name: String::from("Default SAM"),
-            sam_type: SAMType::SA6,
-            health: 100.0,
+/// A missile guided by a ground radar, which has to keep its lock until impact
+#[derive(Component)]
+pub struct RadarGuidance {
+    pub illuminator: Entity,
+    pub guidance: MissileGuidance,
+    pub aim_error: Vec3, // Direction of the command guidance error, fixed for the flight
+}
+
+/// Lay out a battery around the site position. Vehicles are put on the ground if the terrain is known.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_sam_battery(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    terrain: Option<&TerrainData>,
+    sam_type: SAMType,
+    name: &str,
+    position: Vec3,
+    side: CoalitionType,
+) -> Entity {
+    let threat = threat_data(&sam_type);
+    let radar_mesh = meshes.add(Cuboid::new(0.6, 0.6, 0.9));
+    let loader_mesh = meshes.add(Cuboid::new(0.5, 0.5, 1.0));
+    let vehicle_material = materials.add(Color::srgb(0.35, 0.38, 0.28));
+    let ground = |offset: Vec2| -> Vec3 {
+        let (x, z) = (position.x + offset.x, position.z + offset.y);
+        Vec3::new(x, terrain.map(|t| t.get_height_world(x, z)).unwrap_or(position.y), z)
+    };
+    let spawn_launcher = |commands: &mut Commands, launcher_position: Vec3| -> Entity {
+        let launcher = match &threat.launcher_model {
+            Some(model) => commands.spawn(SceneRoot(asset_server.load(model.clone()))).id(),
+            None => commands.spawn((Mesh3d(loader_mesh.clone()), MeshMaterial3d(vehicle_material.clone()))).id(),
+        };
+        commands.entity(launcher)
+            .insert(Transform::from_translation(launcher_position + Vec3::Y * 0.35));
+        add_battery_vehicle_components(commands, launcher, side, 100.0);
+        launcher
+    };
+
+    let battery = commands.spawn_empty().id();
+    let fire_control = threat.fire_control.emitter(threat.reaction_time);
+    let (acquisition_radar, fire_control_radar, launchers) = if threat.self_contained {
+        let vehicle = spawn_launcher(commands, ground(Vec2::ZERO));
+        commands.entity(vehicle)
+            .insert(Name::new(format!("{} {}", name, threat.fire_control.name)))
+            .insert(fire_control)
+            .insert(IadsSamSite{..default()})
+            .insert(ElintReceiver{..default()})
+            .insert(LineOfSightCache::default());
+        (vehicle, vehicle, vec![vehicle])
+    } else {
+        let fire_control_radar = spawn_battery_vehicle(commands, Mesh3d(radar_mesh.clone()), &vehicle_material, ground(Vec2::ZERO), side, 60.0)
+            .insert(Name::new(format!("{} {}", name, threat.fire_control.name)))
+            .insert(fire_control)
+            .insert(ElintReceiver{..default()})
+            .insert(LineOfSightCache::default())
+            .id();
+        let acquisition = threat.acquisition.as_ref().expect("Battery without an acquisition radar");
+        let acquisition_radar = spawn_battery_vehicle(commands, Mesh3d(radar_mesh.clone()), &vehicle_material,
+            ground(Vec2::new(-ACQUISITION_RADAR_OFFSET, 0.0)), side, 60.0)
+            .insert(Name::new(format!("{} {}", name, acquisition.name)))
+            .insert(RadarEmitter {
+                coast_time: 15.0,
+                // Acquisition radars hand over their tracks, they can't lock
+                lock_quality: 2.0,
+                ..acquisition.emitter(threat.reaction_time)
+            })
+            .insert(IadsSamSite{..default()})
+            .insert(ElintReceiver{..default()})
+            .insert(LineOfSightCache::default())
+            .id();
+        let launchers = (0..threat.launchers).map(|i| {
+            let angle = i as f32 * std::f32::consts::TAU / threat.launchers as f32;
+            spawn_launcher(commands, ground(Vec2::from_angle(angle) * threat.launcher_spacing))
+        }).collect();
+        (acquisition_radar, fire_control_radar, launchers)
+    };
+    for launcher in launchers.iter() {
+        if threat.missile.is_some() {
+            commands.entity(*launcher).insert(SamLauncher::for_sam_type(&sam_type));
+        }
+        if let Some(gun) = &threat.gun {
+            commands.entity(*launcher).insert(FlakGun::from_data(gun, threat.envelope));
         }
     }
+    let loaders: Vec<Entity> = (0..threat.loaders).map(|i| {
+        let loader_position = ground(Vec2::new(LOADER_OFFSET, (i as f32 - 0.5) * 4.0));
+        spawn_battery_vehicle(commands, Mesh3d(loader_mesh.clone()), &vehicle_material, loader_position, side, 80.0)
+            .insert(SamLoader{missiles: threat.loader_missiles})
+            .id()
+    }).collect();
+
+    commands.entity(battery)
+    .insert(Transform::from_translation(position))
+    .insert(Coalition{side: side})
+    .insert(SamBattery {
+        name: String::from(name),
+        sam_type,
+        acquisition_radar,
+        fire_control_radar,
+        launchers,
+        loaders,
+        envelope: threat.envelope,
+        salvo_size: threat.salvo_size,
+        salvo_interval: (threat.salvo_interval * 1000.0) as u64,
+        last_launch_time: 0,
+    });
+
+    spawn_map_symbol(commands, asset_server, &sam_type, position, battery);
+    info!("{} battery {} set up", threat.name, name);
+    battery
 }
 
-pub fn spawn_sam(commands: &mut Commands,
+/// A lone radar vehicle emitting like the fire-control radar of the given system. It can't lock
+/// anything, but it is briefed and shows up on the RWR like the real thing.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_decoy_emitter(
+    commands: &mut Commands,
     asset_server: &Res<AssetServer>,
-    xpos: f32,
-    zpos: f32,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    terrain: Option<&TerrainData>,
+    sam_type: SAMType,
+    position: Vec3,
     side: CoalitionType,
 ) {
-    commands.spawn(SceneRoot(asset_server.load("models/vehicles/SA6.gltf#Scene0")))
+    let threat = threat_data(&sam_type);
+    let position = Vec3::new(position.x, terrain.map(|t| t.get_height_world(position.x, position.z)).unwrap_or(position.y), position.z);
+    let decoy = spawn_battery_vehicle(commands, Mesh3d(meshes.add(Cuboid::new(0.6, 0.6, 0.9))), &materials.add(Color::srgb(0.35, 0.38, 0.28)), position, side, 40.0)
+        .insert(Name::new(format!("{} decoy", threat.fire_control.name)))
+        .insert(RadarEmitter {
+            emitting: true,
+            lock_quality: 2.0,
+            ..threat.fire_control.emitter(threat.reaction_time)
+        })
+        .insert(DecoyEmitter)
+        .id();
+    spawn_map_symbol(commands, asset_server, &sam_type, position, decoy);
+}
+
+/// Fit a ship with the naval version of a self-contained system. The ship is radar, launcher
+/// and magazine in one, and its radar is on whenever it is at sea. The battery is a child of
+/// the ship, so it goes down with it.
+pub fn add_shipborne_sam(commands: &mut Commands, ship: Entity, sam_type: SAMType, name: &str, side: CoalitionType) {
+    let threat = threat_data(&sam_type);
+    commands.entity(ship)
+        .insert(RadarEmitter {
+            emitting: true,
+            ..threat.fire_control.emitter(threat.reaction_time)
+        })
+        .insert(ElintReceiver{..default()})
+        .insert(LineOfSightCache::default());
+    if threat.missile.is_some() {
+        commands.entity(ship).insert(SamLauncher::for_sam_type(&sam_type));
+    }
+    if let Some(gun) = &threat.gun {
+        commands.entity(ship).insert(FlakGun::from_data(gun, threat.envelope));
+    }
+    commands.spawn((
+        Coalition{side: side},
+        SamBattery {
+            name: String::from(name),
+            sam_type,
+            acquisition_radar: ship,
+            fire_control_radar: ship,
+            launchers: vec![ship],
+            loaders: Vec::new(),
+            envelope: threat.envelope,
+            salvo_size: threat.salvo_size,
+            salvo_interval: (threat.salvo_interval * 1000.0) as u64,
+            last_launch_time: 0,
+        },
+        ChildOf(ship),
+    ));
+}
+
+/// Site symbol on the map MFD, labelled with the system's RWR code so the systems sharing a
+/// symbol can be told apart. The marker rotation follows the heading, so point it up.
+fn spawn_map_symbol(commands: &mut Commands, asset_server: &Res<AssetServer>, sam_type: &SAMType, position: Vec3, site: Entity) {
+    let threat = threat_data(sam_type);
+    let symbol = commands.spawn((
+        Sprite {
+            image: asset_server.load(threat.map_symbol.clone()),
+            custom_size: Some(Vec2::splat(MAP_SYMBOL_SIZE)),
+            ..default()
+        },
+        Transform::default(),
+        RenderLayers::layer(RENDERLAYER_COCKPIT),
+        MapMarker { world_pos: Vec2::new(position.x, position.z), heading: std::f32::consts::FRAC_PI_2 },
+        SamMapSymbol { site },
+    )).id();
+    commands.spawn((
+        Text2d::new(threat.fire_control.rwr_code.clone()),
+        TextFont {
+            font: asset_server.load("fonts/Brickshapers-eXPx.ttf"),
+            font_size: MAP_LABEL_SIZE,
+            ..default()
+        },
+        Transform::from_xyz(MAP_SYMBOL_SIZE, 0.0, 0.1),
+        RenderLayers::layer(RENDERLAYER_COCKPIT),
+        ChildOf(symbol),
+    ));
+}
+
+fn spawn_battery_vehicle<'a>(
+    commands: &'a mut Commands,
+    mesh: Mesh3d,
+    material: &Handle<StandardMaterial>,
+    position: Vec3,
+    side: CoalitionType,
+    health: f32,
+) -> EntityCommands<'a> {
+    let entity = commands.spawn((mesh, MeshMaterial3d(material.clone())))
+        .insert(Transform::from_translation(position + Vec3::Y * 0.3))
+        .id();
+    add_battery_vehicle_components(commands, entity, side, health);
+    commands.entity(entity)
+}
+
+fn add_battery_vehicle_components(commands: &mut Commands, entity: Entity, side: CoalitionType, health: f32) {
+    commands.entity(entity)
     .insert(Vehicle{..default()})
     .insert(Coalition{side: side})
-    .insert(SAM{name: String::from("SA-6 #1"), ..default() })
     .insert(Collider::cuboid(0.25, 0.35, 0.4))
     .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_GROUNDVEHICLE),
         Group::from_bits_truncate(
@@ -50,13 +355,244 @@ pub fn spawn_sam(commands: &mut Commands,
         )))
     .insert(RigidBody::Dynamic)
     .insert(ColliderMassProperties::Density(100.0))
-    .insert(Transform::from_xyz(xpos, 0.0, zpos))
     .insert(Targetable)
-    .insert(RadarEmitter{
-        radar_type: RadarEmitterType::PULSE,
-        radar_gain: 10.0,
-        scan_interval: 3.0,
+    .insert(Health{health})
+    .insert(Volatile{cargo: VolatileCargo::AMMUNITION});
+}
+
+/// A battery is out of action once its fire-control radar and all its launchers are destroyed.
+/// The map symbols of batteries and decoys that are gone are removed.
+pub fn remove_destroyed_sites(
+    mut commands: Commands,
+    batteries: Query<(Entity, &SamBattery)>,
+    vehicles: Query<(), With<Vehicle>>,
+    map_symbols: Query<(Entity, &SamMapSymbol)>,
+) {
+    for (entity, battery) in batteries.iter() {
+        if !vehicles.contains(battery.fire_control_radar) && !battery.launchers.iter().any(|l| vehicles.contains(*l)) {
+            info!("{} battery destroyed", battery.name);
+            commands.entity(entity).despawn();
+        }
+    }
+    for (entity, map_symbol) in map_symbols.iter() {
+        if !batteries.contains(map_symbol.site) && !vehicles.contains(map_symbol.site) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Hand the acquisition radar's tracks to the fire-control radar, which only comes on when it has
+/// something to look at. Without the acquisition radar the fire-control radar has to search itself.
+pub fn update_sam_batteries(
+    batteries: Query<&SamBattery>,
+    mut radars: Query<(&mut RadarEmitter, &Transform)>,
+) {
+    let milliseconds = get_time_millis();
+    for battery in batteries.iter() {
+        // Self-contained systems search with their fire-control radar, under control of the IADS
+        if battery.acquisition_radar == battery.fire_control_radar {
+            continue;
+        }
+        let handover: Option<Vec<(Entity, Vec3)>> = radars.get(battery.acquisition_radar).ok()
+            .map(|(radar_emitter, _)| radar_emitter.tracks.iter()
+                .filter(|t| t.state != TrackState::DETECTED)
+                .map(|t| (t.target, t.last_position))
+                .collect());
+        let Ok((mut fire_control, transform)) = radars.get_mut(battery.fire_control_radar) else { continue };
+        let Some(handover) = handover else {
+            fire_control.emitting = true;
+            continue;
+        };
+        let range = fire_control.max_detect_range_km * 1000.0;
+        let mut cued = false;
+        for (target, position) in handover.into_iter().filter(|(_, p)| p.distance(transform.translation) < range) {
+            fire_control.cue(target, position, milliseconds);
+            cued = true;
+        }
+        if cued && !fire_control.emitting {
+            info!("{} fire-control radar on", battery.name);
+        }
+        fire_control.emitting = cued || !fire_control.tracks.is_empty();
+    }
+}
+
+/// Reload the launchers from the loaders and fire at locked targets inside the envelope
+#[allow(clippy::too_many_arguments)]
+pub fn update_sam_launchers(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut batteries: Query<&mut SamBattery>,
+    mut launchers: Query<(&mut SamLauncher, &Transform)>,
+    mut loaders: Query<&mut SamLoader>,
+    radars: Query<(&RadarEmitter, &Transform)>,
+    targets: Query<&Transform, Without<SamLauncher>>,
+    missiles: Query<&Missile>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let milliseconds = get_time_millis();
+    for mut battery in batteries.iter_mut() {
+        let Some(missile_data) = &threat_data(&battery.sam_type).missile else { continue };
+
+        // Missiles are loaded one at a time while there is room on the rails and a loader with spares
+        for launcher_entity in battery.launchers.iter() {
+            let Ok((mut launcher, _)) = launchers.get_mut(*launcher_entity) else { continue };
+            if launcher.missiles >= launcher.capacity {
+                continue;
+            }
+            let loader_entity = battery.loaders.iter()
+                .find(|l| loaders.get(**l).is_ok_and(|loader| loader.missiles > 0));
+            let Some(mut loader) = loader_entity.and_then(|l| loaders.get_mut(*l).ok()) else {
+                launcher.reload_start_time = None;
+                continue;
+            };
+            match launcher.reload_start_time {
+                None => launcher.reload_start_time = Some(milliseconds),
+                Some(start_time) if milliseconds - start_time >= launcher.reload_time => {
+                    launcher.missiles += 1;
+                    loader.missiles -= 1;
+                    launcher.reload_start_time = None;
+                    info!("{} launcher reloaded, {} ready", battery.name, launcher.missiles);
+                },
+                Some(_) => {},
+            }
+        }
+
+        // Without its fire-control radar the battery is blind
+        let Ok((radar_emitter, radar_transform)) = radars.get(battery.fire_control_radar) else { continue };
+        if radar_emitter.mode != RadarMode::LAUNCH || milliseconds - battery.last_launch_time < battery.salvo_interval {
+            continue;
+        }
+        let Some(target) = radar_emitter.locked_target else { continue };
+        let Ok(target_transform) = targets.get(target) else { continue };
+        let guided = missiles.iter()
+            .filter(|missile| battery.launchers.contains(&missile.launching_vehicle) && missile.target == target)
+            .count() as u32;
+        if guided >= battery.salvo_size {
+            continue;
+        }
+        let target_position = target_transform.translation;
+        let ground_height = terrain.as_deref()
+            .map(|t| t.get_height_world(target_position.x, target_position.z))
+            .unwrap_or(0.0);
+        // Altitude is shown in feet, at ten feet per world unit
+        let altitude_feet = (target_position.y - ground_height) * 10.0;
+        if !battery.envelope.contains(target_position.distance(radar_transform.translation), altitude_feet) {
+            continue;
+        }
+        let ready = battery.launchers.iter()
+            .find(|l| launchers.get(**l).is_ok_and(|(launcher, _)| launcher.missiles > 0))
+            .copied();
+        let Some(launcher_entity) = ready else { continue };
+        let Ok((mut launcher, launcher_transform)) = launchers.get_mut(launcher_entity) else { continue };
+
+        info!("{} launch", battery.name);
+        launcher.missiles -= 1;
+        battery.last_launch_time = milliseconds;
+        let sound = if random_u64(0, 2) == 0 { "sounds/SAM Launch.wav" } else { "sounds/SAM Launch 2.wav" };
+        commands.spawn((AudioPlayer::new(asset_server.load(sound)), PlaybackSettings::DESPAWN));
+        launch_sam_missile(&mut commands, &asset_server, missile_data, launcher_entity, battery.fire_control_radar,
+            launcher_transform.translation, target, target_transform);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn launch_sam_missile(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    missile_data: &MissileData,
+    launcher_entity: Entity,
+    illuminator: Entity,
+    launcher_position: Vec3,
+    target: Entity,
+    target_transform: &Transform,
+) {
+    let launch_position = launcher_position + Vec3::Y * 1.0;
+    let direction = ((target_transform.translation - launch_position).normalize_or_zero() + Vec3::Y * LAUNCH_ELEVATION).normalize();
+    let missile = commands.spawn(SceneRoot(asset_server.load("models/weapons/agm-65.glb#Scene0")))
+    .insert(Missile {
+        launching_vehicle: launcher_entity,
+        target,
+        target_transform: *target_transform,
+        max_turn_rate: missile_data.max_turn_rate,
+        max_thrust: missile_data.max_thrust,
+        gain: missile_data.gain,
+        motor_burn_time: (missile_data.motor_burn_time * 1000.0) as u64,
+        proximity_fuse_distance: missile_data.proximity_fuse_distance,
+        proximity_fuse_arm_time: 2000,
+        warhead_radius: missile_data.warhead_radius,
+        warhead_damage: missile_data.warhead_damage,
         ..default()
-    });
+    })
+    .insert(Transform::from_translation(launch_position).looking_to(direction, Vec3::Y))
+    .insert(Velocity { linvel: direction * 5.0, ..default() })
+    .insert(ExternalForce { ..default() })
+    .insert(Collider::cuboid(0.2, 0.05, 0.2))
+    .insert(ActiveEvents::COLLISION_EVENTS)
+    // Ground vehicles are left out, so the missile doesn't hit its own launcher
+    .insert(CollisionGroups::new(
+        Group::from_bits_truncate(COLLISION_MASK_MISSILE),
+        Group::from_bits_truncate(
+            COLLISION_MASK_TERRAIN | COLLISION_MASK_AIRCRAFT |
+            COLLISION_MASK_PLAYER | COLLISION_MASK_MISSILE)))
+    .insert(Ccd::enabled())
+    .insert(RigidBody::Dynamic)
+    .insert(GravityScale(1.0))
+    .insert(Damping { linear_damping: 0.3, angular_damping: 1.0 })
+    .insert(ColliderMassProperties::Density(15.0))
+    .insert(Targetable)
+    .id();
+    match missile_data.guidance {
+        MissileGuidance::INFRARED => commands.entity(missile).insert(IrSeeker {
+            flare_rejection: missile_data.flare_rejection.unwrap_or(IrSeeker::default().flare_rejection),
+            ..default()
+        }),
+        _ => commands.entity(missile).insert(RadarGuidance{illuminator, guidance: missile_data.guidance, aim_error: random_vec3(1.0)}),
+    };
+}
 
+/// Point radar guided missiles at what their fire-control radar has locked. Semi-active missiles
+/// see the reflections themselves and get more accurate as they close in, command guided missiles
+/// are only as accurate as the radar's angle measurement, which gets worse with range.
+/// Once the radar breaks lock the missile has nothing to guide on, and flies on towards the last
+/// position it was given.
+pub fn update_radar_guided_missiles(
+    mut missiles: Query<(&mut Missile, &RadarGuidance)>,
+    radars: Query<(&RadarEmitter, &Transform)>,
+    targets: Query<&Transform, Without<Missile>>,
+) {
+    for (mut missile, radar_guidance) in missiles.iter_mut() {
+        let Ok((radar_emitter, radar_transform)) = radars.get(radar_guidance.illuminator) else {
+            if missile.seeker_state != SeekerState::TARGET_LOST {
+                info!("SAM missile lost its fire-control radar");
+            }
+            missile.seeker_state = SeekerState::TARGET_LOST;
+            continue;
+        };
+        let locked = radar_emitter.locked_target
+            .and_then(|target| targets.get(target).ok().map(|transform| (target, transform)));
+        match locked {
+            Some((target, target_transform)) => {
+                if target != missile.target {
+                    // The radar was pulled off, e.g. by chaff, and the missile follows
+                    info!("SAM missile guiding on a new return");
+                    missile.target = target;
+                    missile.last_target_distance = f32::MAX;
+                }
+                missile.target_transform = *target_transform;
+                if radar_guidance.guidance == MissileGuidance::COMMAND {
+                    let radar_distance = target_transform.translation.distance(radar_transform.translation);
+                    missile.target_transform.translation += radar_guidance.aim_error * radar_distance * COMMAND_GUIDANCE_ERROR;
+                }
+                if missile.seeker_state == SeekerState::TARGET_LOST {
+                    missile.seeker_state = SeekerState::TRACKING;
+                }
+            },
+            None => {
+                if missile.seeker_state != SeekerState::TARGET_LOST {
+                    info!("SAM missile lost guidance");
+                }
+                missile.seeker_state = SeekerState::TARGET_LOST;
+            },
+        }
+    }
 }
@@ -1,2 +1,186 @@
-// Terrain and scenery are now handled by the terrain module.
-// This module is kept for backward compatibility of the module declaration.
+use bevy::prelude::*;
+
+use crate::explosion::{spawn_explosion, spawn_secondary_explosions, ExplosionType, Volatile};
+use crate::health::{BlastEvent, DestroyedEvent, Health};
+use crate::pointlight::LightBillboard;
+use crate::targeting::Targetable;
+use crate::terrain::TerrainData;
+use crate::util::get_time_millis;
+
+/* Destructible scenery. Terrain and scenery generation is handled by the terrain module,
+   this module deals with what happens to it when it gets hit: structures turn into rubble
+   and take nearby lights with them, and blasts close to the ground leave craters. */
+
+/// Oldest craters and scorch marks are removed beyond this count
+const MAX_IMPACT_DECALS: usize = 200;
+/// Lights are only switched off if they are within this height above/below the structure
+const LIGHT_HEIGHT_RANGE: f32 = 20.0;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StructureType {
+    BUILDING,
+    COMM_TOWER,
+    COMMAND_POST,
+    SHIP,
+    RUNWAY,
+}
+
+#[derive(Component)]
+pub struct Destructible {
+    pub structure_type: StructureType,
+    pub light_radius: f32, // Lights within this horizontal distance go dark when destroyed
+}
+
+#[derive(Component)]
+pub struct Rubble;
+
+#[derive(Component)]
+pub struct ImpactDecal {
+    pub spawn_time: u64,
+}
+
+#[derive(Resource)]
+pub struct DestructionAssets {
+    rubble_mesh: Handle<Mesh>,
+    rubble_material: Handle<StandardMaterial>,
+    cratered_runway_material: Handle<StandardMaterial>,
+    decal_mesh: Handle<Mesh>,
+    crater_material: Handle<StandardMaterial>,
+    scorch_material: Handle<StandardMaterial>,
+}
+
+pub fn structure_health(structure_type: StructureType) -> f32 {
+    match structure_type {
+        StructureType::BUILDING => 60.0,
+        StructureType::COMM_TOWER => 40.0,
+        StructureType::COMMAND_POST => 120.0,
+        StructureType::SHIP => 200.0,
+        StructureType::RUNWAY => 150.0,
+    }
+}
+
+pub fn setup_destruction_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(DestructionAssets {
+        rubble_mesh: meshes.add(Cone { radius: 0.5, height: 1.0 }),
+        rubble_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.22, 0.20, 0.18), perceptual_roughness: 1.0, ..default()
+        }),
+        cratered_runway_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.14, 0.13, 0.12), perceptual_roughness: 1.0, ..default()
+        }),
+        decal_mesh: meshes.add(Circle::new(1.0)),
+        crater_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.12, 0.09, 0.06), perceptual_roughness: 1.0, ..default()
+        }),
+        scorch_material: materials.add(StandardMaterial {
+            base_color: Color::srgba(0.02, 0.02, 0.02, 0.6),
+            alpha_mode: AlphaMode::Blend, unlit: true, ..default()
+        }),
+    });
+}
+
+/// Turn destroyed structures into rubble and switch off the lights around them
+pub fn handle_destroyed_scenery(
+    mut commands: Commands,
+    mut destroyed_events: MessageReader<DestroyedEvent>,
+    mut structures: Query<(&Destructible, Option<&Volatile>, &mut Transform, &mut Mesh3d, &mut MeshMaterial3d<StandardMaterial>)>,
+    lights: Query<(Entity, &GlobalTransform), (With<LightBillboard>, Without<ChildOf>)>,
+    terrain: Res<TerrainData>,
+    destruction_assets: Res<DestructionAssets>,
+) {
+    for destroyed in destroyed_events.read() {
+        let Ok((destructible, volatile, mut transform, mut mesh, mut material)) = structures.get_mut(destroyed.entity) else { continue };
+        info!("{:?} destroyed", destructible.structure_type);
+
+        let position = transform.translation;
+        let explosion_type = match destructible.structure_type {
+            StructureType::BUILDING | StructureType::COMM_TOWER | StructureType::COMMAND_POST => ExplosionType::MEDIUM,
+            StructureType::RUNWAY => ExplosionType::SMALL,
+            StructureType::SHIP => ExplosionType::LARGE,
+        };
+        spawn_explosion(&mut commands, explosion_type, &position);
+        if let Some(volatile) = volatile {
+            spawn_secondary_explosions(&mut commands, volatile.cargo, &position);
+        }
+
+        for (light_entity, light_transform) in lights.iter() {
+            let offset = light_transform.translation() - position;
+            if Vec2::new(offset.x, offset.z).length() < destructible.light_radius && offset.y.abs() < LIGHT_HEIGHT_RANGE {
+                commands.entity(light_entity).despawn();
+            }
+        }
+
+        match destructible.structure_type {
+            StructureType::BUILDING | StructureType::COMM_TOWER | StructureType::COMMAND_POST => {
+                // Collapse into a pile of rubble covering the old footprint
+                let footprint = transform.scale.x.max(transform.scale.z).max(1.0);
+                let rubble_height = (transform.scale.y * 0.25).clamp(0.2, 1.0);
+                let ground_height = terrain.get_height_world(position.x, position.z);
+                transform.scale = Vec3::new(footprint, rubble_height, footprint);
+                transform.translation.y = ground_height + rubble_height / 2.0;
+                mesh.0 = destruction_assets.rubble_mesh.clone();
+                material.0 = destruction_assets.rubble_material.clone();
+            },
+            StructureType::RUNWAY => {
+                material.0 = destruction_assets.cratered_runway_material.clone();
+            },
+            StructureType::SHIP => {
+                // Ships sink, taking their superstructure with them
+                commands.entity(destroyed.entity).despawn();
+                continue;
+            },
+        }
+        commands.entity(destroyed.entity)
+            .remove::<(Destructible, Health, Targetable)>()
+            .insert(Rubble);
+    }
+}
+
+/// Leave a crater and a scorch mark where a blast reached the ground
+pub fn spawn_impact_decals(
+    mut commands: Commands,
+    mut blast_events: MessageReader<BlastEvent>,
+    decals: Query<(Entity, &ImpactDecal)>,
+    terrain: Res<TerrainData>,
+    destruction_assets: Res<DestructionAssets>,
+) {
+    let mut decal_count = decals.iter().count();
+    for blast in blast_events.read() {
+        let ground_height = terrain.get_height_world(blast.position.x, blast.position.z);
+        if blast.position.y - ground_height > blast.radius || terrain.is_water(blast.position.x, blast.position.z) {
+            continue;
+        }
+        let spawn_time = get_time_millis();
+        let flat = Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2);
+        commands.spawn((
+            Mesh3d(destruction_assets.decal_mesh.clone()),
+            MeshMaterial3d(destruction_assets.scorch_material.clone()),
+            Transform::from_xyz(blast.position.x, ground_height + 0.04, blast.position.z)
+                .with_rotation(flat)
+                .with_scale(Vec3::splat(blast.radius * 1.5)),
+            ImpactDecal { spawn_time },
+        ));
+        commands.spawn((
+            Mesh3d(destruction_assets.decal_mesh.clone()),
+            MeshMaterial3d(destruction_assets.crater_material.clone()),
+            Transform::from_xyz(blast.position.x, ground_height + 0.05, blast.position.z)
+                .with_rotation(flat)
+                .with_scale(Vec3::splat(blast.radius * 0.6)),
+            ImpactDecal { spawn_time },
+        ));
+        decal_count += 2;
+    }
+
+    if decal_count > MAX_IMPACT_DECALS {
+        let mut sorted_decals = decals.iter().collect::<Vec<_>>();
+        sorted_decals.sort_by_key(|(_, decal)| decal.spawn_time);
+        for (entity, _) in sorted_decals.iter().take(decal_count - MAX_IMPACT_DECALS) {
+            commands.entity(*entity).despawn();
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{prelude::*, camera::visibility::RenderLayers};
+use bevy_rapier3d::prelude::*;
+
+use crate::coalition::{Coalition, CoalitionType};
+use crate::convoy::{place_on_road, route_length, ConvoyVehicle, ConvoyVehicleType};
+use crate::definitions::*;
+use crate::explosion::{spawn_smoke_puff, ExplosionAssets, SmokePuff, Volatile};
+use crate::f117_ai::{activate_f117_ai, F117AIEvent, F117AIState};
+use crate::health::Health;
+use crate::map_mfd::MapMarker;
+use crate::road_network::{RoadNetwork, RoadNodeType};
+use crate::targeting::Targetable;
+use crate::terrain::TerrainData;
+use crate::util::{get_time_millis, random_f32, random_u64};
+use crate::vehicle::Vehicle;
+
+/* Scud hunting. A launcher spends most of its time hidden in a town or under a bridge, where it
+   can't be seen from the air. Every now and then it drives out along the roads, stops, erects
+   its missile and fires it, then lowers the erector and drives on to another hiding place,
+   where it is reloaded. The plume of a launch can be seen from far away, and every launch
+   is reported to the player as a time-critical target: the launcher is only out in the open
+   until it has packed up and driven off. */
+
+/// Time spent in a hiding place before the next launch, in seconds
+const SCUD_MIN_HIDE_TIME: f32 = 120.0;
+const SCUD_MAX_HIDE_TIME: f32 = 400.0;
+/// Time to raise the missile on its erector (ms)
+const SCUD_ERECT_TIME: u64 = 45000;
+/// Time to lower the erector after launch before driving off (ms)
+const SCUD_STOW_TIME: u64 = 30000;
+/// Launchers drive out this far along the route to their next hiding place before firing
+const SCUD_MIN_LAUNCH_FRACTION: f32 = 0.3;
+const SCUD_MAX_LAUNCH_FRACTION: f32 = 0.7;
+/// Driving speed in world units per second
+const SCUD_SPEED: f32 = 4.0;
+/// Length and radius of the missile on the erector
+const SCUD_MISSILE_LENGTH: f32 = 1.6;
+const SCUD_MISSILE_RADIUS: f32 = 0.08;
+/// Climb acceleration of a launched missile, in world units per second squared
+const SCUD_ACCELERATION: f32 = 6.0;
+/// The missile is out of sight after this long (ms)
+const SCUD_BOOST_TIME: u64 = 40000;
+/// Time between two puffs of the plume (ms)
+const SCUD_PLUME_INTERVAL: u64 = 150;
+/// A launch report is useful for this long, after that the launcher is long gone (ms)
+const TIME_CRITICAL_TARGET_TIME: u64 = 600000;
+/// Size of the launch report symbols on the map
+const MAP_SYMBOL_SIZE: f32 = 12.0;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScudState {
+    HIDING,
+    DRIVING_OUT,
+    ERECTING,
+    STOWING,
+    RELOCATING,
+}
+
+#[derive(Component)]
+pub struct ScudLauncher {
+    pub name: String,
+    pub state: ScudState,
+    pub state_until: u64, // End of a timed state
+    pub hide: usize, // Road network node the launcher is hiding at, or last hid at
+    pub destination: usize, // Next hiding place
+    pub route: Vec<Vec2>, // Original terrain coordinates
+    pub distance: f32, // Distance along the route
+    pub launch_distance: f32,
+    pub erector: Entity,
+}
+
+/// The missile on the erector, a child of the launcher
+#[derive(Component)]
+pub struct ScudErector;
+
+/// A launched missile, climbing away on its plume
+#[derive(Component)]
+pub struct ScudMissile {
+    pub launch_time: u64,
+    pub speed: f32,
+    pub last_plume_time: u64,
+}
+
+/// Map symbol where a launch was seen
+#[derive(Component)]
+pub struct TimeCriticalTarget {
+    pub launcher: Entity,
+    pub expires: u64,
+}
+
+/// Nodes of the road network a launcher can hide at: towns and bridges
+pub fn scud_hides(network: &RoadNetwork) -> Vec<usize> {
+    network.nodes.iter().enumerate()
+        .filter(|(_, n)| matches!(n.node_type, RoadNodeType::CITY(_) | RoadNodeType::BRIDGE))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+fn hide_time(milliseconds: u64) -> u64 {
+    milliseconds + (random_f32(SCUD_MIN_HIDE_TIME, SCUD_MAX_HIDE_TIME) * 1000.0) as u64
+}
+
+/// Erector angle from horizontal (0.0) to upright (1.0), pivoting at the rear of the launcher
+fn erector_pose(raised: f32, size: Vec3) -> Transform {
+    let rotation = Quat::from_rotation_x(FRAC_PI_2 * (1.0 - raised));
+    let pivot = Vec3::new(0.0, size.y / 2.0 + SCUD_MISSILE_RADIUS, -size.z / 2.0 + 0.2);
+    Transform::from_translation(pivot + rotation * Vec3::Y * SCUD_MISSILE_LENGTH / 2.0).with_rotation(rotation)
+}
+
+/// A loaded launcher hiding at a node of the road network
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_scud_launcher(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    terrain: &TerrainData,
+    network: &RoadNetwork,
+    name: &str,
+    hide: usize,
+    side: CoalitionType,
+) {
+    let vehicle_type = ConvoyVehicleType::SCUD_TEL;
+    let data = vehicle_type.data();
+    let position = network.nodes[hide].position;
+    let mut transform = Transform::default();
+    place_on_road(&mut transform, &[position, position + Vec2::X], 0.0, data.size.y / 2.0, terrain);
+
+    let launcher = commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(data.size.x, data.size.y, data.size.z))),
+        MeshMaterial3d(materials.add(Color::srgb(0.33, 0.35, 0.27))),
+        transform,
+        Visibility::Hidden,
+    ))
+    .insert(Name::new(String::from(name)))
+    .insert(ConvoyVehicle{vehicle_type})
+    .insert(Vehicle{..default()})
+    .insert(Coalition{side: side})
+    .insert(Collider::cuboid(data.size.x / 2.0, data.size.y / 2.0, data.size.z / 2.0))
+    .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_GROUNDVEHICLE),
+        Group::from_bits_truncate(
+            COLLISION_MASK_AIRCRAFT |
+            COLLISION_MASK_MISSILE |
+            COLLISION_MASK_PLAYER
+        )))
+    .insert(RigidBody::KinematicPositionBased)
+    .insert(Health{health: data.health})
+    .insert(Volatile{cargo: data.cargo})
+    .id();
+    let erector = commands.spawn((
+        Mesh3d(meshes.add(Cylinder::new(SCUD_MISSILE_RADIUS, SCUD_MISSILE_LENGTH))),
+        MeshMaterial3d(materials.add(Color::srgb(0.55, 0.57, 0.50))),
+        erector_pose(0.0, data.size),
+        ScudErector,
+        ChildOf(launcher),
+    )).id();
+
+    commands.entity(launcher).insert(ScudLauncher {
+        name: String::from(name),
+        state: ScudState::HIDING,
+        state_until: hide_time(get_time_millis()),
+        hide,
+        destination: hide,
+        route: Vec::new(),
+        distance: 0.0,
+        launch_distance: 0.0,
+        erector,
+    });
+    info!("{} hiding", name);
+}
+
+/// Run the hide, drive, erect, launch and relocate cycle of the launchers
+#[allow(clippy::too_many_arguments)]
+pub fn update_scud_launchers(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut launchers: Query<(Entity, &mut ScudLauncher, &mut Transform, &mut Visibility, &ConvoyVehicle)>,
+    mut erectors: Query<(&mut Transform, &mut Visibility), (With<ScudErector>, Without<ScudLauncher>)>,
+    mut f117_ai_state: ResMut<F117AIState>,
+    terrain: Option<Res<TerrainData>>,
+    road_network: Option<Res<RoadNetwork>>,
+) {
+    let (Some(terrain), Some(network)) = (terrain, road_network) else { return };
+    let milliseconds = get_time_millis();
+    for (entity, mut launcher, mut transform, mut visibility, vehicle) in launchers.iter_mut() {
+        let size = vehicle.vehicle_type.data().size;
+        match launcher.state {
+            ScudState::HIDING => {
+                if milliseconds < launcher.state_until {
+                    continue;
+                }
+                let hide = launcher.hide;
+                let hides: Vec<usize> = scud_hides(&network).into_iter().filter(|h| *h != hide).collect();
+                let next = (!hides.is_empty())
+                    .then(|| hides[random_u64(0, hides.len() as u64) as usize])
+                    .and_then(|to| network.route(hide, to).map(|route| (to, route)));
+                let Some((destination, route)) = next else {
+                    launcher.state_until = hide_time(milliseconds);
+                    continue;
+                };
+                info!("{} leaving its hiding place", launcher.name);
+                launcher.launch_distance = route_length(&route) * random_f32(SCUD_MIN_LAUNCH_FRACTION, SCUD_MAX_LAUNCH_FRACTION);
+                launcher.route = route;
+                launcher.destination = destination;
+                launcher.distance = 0.0;
+                launcher.state = ScudState::DRIVING_OUT;
+                *visibility = Visibility::Inherited;
+                commands.entity(entity).insert(Targetable);
+            },
+            ScudState::DRIVING_OUT | ScudState::RELOCATING => {
+                launcher.distance += SCUD_SPEED * time.delta_secs();
+                place_on_road(&mut transform, &launcher.route, launcher.distance, size.y / 2.0, &terrain);
+                if launcher.state == ScudState::DRIVING_OUT && launcher.distance >= launcher.launch_distance {
+                    info!("{} erecting", launcher.name);
+                    launcher.state = ScudState::ERECTING;
+                    launcher.state_until = milliseconds + SCUD_ERECT_TIME;
+                } else if launcher.distance >= route_length(&launcher.route) {
+                    // Under cover again, and loaded for the next launch
+                    info!("{} hiding", launcher.name);
+                    launcher.hide = launcher.destination;
+                    launcher.state = ScudState::HIDING;
+                    launcher.state_until = hide_time(milliseconds);
+                    *visibility = Visibility::Hidden;
+                    commands.entity(entity).remove::<Targetable>();
+                    if let Ok((mut erector_transform, mut erector_visibility)) = erectors.get_mut(launcher.erector) {
+                        *erector_transform = erector_pose(0.0, size);
+                        *erector_visibility = Visibility::Inherited;
+                    }
+                }
+            },
+            ScudState::ERECTING => {
+                let raised = 1.0 - launcher.state_until.saturating_sub(milliseconds) as f32 / SCUD_ERECT_TIME as f32;
+                let Ok((mut erector_transform, mut erector_visibility)) = erectors.get_mut(launcher.erector) else { continue };
+                *erector_transform = erector_pose(raised, size);
+                if milliseconds < launcher.state_until {
+                    continue;
+                }
+                // The missile leaves the erector, which is lowered again empty
+                let launch_position = transform.translation + transform.rotation * (erector_transform.translation + Vec3::Y * SCUD_MISSILE_LENGTH / 2.0);
+                *erector_visibility = Visibility::Hidden;
+                launch_scud_missile(&mut commands, &asset_server, launch_position);
+
+                let terrain_position = Vec2::new(transform.translation.x + terrain.origin_shift.x, transform.translation.z + terrain.origin_shift.z);
+                info!("{} launch detected at {:.0}, {:.0}", launcher.name, terrain_position.x, terrain_position.y);
+                commands.spawn((
+                    Sprite {
+                        image: asset_server.load("mfd/symbology-ground.png"),
+                        custom_size: Some(Vec2::splat(MAP_SYMBOL_SIZE)),
+                        ..default()
+                    },
+                    Transform::default(),
+                    RenderLayers::layer(RENDERLAYER_COCKPIT),
+                    MapMarker { world_pos: terrain_position, heading: FRAC_PI_2 },
+                    TimeCriticalTarget { launcher: entity, expires: milliseconds + TIME_CRITICAL_TARGET_TIME },
+                ));
+                if f117_ai_state.cooldown_scud_launch <= 0.0 {
+                    f117_ai_state.cooldown_scud_launch = 30.0;
+                    activate_f117_ai(&mut f117_ai_state, F117AIEvent::ScudLaunch);
+                }
+                launcher.state = ScudState::STOWING;
+                launcher.state_until = milliseconds + SCUD_STOW_TIME;
+            },
+            ScudState::STOWING => {
+                let raised = launcher.state_until.saturating_sub(milliseconds) as f32 / SCUD_STOW_TIME as f32;
+                if let Ok((mut erector_transform, _)) = erectors.get_mut(launcher.erector) {
+                    *erector_transform = erector_pose(raised, size);
+                }
+                if milliseconds >= launcher.state_until {
+                    info!("{} relocating", launcher.name);
+                    launcher.state = ScudState::RELOCATING;
+                }
+            },
+        }
+    }
+}
+
+fn launch_scud_missile(commands: &mut Commands, asset_server: &Res<AssetServer>, position: Vec3) {
+    commands.spawn((AudioPlayer::new(asset_server.load("sounds/SAM Launch 2.wav")), PlaybackSettings::DESPAWN));
+    commands.spawn((
+        Transform::from_translation(position),
+        ScudMissile {
+            launch_time: get_time_millis(),
+            speed: 0.0,
+            last_plume_time: 0,
+        },
+    ));
+}
+
+/// Climb the launched missiles away on a trail of smoke
+pub fn update_scud_missiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Option<Res<ExplosionAssets>>,
+    mut missiles: Query<(Entity, &mut ScudMissile, &mut Transform)>,
+) {
+    let Some(explosion_assets) = explosion_assets else { return };
+    let milliseconds = get_time_millis();
+    for (entity, mut missile, mut transform) in missiles.iter_mut() {
+        if milliseconds - missile.launch_time > SCUD_BOOST_TIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        missile.speed += SCUD_ACCELERATION * time.delta_secs();
+        transform.translation.y += missile.speed * time.delta_secs();
+        if milliseconds - missile.last_plume_time > SCUD_PLUME_INTERVAL {
+            // The first puffs billow out over the launch site
+            let launch_cloud = missile.last_plume_time == 0;
+            missile.last_plume_time = milliseconds;
+            spawn_smoke_puff(&mut commands, &mut materials, &explosion_assets, transform.translation,
+                SmokePuff {
+                    start_time: milliseconds,
+                    life_time: if launch_cloud { 30000 } else { 15000 },
+                    start_size: if launch_cloud { 3.0 } else { 0.8 },
+                    end_size: if launch_cloud { 12.0 } else { 4.0 },
+                    velocity: Vec3::ZERO,
+                    opacity: 0.7,
+                });
+        }
+    }
+}
+
+/// Launch reports run out, or go away with the launcher
+pub fn update_time_critical_targets(
+    mut commands: Commands,
+    reports: Query<(Entity, &TimeCriticalTarget)>,
+    launchers: Query<(), With<ScudLauncher>>,
+) {
+    let milliseconds = get_time_millis();
+    for (entity, report) in reports.iter() {
+        if milliseconds > report.expires || !launchers.contains(report.launcher) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
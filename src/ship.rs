@@ -0,0 +1,293 @@
+use bevy::{prelude::*, camera::visibility::RenderLayers};
+use bevy_rapier3d::prelude::*;
+
+use crate::coalition::{Coalition, CoalitionType};
+use crate::definitions::*;
+use crate::explosion::{spawn_smoke_puff, ExplosionAssets, SmokePuff, Volatile, VolatileCargo};
+use crate::health::Health;
+use crate::map_mfd::MapMarker;
+use crate::radar::{RadarBand, RadarEmitter, RadarEmitterType};
+use crate::sam::{add_shipborne_sam, SAMType};
+use crate::scenery::{Destructible, StructureType};
+use crate::targeting::Targetable;
+use crate::terrain::{TerrainData, SEA_LANE_INNER, SEA_LANE_OUTER};
+use crate::util::get_time_millis;
+use crate::vehicle::Vehicle;
+
+/* Shipping. Ships sail around the island in a lane of their own, turning away from the coast
+   when they get too close to it. A damaged ship loses speed and goes dead in the water before
+   it sinks. Warships carry a SAM system with its radar, merchant ships only a navigation radar,
+   which gives them away but can't guide anything. Ships are drawn with the ship symbol on the
+   map and on the FLIR. */
+
+/// Course changes in radians per second
+const SHIP_TURN_RATE: f32 = 0.02;
+/// How far ahead the crew looks out for the coast
+const SHIP_LOOKOUT_DISTANCE: f32 = 800.0;
+/// Ships steer back into their lane over this distance
+const SHIP_LANE_CORRECTION: f32 = 500.0;
+/// Below this share of its health a ship has no propulsion left
+const SHIP_DEAD_IN_WATER: f32 = 0.3;
+/// Time between two wake puffs (ms)
+const WAKE_INTERVAL: u64 = 1000;
+/// Lifetime of a wake puff (ms)
+const WAKE_LIFE_TIME: u64 = 10000;
+/// Size of the ship symbols on the map
+const MAP_SYMBOL_SIZE: f32 = 12.0;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShipType {
+    CARGO,
+    PATROL_BOAT,
+    FRIGATE,
+    DESTROYER,
+}
+
+pub struct ShipData {
+    pub name: &'static str,
+    pub length: f32, // Beam and height of the hull are in proportion
+    pub speed: f32, // World units per second
+    pub health: f32,
+    pub cargo: VolatileCargo,
+    pub sam: Option<SAMType>, // Naval versions of the land based systems
+}
+
+impl ShipType {
+    pub fn data(&self) -> ShipData {
+        match self {
+            ShipType::CARGO => ShipData {
+                name: "Cargo ship",
+                length: 7.0,
+                speed: 2.0,
+                health: 200.0,
+                cargo: VolatileCargo::FUEL,
+                sam: None,
+            },
+            ShipType::PATROL_BOAT => ShipData {
+                name: "Patrol boat",
+                length: 3.5,
+                speed: 5.0,
+                health: 80.0,
+                cargo: VolatileCargo::AMMUNITION,
+                sam: None,
+            },
+            ShipType::FRIGATE => ShipData {
+                name: "Frigate",
+                length: 5.5,
+                speed: 3.5,
+                health: 300.0,
+                cargo: VolatileCargo::AMMUNITION,
+                sam: Some(SAMType::SA8),
+            },
+            ShipType::DESTROYER => ShipData {
+                name: "Destroyer",
+                length: 7.5,
+                speed: 3.5,
+                health: 450.0,
+                cargo: VolatileCargo::AMMUNITION,
+                sam: Some(SAMType::SA15),
+            },
+        }
+    }
+
+    pub fn is_warship(&self) -> bool {
+        self.data().sam.is_some()
+    }
+}
+
+#[derive(Component)]
+pub struct Ship {
+    pub ship_type: ShipType,
+    pub heading: f32, // Rotation about the vertical axis, the bow is along the hull's X axis
+    pub max_speed: f32,
+    pub speed: f32,
+    pub lane: f32, // Distance from the centre of the island the ship keeps to
+    pub max_health: f32,
+    pub map_symbol: Entity,
+    pub last_wake_time: u64,
+}
+
+/// Light billboards carried by a ship, with their position on board
+#[derive(Component)]
+pub struct NavigationLights {
+    pub lights: Vec<(Entity, Vec3)>,
+}
+
+/// Warships sailing together in line ahead
+#[derive(Component)]
+pub struct TaskGroup;
+
+#[derive(Component)]
+pub struct ShipMapSymbol {
+    pub ship: Entity,
+}
+
+/// Put a ship to sea at the given position, bow pointing along the heading
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_ship(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    hull_mesh: &Handle<Mesh>,
+    hull_material: &Handle<StandardMaterial>,
+    bridge_material: &Handle<StandardMaterial>,
+    ship_type: ShipType,
+    name: &str,
+    position: Vec3,
+    heading: f32,
+    lane: f32,
+    side: CoalitionType,
+) -> Entity {
+    let data = ship_type.data();
+    let (length, beam, height) = (data.length, data.length * 0.2, data.length * 0.08);
+    let map_symbol = commands.spawn((
+        Sprite {
+            image: asset_server.load("mfd/symbology-ship.png"),
+            custom_size: Some(Vec2::splat(MAP_SYMBOL_SIZE)),
+            ..default()
+        },
+        Transform::default(),
+        RenderLayers::layer(RENDERLAYER_COCKPIT),
+        MapMarker { world_pos: Vec2::new(position.x, position.z), heading: std::f32::consts::FRAC_PI_2 },
+    )).id();
+
+    // The collider is scaled along with the hull
+    let ship = commands.spawn((Mesh3d(hull_mesh.clone()), MeshMaterial3d(hull_material.clone())))
+        .insert(Transform::from_translation(position)
+            .with_rotation(Quat::from_rotation_y(heading))
+            .with_scale(Vec3::new(length, height, beam)))
+        .insert(Name::new(String::from(name)))
+        .insert(Ship {
+            ship_type,
+            heading,
+            max_speed: data.speed,
+            speed: data.speed,
+            lane,
+            max_health: data.health,
+            map_symbol,
+            last_wake_time: 0,
+        })
+        .insert(Vehicle{..default()})
+        .insert(Coalition{side: side})
+        .insert(Collider::cuboid(0.5, 0.5, 0.5))
+        .insert(CollisionGroups::new(Group::from_bits_truncate(COLLISION_MASK_GROUNDVEHICLE),
+            Group::from_bits_truncate(
+                COLLISION_MASK_AIRCRAFT |
+                COLLISION_MASK_MISSILE |
+                COLLISION_MASK_PLAYER
+            )))
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(Destructible{structure_type: StructureType::SHIP, light_radius: length * 0.6})
+        .insert(Targetable)
+        .insert(Health{health: data.health})
+        .insert(Volatile{cargo: data.cargo})
+        .id();
+    commands.entity(map_symbol).insert(ShipMapSymbol{ship});
+
+    // Bridge/superstructure, a child of the hull so it sinks with it.
+    // Local coordinates are in units of the (scaled) hull.
+    commands.spawn((Mesh3d(hull_mesh.clone()), MeshMaterial3d(bridge_material.clone()),
+        Transform::from_translation(Vec3::new(0.2, 1.5, 0.0)).with_scale(Vec3::new(0.2, 2.0, 0.7)),
+        ChildOf(ship)));
+
+    match data.sam {
+        Some(sam_type) => add_shipborne_sam(commands, ship, sam_type, name, side),
+        None => {
+            commands.entity(ship).insert(RadarEmitter {
+                radar_type: RadarEmitterType::PULSE,
+                radar_gain: 5.0,
+                band: RadarBand::X,
+                max_detect_range_km: 40.0,
+                scan_interval: 2.5,
+                // A navigation radar can't lock anything
+                lock_quality: 2.0,
+                rwr_code: String::from("N"),
+                ..default()
+            });
+        },
+    }
+    info!("{} ({:?}) at sea", name, ship_type);
+    ship
+}
+
+/// Sail the ships along their lanes, with their lights, wakes and map symbols in tow
+#[allow(clippy::too_many_arguments)]
+pub fn update_ships(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    explosion_assets: Option<Res<ExplosionAssets>>,
+    mut ships: Query<(&mut Ship, &mut Transform, &Health, Option<&NavigationLights>)>,
+    mut lights: Query<&mut Transform, Without<Ship>>,
+    mut map_symbols: Query<(Entity, &mut MapMarker, &ShipMapSymbol)>,
+    terrain: Option<Res<TerrainData>>,
+) {
+    let Some(terrain) = terrain else { return };
+    let milliseconds = get_time_millis();
+    for (mut ship, mut transform, health, navigation_lights) in ships.iter_mut() {
+        let damage_state = health.health / ship.max_health;
+        let speed = if damage_state < SHIP_DEAD_IN_WATER { 0.0 } else { ship.max_speed * damage_state.min(1.0) };
+        if speed == 0.0 && ship.speed > 0.0 {
+            info!("{} dead in the water", ship.ship_type.data().name);
+        }
+        ship.speed = speed;
+
+        // Follow the lane around the island, heading out to sea when the coast comes up ahead
+        let p = transform.translation;
+        let position = Vec2::new(p.x + terrain.origin_shift.x, p.z + terrain.origin_shift.z);
+        let forward = Vec2::new(ship.heading.cos(), -ship.heading.sin());
+        let lookout = p + Vec3::new(forward.x, 0.0, forward.y) * SHIP_LOOKOUT_DISTANCE;
+        let outward = position.normalize_or(Vec2::X);
+        let lane = ship.lane.clamp(SEA_LANE_INNER, SEA_LANE_OUTER);
+        let desired = if terrain.get_height_world(lookout.x, lookout.z) > p.y {
+            outward
+        } else {
+            let along = outward.perp() * outward.perp().dot(forward).signum();
+            along + outward * ((lane - position.length()) / SHIP_LANE_CORRECTION).clamp(-1.0, 1.0)
+        };
+        let turn = forward.angle_to(desired).clamp(-1.0, 1.0) * SHIP_TURN_RATE * time.delta_secs();
+        // A positive heading turns the bow from X towards -Z
+        ship.heading -= turn;
+
+        let heading = ship.heading;
+        let forward = Vec3::new(heading.cos(), 0.0, -heading.sin());
+        transform.rotation = Quat::from_rotation_y(heading);
+        transform.translation += forward * ship.speed * time.delta_secs();
+
+        if let Some(navigation_lights) = navigation_lights {
+            for (light, offset) in navigation_lights.lights.iter() {
+                if let Ok(mut light_transform) = lights.get_mut(*light) {
+                    light_transform.translation = transform.translation + transform.rotation * *offset;
+                }
+            }
+        }
+        if let Ok((_, mut map_marker, _)) = map_symbols.get_mut(ship.map_symbol) {
+            map_marker.world_pos = Vec2::new(transform.translation.x + terrain.origin_shift.x, transform.translation.z + terrain.origin_shift.z);
+            map_marker.heading = forward.z.atan2(forward.x);
+        }
+
+        if let Some(explosion_assets) = explosion_assets.as_deref() {
+            if ship.speed > 0.0 && milliseconds - ship.last_wake_time > WAKE_INTERVAL {
+                ship.last_wake_time = milliseconds;
+                let beam = transform.scale.z;
+                spawn_smoke_puff(&mut commands, &mut materials, explosion_assets,
+                    transform.translation - forward * transform.scale.x * 0.5,
+                    SmokePuff {
+                        start_time: milliseconds,
+                        life_time: WAKE_LIFE_TIME,
+                        start_size: beam,
+                        end_size: beam * 4.0,
+                        velocity: Vec3::ZERO,
+                        opacity: 0.5,
+                    });
+            }
+        }
+    }
+
+    // The symbols of sunk ships go with them
+    for (entity, _, map_symbol) in map_symbols.iter() {
+        if !ships.contains(map_symbol.ship) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
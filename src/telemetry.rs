@@ -0,0 +1,262 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::missile::{Missile, SeekerState};
+use crate::util::{get_serial_number, get_time_millis};
+
+/* Missile flight telemetry. When switched on, every missile records its guidance state each
+   frame, and when it is removed from the world a miss-distance report is logged and the flight
+   is exported as CSV for tuning gain, turn_ramp and the proximity fuse. */
+
+/// Directory (next to the game executable) that receives the telemetry files
+const TELEMETRY_DIR: &str = "telemetry";
+/// Summary file with one line per shot, appended across sessions
+const TELEMETRY_SUMMARY_FILE: &str = "shots.csv";
+/// Upper bound on recorded samples per missile, so a lost missile can't eat memory
+const TELEMETRY_MAX_SAMPLES: usize = 20000;
+
+/// Whether missile flights are recorded
+#[derive(Resource, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+}
+
+impl TelemetrySettings {
+    /// Switched on from the command line with `--telemetry`
+    pub fn from_args() -> TelemetrySettings {
+        TelemetrySettings { enabled: std::env::args().any(|a| a == "--telemetry") }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TelemetrySample {
+    pub time: u64, // Milliseconds since launch
+    pub position: Vec3,
+    pub los_rate: f32,
+    pub commanded_acceleration: Vec3,
+    pub thrust: f32,
+    pub seeker_state: SeekerState,
+    pub target_position: Vec3,
+}
+
+#[derive(Component)]
+pub struct MissileTelemetry {
+    pub serialnumber: u64,
+    pub launch_time: u64,
+    pub gain: f32,
+    pub turn_ramp: f32,
+    pub max_turn_rate: f32,
+    pub max_thrust: f32,
+    pub thrust_ramp: f32,
+    pub proximity_fuse_distance: f32,
+    pub samples: Vec<TelemetrySample>,
+}
+
+impl MissileTelemetry {
+    pub fn from_missile(missile: &Missile) -> Self {
+        MissileTelemetry {
+            serialnumber: get_serial_number(),
+            launch_time: missile.start_time,
+            gain: missile.gain,
+            turn_ramp: missile.turn_ramp,
+            max_turn_rate: missile.max_turn_rate,
+            max_thrust: missile.max_thrust,
+            thrust_ramp: missile.thrust_ramp,
+            proximity_fuse_distance: missile.proximity_fuse_distance,
+            samples: Vec::new(),
+        }
+    }
+}
+
+/// Result of the post-shot analysis
+pub struct MissDistanceReport {
+    pub miss_distance: f32,
+    pub time_of_closest_approach: u64,
+    pub relative_position: Vec3, // Missile position relative to target at closest approach
+}
+
+/// Find the closest approach between missile and target. The relative motion is
+/// assumed to be linear between two samples, so the result does not depend on frame rate.
+pub fn closest_approach(samples: &[TelemetrySample]) -> Option<MissDistanceReport> {
+    let first = samples.first()?;
+    let first_relative = first.position - first.target_position;
+    let mut report = MissDistanceReport {
+        miss_distance: first_relative.length(),
+        time_of_closest_approach: first.time,
+        relative_position: first_relative,
+    };
+    for pair in samples.windows(2) {
+        let r0 = pair[0].position - pair[0].target_position;
+        let r1 = pair[1].position - pair[1].target_position;
+        let dr = r1 - r0;
+        let t = if dr.length_squared() > 0.0 { (-r0.dot(dr) / dr.length_squared()).clamp(0.0, 1.0) } else { 0.0 };
+        let relative = r0 + dr * t;
+        if relative.length() < report.miss_distance {
+            report.miss_distance = relative.length();
+            report.time_of_closest_approach = pair[0].time + ((pair[1].time - pair[0].time) as f32 * t) as u64;
+            report.relative_position = relative;
+        }
+    }
+    Some(report)
+}
+
+/// Attach a telemetry buffer to new missiles and record one sample per frame
+pub fn record_missile_telemetry(
+    mut commands: Commands,
+    settings: Res<TelemetrySettings>,
+    new_missiles: Query<(Entity, &Missile), Without<MissileTelemetry>>,
+    mut missiles: Query<(&Missile, &Transform, &mut MissileTelemetry)>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    for (entity, missile) in new_missiles.iter() {
+        commands.entity(entity).insert(MissileTelemetry::from_missile(missile));
+    }
+
+    let current_time = get_time_millis();
+    for (missile, transform, mut telemetry) in missiles.iter_mut() {
+        if telemetry.samples.len() >= TELEMETRY_MAX_SAMPLES {
+            continue;
+        }
+        telemetry.samples.push(TelemetrySample {
+            time: current_time.saturating_sub(missile.start_time),
+            position: transform.translation,
+            los_rate: missile.los_rate,
+            commanded_acceleration: missile.acceleration,
+            thrust: missile.thrust,
+            seeker_state: missile.seeker_state,
+            target_position: missile.target_position,
+        });
+    }
+}
+
+/// Observer that runs when a missile is despawned, while its telemetry can still be read
+pub fn report_missile_telemetry(
+    remove: On<Remove, MissileTelemetry>,
+    telemetry: Query<&MissileTelemetry>,
+) {
+    let Ok(telemetry) = telemetry.get(remove.entity) else { return };
+    let Some(report) = closest_approach(&telemetry.samples) else { return };
+    info!(
+        "Missile #{} miss distance {:.2} at {:.2}s (fuse {:.2}, gain {}, turn_ramp {})",
+        telemetry.serialnumber,
+        report.miss_distance,
+        report.time_of_closest_approach as f32 / 1000.0,
+        telemetry.proximity_fuse_distance,
+        telemetry.gain,
+        telemetry.turn_ramp,
+    );
+    if let Err(e) = export_telemetry(telemetry, &report) {
+        warn!("Could not write missile telemetry: {}", e);
+    }
+}
+
+fn telemetry_dir() -> PathBuf {
+    match std::env::current_exe() {
+        Ok(exe) => exe.parent().map(|p| p.join(TELEMETRY_DIR)).unwrap_or_else(|| PathBuf::from(TELEMETRY_DIR)),
+        Err(_) => PathBuf::from(TELEMETRY_DIR),
+    }
+}
+
+fn export_telemetry(telemetry: &MissileTelemetry, report: &MissDistanceReport) -> std::io::Result<()> {
+    let dir = telemetry_dir();
+    fs::create_dir_all(&dir)?;
+
+    let flight_file_name = format!("missile_{}_{}.csv", telemetry.launch_time, telemetry.serialnumber);
+    let mut flight_file = fs::File::create(dir.join(&flight_file_name))?;
+    writeln!(flight_file, "time_ms,pos_x,pos_y,pos_z,los_rate,accel_x,accel_y,accel_z,thrust,seeker_state,target_x,target_y,target_z,target_distance")?;
+    for s in telemetry.samples.iter() {
+        writeln!(flight_file, "{},{},{},{},{},{},{},{},{},{:?},{},{},{},{}",
+            s.time,
+            s.position.x, s.position.y, s.position.z,
+            s.los_rate,
+            s.commanded_acceleration.x, s.commanded_acceleration.y, s.commanded_acceleration.z,
+            s.thrust,
+            s.seeker_state,
+            s.target_position.x, s.target_position.y, s.target_position.z,
+            (s.target_position - s.position).length(),
+        )?;
+    }
+
+    let summary_path = dir.join(TELEMETRY_SUMMARY_FILE);
+    let write_header = !summary_path.exists();
+    let mut summary_file = OpenOptions::new().create(true).append(true).open(summary_path)?;
+    if write_header {
+        writeln!(summary_file, "launch_time,flight_file,gain,turn_ramp,max_turn_rate,max_thrust,thrust_ramp,proximity_fuse_distance,miss_distance,time_of_closest_approach_ms,rel_x,rel_y,rel_z,flight_time_ms,final_seeker_state")?;
+    }
+    let last = telemetry.samples.last();
+    writeln!(summary_file, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:?}",
+        telemetry.launch_time,
+        flight_file_name,
+        telemetry.gain,
+        telemetry.turn_ramp,
+        telemetry.max_turn_rate,
+        telemetry.max_thrust,
+        telemetry.thrust_ramp,
+        telemetry.proximity_fuse_distance,
+        report.miss_distance,
+        report.time_of_closest_approach,
+        report.relative_position.x, report.relative_position.y, report.relative_position.z,
+        last.map(|s| s.time).unwrap_or(0),
+        last.map(|s| s.seeker_state).unwrap_or(SeekerState::IGNITION),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: u64, position: Vec3, target_position: Vec3) -> TelemetrySample {
+        TelemetrySample {
+            time,
+            position,
+            los_rate: 0.0,
+            commanded_acceleration: Vec3::ZERO,
+            thrust: 0.0,
+            seeker_state: SeekerState::TRACKING,
+            target_position,
+        }
+    }
+
+    #[test]
+    fn closest_approach_between_samples() {
+        // The missile passes 2 units beside a stationary target halfway between two frames
+        let target = Vec3::new(0.0, 100.0, 0.0);
+        let samples = [
+            sample(1000, Vec3::new(-10.0, 100.0, 2.0), target),
+            sample(1100, Vec3::new(10.0, 100.0, 2.0), target),
+        ];
+        let report = closest_approach(&samples).unwrap();
+        assert!((report.miss_distance - 2.0).abs() < 1e-5, "miss distance {}", report.miss_distance);
+        assert_eq!(report.time_of_closest_approach, 1050);
+        assert!(report.relative_position.distance(Vec3::new(0.0, 0.0, 2.0)) < 1e-5);
+    }
+
+    #[test]
+    fn closest_approach_at_last_sample() {
+        // Still closing when the flight ends
+        let target = Vec3::ZERO;
+        let samples = [
+            sample(0, Vec3::new(30.0, 0.0, 0.0), target),
+            sample(100, Vec3::new(20.0, 0.0, 0.0), target),
+            sample(200, Vec3::new(12.0, 0.0, 0.0), target),
+        ];
+        let report = closest_approach(&samples).unwrap();
+        assert_eq!(report.miss_distance, 12.0);
+        assert_eq!(report.time_of_closest_approach, 200);
+    }
+
+    #[test]
+    fn closest_approach_needs_samples() {
+        assert!(closest_approach(&[]).is_none());
+        // A single sample is its own closest approach
+        let report = closest_approach(&[sample(0, Vec3::new(3.0, 4.0, 0.0), Vec3::ZERO)]).unwrap();
+        assert_eq!(report.miss_distance, 5.0);
+        assert_eq!(report.time_of_closest_approach, 0);
+    }
+}
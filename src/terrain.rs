@@ -10,8 +10,20 @@ use rand::rngs::StdRng;
 
 use crate::billboard::Billboard;
 use crate::definitions::RENDERLAYER_POINTLIGHTS;
+use crate::health::Health;
+use crate::iads::{spawn_command_post, spawn_early_warning_radar};
+use crate::coalition::CoalitionType;
 use crate::player::Player;
+use crate::road_network::{RoadNetwork, RoadNode, RoadNodeType, RoadPolyline};
+use crate::convoy::{spawn_convoy, ConvoyVehicleType};
+use crate::emitter_tactics::{BlinkCycle, EmitterTactics, ScootPlan};
+use crate::sam::{spawn_decoy_emitter, spawn_sam_battery, SAMType};
+use crate::scud::{scud_hides, spawn_scud_launcher};
+use crate::ship::{spawn_ship, NavigationLights, ShipType, TaskGroup};
+use crate::threats::{threat_data, Difficulty};
 use crate::pointlight::*;
+use crate::scenery::{Destructible, StructureType, structure_health};
+use crate::targeting::Targetable;
 
 // ============================================================
 // Constants
@@ -49,6 +61,8 @@ const ROAD_WIDTH: f32 = 0.4;
 const ROAD_LIGHT_SPACING: f32 = 200.0;
 const ROAD_MAX_HEIGHT: f32 = 6.0;
 const ROAD_SEGMENT_LEN: f32 = 50.0;
+/// Where the airbase road starts, beyond the end of the runway
+const AIRBASE_ROAD_EXIT: Vec2 = Vec2::new(RUNWAY_LENGTH + 50.0, 0.0);
 
 const FIELD_COUNT: usize = 80;
 const FIELD_SIZE_MIN: f32 = 60.0;
@@ -58,14 +72,66 @@ const RUNWAY_LENGTH: f32 = 172.0;
 const RUNWAY_WIDTH: f32 = 5.0;
 const RUNWAY_Y: f32 = -0.96;
 const RUNWAY_Z: f32 = 0.5;
+const RUNWAY_SEGMENTS: usize = 8;
 
 const NUM_TREES: usize = 1500;
 const NUM_FARMS: usize = 60;
 const NUM_COMM_TOWERS: usize = 12;
 const NUM_SHIPS: usize = 15;
+/// Ships keep to lanes between these distances from the centre of the island
+pub const SEA_LANE_INNER: f32 = LAND_RADIUS + 1000.0;
+pub const SEA_LANE_OUTER: f32 = LAND_RADIUS + 8000.0;
+/// Warships sailing together, the strike target of anti-ship missions
+const TASK_GROUP: [ShipType; 3] = [ShipType::DESTROYER, ShipType::FRIGATE, ShipType::FRIGATE];
+const TASK_GROUP_SPACING: f32 = 150.0;
+
+const NUM_COMMAND_POSTS: usize = 4;
+const NUM_EW_RADARS: usize = 6;
+/// Early warning radar sites are picked as the highest of this many candidate spots
+const EW_SITE_CANDIDATES: usize = 20;
+
+/// The largest cities are ringed by medium range SAM sites
+const SAM_RING_CITIES: usize = 6;
+/// Medium range sites per city ring and short range sites inside it, before difficulty scaling
+const SAM_RING_SITES: usize = 3;
+const SHORAD_SITES_PER_CITY: usize = 1;
+/// Distance of the ring sites beyond the edge of the city
+const SAM_RING_DISTANCE: f32 = 1500.0;
+/// Long range sites are put on the highest of this many candidate spots
+const NUM_HIGH_GROUND_SITES: usize = 3;
+const HIGH_GROUND_CANDIDATES: usize = 30;
+/// Keeps the long range sites off the airbase's doorstep. They can still reach the airbase: an
+/// SA-2 reaches further than the island is wide, so no high ground is out of its reach, and the
+/// player has to stay below its minimum altitude after takeoff.
+const HIGH_GROUND_MIN_AIRBASE_DISTANCE: f32 = 12_000.0;
+/// AAA sites guarding each bridge, before difficulty scaling
+const BRIDGE_AAA_SITES: usize = 2;
+/// AAA sites defending the home airbase
+const AIRBASE_AAA_SITES: usize = 2;
+const AIRBASE_AAA_DISTANCE: f32 = 250.0;
+const NUM_CONVOYS: usize = 8;
+const CONVOY_MIN_VEHICLES: usize = 3;
+const CONVOY_MAX_VEHICLES: usize = 7;
+const NUM_SCUD_LAUNCHERS: usize = 4;
+
+/// Mobile batteries have this many positions to move to after firing, about this far apart
+const SCOOT_POSITIONS: usize = 2;
+const SCOOT_DISTANCE: f32 = 2000.0;
+/// Decoy emitters are set up about this far from the site they imitate
+const DECOY_DISTANCE: f32 = 3000.0;
+/// Candidate spots tried around the intended position of a site
+const SITE_CANDIDATES: usize = 12;
+const SITE_SEARCH_RADIUS: f32 = 400.0;
+/// Steepest ground (rise over run) the vehicles of a site can be parked on
+const MAX_SITE_SLOPE: f32 = 0.08;
 
 const ORIGIN_SHIFT_THRESHOLD: f32 = 10_000.0;
 
+/// Effective earth radius for line of sight (4/3 of the real one, for atmospheric refraction)
+const EFFECTIVE_EARTH_RADIUS: f32 = 6_371_000.0 * 4.0 / 3.0;
+/// Observers and targets are raised by this much so they don't mask themselves
+const LOS_CLEARANCE: f32 = 1.0;
+
 // ============================================================
 // Noise
 // ============================================================
@@ -145,6 +211,47 @@ impl TerrainData {
         a + lz * (b - a)
     }
 
+    /// Steepness of the ground (rise over run) at original-terrain coordinates.
+    pub fn get_slope_world(&self, wx: f32, wz: f32) -> f32 {
+        let dx = self.get_height_world(wx + CELL_SIZE, wz) - self.get_height_world(wx - CELL_SIZE, wz);
+        let dz = self.get_height_world(wx, wz + CELL_SIZE) - self.get_height_world(wx, wz - CELL_SIZE);
+        Vec2::new(dx, dz).length() / (2.0 * CELL_SIZE)
+    }
+
+    /// Distance to the radar horizon between two heights above sea level
+    pub fn horizon_distance(height_a: f32, height_b: f32) -> f32 {
+        let a = (height_a - WATER_LEVEL).max(0.0);
+        let b = (height_b - WATER_LEVEL).max(0.0);
+        (2.0 * EFFECTIVE_EARTH_RADIUS * a).sqrt() + (2.0 * EFFECTIVE_EARTH_RADIUS * b).sqrt()
+    }
+
+    /// True if nothing of the terrain is between the two positions.
+    /// Earth curvature is applied as a bulge of the ground towards the middle of the path.
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        let from = from + Vec3::Y * LOS_CLEARANCE;
+        let to = to + Vec3::Y * LOS_CLEARANCE;
+        let distance = Vec2::new(to.x - from.x, to.z - from.z).length();
+        if distance > Self::horizon_distance(from.y, to.y) {
+            return false;
+        }
+        let steps = (distance / (CELL_SIZE * 0.5)) as usize;
+        for i in 1..steps {
+            let t = i as f32 / steps as f32;
+            let p = from.lerp(to, t);
+            let travelled = distance * t;
+            let bulge = travelled * (distance - travelled) / (2.0 * EFFECTIVE_EARTH_RADIUS);
+            if self.get_height_world(p.x, p.z) + bulge > p.y {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// True if the given world position is open water
+    pub fn is_water(&self, wx: f32, wz: f32) -> bool {
+        self.get_height_world(wx, wz) < WATER_LEVEL
+    }
+
     /// Grid-index bounds for a world-space AABB (clamped to grid).
     fn grid_bounds(min_w: f32, max_w: f32, min_d: f32, max_d: f32, width: usize, depth: usize) -> (usize,usize,usize,usize) {
         let gx0 = ((min_w + HALF_SIZE) / CELL_SIZE).floor().max(0.0) as usize;
@@ -224,7 +331,7 @@ impl TerrainData {
 // ============================================================
 
 struct CityData { pos: Vec2, radius: f32 }
-struct RoadPath { waypoints: Vec<Vec2> }
+struct RoadPath { waypoints: Vec<Vec2>, ends: (usize,usize) } // Ends are road network nodes
 struct FieldRect { center: Vec2, half_size: Vec2, color: Color }
 
 // ============================================================
@@ -356,18 +463,44 @@ fn generate_roads(cities: &[CityData], terrain: &TerrainData) -> Vec<RoadPath> {
         for &(j,_) in ds.iter().take(2) {
             let k = if i<j {(i,j)} else {(j,i)};
             if connected.insert(k) {
-                roads.push(RoadPath { waypoints: route_road(cities[k.0].pos, cities[k.1].pos, terrain) });
+                roads.push(RoadPath { waypoints: route_road(cities[k.0].pos, cities[k.1].pos, terrain), ends: k });
             }
         }
     }
     // Road from airbase to nearest city — start beyond the runway, not at origin
-    if let Some(near) = cities.iter().min_by(|a,b| a.pos.length().partial_cmp(&b.pos.length()).unwrap()) {
-        let exit = Vec2::new(RUNWAY_LENGTH + 50.0, 0.0);
-        roads.push(RoadPath { waypoints: route_road(exit, near.pos, terrain) });
+    if let Some((i,near)) = cities.iter().enumerate().min_by(|a,b| a.1.pos.length().partial_cmp(&b.1.pos.length()).unwrap()) {
+        roads.push(RoadPath { waypoints: route_road(AIRBASE_ROAD_EXIT, near.pos, terrain), ends: (cities.len(),i) });
     }
     roads
 }
 
+/// Graph of the roads, with the cities and the airbase as nodes, in that order
+fn build_road_network(cities: &[CityData], roads: &[RoadPath], bridges: &[Vec2]) -> RoadNetwork {
+    let mut nodes: Vec<RoadNode> = cities.iter().enumerate()
+        .map(|(i,c)| RoadNode { position: c.pos, node_type: RoadNodeType::CITY(i) }).collect();
+    nodes.push(RoadNode { position: AIRBASE_ROAD_EXIT, node_type: RoadNodeType::AIRBASE });
+    nodes.extend(bridges.iter().map(|b| RoadNode { position: *b, node_type: RoadNodeType::BRIDGE }));
+    let polylines: Vec<RoadPolyline> = roads.iter().filter(|r| r.waypoints.len() >= 2)
+        .map(|r| RoadPolyline { waypoints: r.waypoints.clone(), from: r.ends.0, to: r.ends.1 }).collect();
+    RoadNetwork::new(nodes, &polylines)
+}
+
+/// Places where a road crosses water, found before the roads are built up on embankments
+fn find_bridges(roads: &[RoadPath], terrain: &TerrainData) -> Vec<Vec2> {
+    let mut bridges = Vec::new();
+    for road in roads {
+        let mut crossing: Vec<Vec2> = Vec::new();
+        for p in &road.waypoints {
+            if terrain.get_height_world(p.x,p.y) < WATER_LEVEL { crossing.push(*p); continue; }
+            if let (Some(first), Some(last)) = (crossing.first(), crossing.last()) {
+                bridges.push((*first+*last)/2.0);
+            }
+            crossing.clear();
+        }
+    }
+    bridges
+}
+
 fn generate_fields(t: &TerrainData, cities: &[CityData], rng: &mut StdRng) -> Vec<FieldRect> {
     let pal = [Color::srgb(0.55,0.50,0.25),Color::srgb(0.30,0.50,0.20),
                Color::srgb(0.45,0.40,0.20),Color::srgb(0.35,0.55,0.25)];
@@ -467,13 +600,20 @@ fn make_ground_quad(w: f32, l: f32) -> Mesh {
 // ============================================================
 
 fn spawn_bb(cmd: &mut Commands, m: &Handle<Mesh>, mat: &Handle<StandardMaterial>,
-    pos: Vec3, lc: LightColor, lt: LightType)
+    pos: Vec3, lc: LightColor, lt: LightType) -> Entity
 {
     cmd.spawn((Mesh3d(m.clone()), MeshMaterial3d(mat.clone()),
         Transform::from_translation(pos), Billboard,
         LightBillboard { light_color: lc, light_type: lt,
             lightsource_type: LightSourceType::NONE, active: true, occluded: false },
-        RenderLayers::layer(RENDERLAYER_POINTLIGHTS)));
+        RenderLayers::layer(RENDERLAYER_POINTLIGHTS))).id()
+}
+
+/// Components that make a piece of scenery something weapons can destroy
+fn destructible(structure_type: StructureType, light_radius: f32) -> (Destructible, Health, Targetable) {
+    (Destructible { structure_type, light_radius },
+        Health { health: structure_health(structure_type) },
+        Targetable)
 }
 
 fn bb_mat(mats: &mut Assets<StandardMaterial>, img: Handle<Image>) -> Handle<StandardMaterial> {
@@ -555,7 +695,8 @@ fn spawn_cities(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Assets
             let m = mats.add(StandardMaterial { base_color: bcols[rng.gen_range(0..bcols.len())],
                 perceptual_roughness: 0.8, ..default() });
             cmd.spawn((Mesh3d(ub.clone()), MeshMaterial3d(m),
-                Transform::from_translation(Vec3::new(bx,gh+h/2.0,bz)).with_scale(Vec3::new(w,h,dp))));
+                Transform::from_translation(Vec3::new(bx,gh+h/2.0,bz)).with_scale(Vec3::new(w,h,dp)),
+                destructible(StructureType::BUILDING, w.max(dp) + 1.0)));
         }
         for _ in 0..(city.radius/30.0) as usize {
             let a = rng.gen_range(0.0..std::f32::consts::TAU);
@@ -624,8 +765,14 @@ fn spawn_airbase(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Asset
     let bm = mats.add(StandardMaterial { base_color: Color::srgb(0.50,0.48,0.44), perceptual_roughness: 0.85, ..default() });
     let tm = mats.add(StandardMaterial { base_color: Color::srgb(0.55,0.55,0.52), perceptual_roughness: 0.7, ..default() });
 
-    cmd.spawn((Mesh3d(meshes.add(make_ground_quad(RUNWAY_WIDTH,RUNWAY_LENGTH))), MeshMaterial3d(cm.clone()),
-        Transform::from_translation(Vec3::new(RUNWAY_LENGTH/2.0-0.2,RUNWAY_Y+0.02,RUNWAY_Z))));
+    // Runway is split into segments that can be cratered individually
+    let seg_len = RUNWAY_LENGTH / RUNWAY_SEGMENTS as f32;
+    let seg_mesh = meshes.add(make_ground_quad(RUNWAY_WIDTH,seg_len));
+    for i in 0..RUNWAY_SEGMENTS {
+        cmd.spawn((Mesh3d(seg_mesh.clone()), MeshMaterial3d(cm.clone()),
+            Transform::from_translation(Vec3::new(RUNWAY_LENGTH/2.0-0.2,RUNWAY_Y+0.02,RUNWAY_Z-RUNWAY_LENGTH/2.0+seg_len*(i as f32+0.5))),
+            destructible(StructureType::RUNWAY, seg_len/2.0)));
+    }
     cmd.spawn((Mesh3d(meshes.add(make_ground_quad(3.0,RUNWAY_LENGTH))), MeshMaterial3d(cm),
         Transform::from_translation(Vec3::new(RUNWAY_LENGTH/2.0-0.2,RUNWAY_Y+0.01,7.3))));
 
@@ -695,7 +842,8 @@ fn spawn_terrain_features(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &
         if cities.iter().any(|c| (c.pos-Vec2::new(x,z)).length() < c.radius+50.0) { continue; }
         let (w,bh,d) = (rng.gen_range(1.0..3.0_f32), rng.gen_range(0.5..1.5_f32), rng.gen_range(1.0..2.5_f32));
         cmd.spawn((Mesh3d(fm.clone()), MeshMaterial3d(fmat.clone()),
-            Transform::from_translation(Vec3::new(x,h+bh/2.0,z)).with_scale(Vec3::new(w,bh,d))));
+            Transform::from_translation(Vec3::new(x,h+bh/2.0,z)).with_scale(Vec3::new(w,bh,d)),
+            destructible(StructureType::BUILDING, w.max(d) + 1.0)));
         cnt += 1;
     }
     cnt = 0; att = 0;
@@ -707,60 +855,243 @@ fn spawn_terrain_features(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &
         if (x*x+z*z).sqrt() < AIRBASE_FLAT_RADIUS+200.0 { continue; }
         let th = rng.gen_range(6.0..12.0_f32);
         cmd.spawn((Mesh3d(twm.clone()), MeshMaterial3d(twmat.clone()),
-            Transform::from_translation(Vec3::new(x,h+th/2.0,z)).with_scale(Vec3::new(0.15,th,0.15))));
+            Transform::from_translation(Vec3::new(x,h+th/2.0,z)).with_scale(Vec3::new(0.15,th,0.15)),
+            destructible(StructureType::COMM_TOWER, 1.0)));
         spawn_bb(cmd,bbm,bbr,Vec3::new(x,h+th+0.2,z),LightColor::RED,LightType::FLASH_ALT_SINGLE);
         cnt += 1;
     }
 }
 
-fn spawn_ships(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Assets<StandardMaterial>,
-    bbm: &Handle<Mesh>, bbr: &Handle<StandardMaterial>, bbg: &Handle<StandardMaterial>,
-    bbw: &Handle<StandardMaterial>, rng: &mut StdRng)
+fn spawn_ships(cmd: &mut Commands, asset_server: &Res<AssetServer>, meshes: &mut Assets<Mesh>,
+    mats: &mut Assets<StandardMaterial>, bbm: &Handle<Mesh>, bbr: &Handle<StandardMaterial>,
+    bbg: &Handle<StandardMaterial>, bbw: &Handle<StandardMaterial>, rng: &mut StdRng)
 {
+    use std::f32::consts::TAU;
     let hull_mat = mats.add(StandardMaterial { base_color: Color::srgb(0.30,0.30,0.32), perceptual_roughness: 0.8, ..default() });
+    let warship_mat = mats.add(StandardMaterial { base_color: Color::srgb(0.42,0.45,0.48), perceptual_roughness: 0.6, ..default() });
     let bridge_mat = mats.add(StandardMaterial { base_color: Color::srgb(0.45,0.42,0.40), perceptual_roughness: 0.7, ..default() });
     let hull_mesh = meshes.add(Cuboid::new(1.0,1.0,1.0));
 
-    for _ in 0..NUM_SHIPS {
-        // Place ships in the ocean (outside the land radius)
-        let angle = rng.gen_range(0.0..std::f32::consts::TAU);
-        let dist = rng.gen_range(LAND_RADIUS+1000.0..LAND_RADIUS+8000.0);
-        let sx = angle.cos() * dist;
-        let sz = angle.sin() * dist;
+    // Ships spread around the island in lanes of their own: (type, angle, lane, direction of travel)
+    let mut ships: Vec<(ShipType, f32, f32, f32)> = (0..NUM_SHIPS).map(|_| {
+        let ship_type = match rng.gen_range(0..10) { 0..=4 => ShipType::CARGO, 5..=6 => ShipType::PATROL_BOAT, 7..=8 => ShipType::FRIGATE, _ => ShipType::DESTROYER };
+        let direction = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+        (ship_type, rng.gen_range(0.0..TAU), rng.gen_range(SEA_LANE_INNER..SEA_LANE_OUTER), direction)
+    }).collect();
+    // The task group sails in line ahead
+    let (angle, lane) = (rng.gen_range(0.0..TAU), (SEA_LANE_INNER + SEA_LANE_OUTER) / 2.0);
+    ships.extend(TASK_GROUP.iter().enumerate().map(|(i, t)| (*t, angle - i as f32 * TASK_GROUP_SPACING / lane, lane, 1.0)));
+
+    for (i, (ship_type, angle, lane, direction)) in ships.into_iter().enumerate() {
+        let in_task_group = i >= NUM_SHIPS;
+        let data = ship_type.data();
+        let (sx, sz) = (angle.cos() * lane, angle.sin() * lane);
         let ship_y = WATER_LEVEL + 0.15;
-        let heading = rng.gen_range(0.0..std::f32::consts::TAU);
-
-        let hull_len = rng.gen_range(3.0..8.0_f32);
-        let hull_w = hull_len * 0.2;
-        let hull_h = hull_len * 0.08;
-
-        // Hull
-        cmd.spawn((Mesh3d(hull_mesh.clone()), MeshMaterial3d(hull_mat.clone()),
-            Transform::from_translation(Vec3::new(sx, ship_y, sz))
-                .with_rotation(Quat::from_rotation_y(heading))
-                .with_scale(Vec3::new(hull_len, hull_h, hull_w))));
-
-        // Bridge/superstructure
-        let bridge_h = hull_h * 2.0;
-        let bridge_offset = hull_len * 0.2;
-        let bridge_pos = Vec3::new(
-            sx + heading.cos() * bridge_offset,
-            ship_y + hull_h/2.0 + bridge_h/2.0,
-            sz + heading.sin() * bridge_offset,
-        );
-        cmd.spawn((Mesh3d(hull_mesh.clone()), MeshMaterial3d(bridge_mat.clone()),
-            Transform::from_translation(bridge_pos)
-                .with_rotation(Quat::from_rotation_y(heading))
-                .with_scale(Vec3::new(hull_len*0.2, bridge_h, hull_w*0.7))));
-
-        // Navigation lights: red (port), green (starboard), white (masthead)
-        let port_offset = Vec3::new(-heading.sin()*hull_w*0.6, 0.3, heading.cos()*hull_w*0.6);
-        let stbd_offset = Vec3::new(heading.sin()*hull_w*0.6, 0.3, -heading.cos()*hull_w*0.6);
-        let mast_pos = Vec3::new(sx + heading.cos()*bridge_offset, ship_y+hull_h+bridge_h+0.3, sz + heading.sin()*bridge_offset);
-
-        spawn_bb(cmd, bbm, bbr, Vec3::new(sx,ship_y,sz)+port_offset, LightColor::RED, LightType::SOLID);
-        spawn_bb(cmd, bbm, bbg, Vec3::new(sx,ship_y,sz)+stbd_offset, LightColor::GREEN, LightType::SOLID);
-        spawn_bb(cmd, bbm, bbw, mast_pos, LightColor::WHITE, LightType::SOLID);
+        // Along the lane, anticlockwise seen from above for a positive direction
+        let course = Vec2::new(-angle.sin(), angle.cos()) * direction;
+        let heading = (-course.y).atan2(course.x);
+        let hull_mat = if ship_type.is_warship() { &warship_mat } else { &hull_mat };
+        let name = format!("{} #{}", data.name, i + 1);
+        let ship = spawn_ship(cmd, asset_server, &hull_mesh, hull_mat, &bridge_mat, ship_type, &name,
+            Vec3::new(sx, ship_y, sz), heading, lane, CoalitionType::RED);
+
+        // Navigation lights: red (port), green (starboard), white (masthead), placed on board
+        let (hull_len, hull_w, hull_h) = (data.length, data.length * 0.2, data.length * 0.08);
+        let rotation = Quat::from_rotation_y(heading);
+        let lights = [
+            (Vec3::new(0.0, 0.3, -hull_w*0.6), bbr, LightColor::RED),
+            (Vec3::new(0.0, 0.3, hull_w*0.6), bbg, LightColor::GREEN),
+            (Vec3::new(hull_len*0.2, hull_h*3.0+0.3, 0.0), bbw, LightColor::WHITE),
+        ].into_iter().map(|(offset, mat, color)| {
+            (spawn_bb(cmd, bbm, mat, Vec3::new(sx,ship_y,sz) + rotation*offset, color, LightType::SOLID), offset)
+        }).collect();
+        cmd.entity(ship).insert(NavigationLights { lights });
+        if in_task_group { cmd.entity(ship).insert(TaskGroup); }
+    }
+}
+
+/// Command posts in the larger cities, early warning radars on high ground
+fn spawn_air_defence_network(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Assets<StandardMaterial>,
+    terrain: &TerrainData, cities: &[CityData], rng: &mut StdRng)
+{
+    let site_mesh = meshes.add(Cuboid::new(1.0,1.0,1.0));
+    let bunker_mat = mats.add(StandardMaterial { base_color: Color::srgb(0.35,0.36,0.30), perceptual_roughness: 0.9, ..default() });
+    let radar_mat = mats.add(StandardMaterial { base_color: Color::srgb(0.40,0.42,0.36), perceptual_roughness: 0.7, ..default() });
+
+    let mut by_size: Vec<&CityData> = cities.iter().collect();
+    by_size.sort_by(|a,b| b.radius.total_cmp(&a.radius));
+    for city in by_size.iter().take(NUM_COMMAND_POSTS) {
+        let a = rng.gen_range(0.0..std::f32::consts::TAU);
+        let (x,z) = (city.pos.x+a.cos()*city.radius*1.2, city.pos.y+a.sin()*city.radius*1.2);
+        let h = terrain.get_height_world(x,z);
+        if h < WATER_LEVEL+0.5 { continue; }
+        spawn_command_post(cmd, &site_mesh, &bunker_mat, Vec3::new(x,h,z), CoalitionType::RED);
+    }
+
+    let feature_range = LAND_RADIUS * 0.8;
+    for _ in 0..NUM_EW_RADARS {
+        let mut best: Option<Vec3> = None;
+        for _ in 0..EW_SITE_CANDIDATES {
+            let x = rng.gen_range(-feature_range..feature_range);
+            let z = rng.gen_range(-feature_range..feature_range);
+            if (x*x+z*z).sqrt() < AIRBASE_FLAT_RADIUS+2000.0 { continue; }
+            let h = terrain.get_height_world(x,z);
+            if h < WATER_LEVEL+0.5 { continue; }
+            if best.is_none_or(|b| h > b.y) { best = Some(Vec3::new(x,h,z)); }
+        }
+        if let Some(site) = best {
+            spawn_early_warning_radar(cmd, &site_mesh, &radar_mat, site, CoalitionType::RED);
+        }
+    }
+}
+
+/// Dry and level ground for the vehicles of a site, outside the city streets
+fn site_suitable(terrain: &TerrainData, cities: &[CityData], p: Vec2) -> bool {
+    terrain.get_height_world(p.x,p.y) > WATER_LEVEL+0.5
+        && terrain.get_slope_world(p.x,p.y) < MAX_SITE_SLOPE
+        && !cities.iter().any(|c| c.pos.distance(p) < c.radius)
+}
+
+fn find_site(terrain: &TerrainData, cities: &[CityData], around: Vec2, radius: f32, rng: &mut StdRng) -> Option<Vec3> {
+    (0..SITE_CANDIDATES)
+        .map(|_| around + Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU)) * rng.gen_range(0.0..radius))
+        .find(|p| site_suitable(terrain, cities, *p))
+        .map(|p| Vec3::new(p.x, terrain.get_height_world(p.x,p.y), p.y))
+}
+
+/// SAM sites ringing the largest cities, long range sites on high ground and AAA guarding the
+/// bridges and the airbase. The difficulty sets how many enemy sites there are and how cunning
+/// their crews are with their radars.
+#[allow(clippy::too_many_arguments)]
+fn spawn_threat_layout(cmd: &mut Commands, asset_server: &Res<AssetServer>, meshes: &mut Assets<Mesh>,
+    mats: &mut Assets<StandardMaterial>, terrain: &TerrainData, cities: &[CityData], bridges: &[Vec2],
+    difficulty: Difficulty, rng: &mut StdRng)
+{
+    use std::f32::consts::{FRAC_PI_2, TAU};
+    let density = difficulty.threat_density();
+    let scaled = |count: usize| (count as f32 * density).round() as usize;
+    let tactics = difficulty.tactics();
+    let mut sites = 0;
+    let mut spawn_site = |cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Assets<StandardMaterial>,
+        rng: &mut StdRng, sam_type: SAMType, position: Vec3, side: CoalitionType|
+    {
+        sites += 1;
+        let threat = threat_data(&sam_type);
+        let name = format!("{} #{}", threat.name, sites);
+        let battery = spawn_sam_battery(cmd, asset_server, meshes, mats, Some(terrain), sam_type, &name, position, side);
+        if side != CoalitionType::RED { return; }
+
+        // Enemy crews roll their tactics, mobile ones get positions to fall back to
+        let mut site_tactics = EmitterTactics {
+            blink: rng.gen_bool(tactics.blink).then(BlinkCycle::default),
+            arm_shutdown: rng.gen_bool(tactics.arm_shutdown),
+            ..default()
+        };
+        let origin = Vec2::new(position.x, position.z);
+        if threat.mobile && rng.gen_bool(tactics.shoot_and_scoot) {
+            let positions: Vec<Vec2> = std::iter::once(origin).chain((0..SCOOT_POSITIONS).filter_map(|_| {
+                let around = origin + Vec2::from_angle(rng.gen_range(0.0..TAU)) * SCOOT_DISTANCE;
+                find_site(terrain, cities, around, SITE_SEARCH_RADIUS, rng).map(|p| Vec2::new(p.x, p.z))
+            })).collect();
+            if positions.len() > 1 {
+                site_tactics.scoot = Some(ScootPlan { positions, ..default() });
+            }
+        }
+        cmd.entity(battery).insert(site_tactics);
+        if rng.gen_bool(tactics.decoy) {
+            let around = origin + Vec2::from_angle(rng.gen_range(0.0..TAU)) * DECOY_DISTANCE;
+            if let Some(decoy) = find_site(terrain, cities, around, SITE_SEARCH_RADIUS, rng) {
+                spawn_decoy_emitter(cmd, asset_server, meshes, mats, Some(terrain), sam_type, decoy, side);
+            }
+        }
+    };
+
+    // Medium range sites around the city, point defence at its edge
+    let mut by_size: Vec<&CityData> = cities.iter().collect();
+    by_size.sort_by(|a,b| b.radius.total_cmp(&a.radius));
+    for city in by_size.iter().take(SAM_RING_CITIES) {
+        let ring_sites = scaled(SAM_RING_SITES);
+        let start = rng.gen_range(0.0..TAU);
+        for i in 0..ring_sites {
+            let angle = start + i as f32 * TAU / ring_sites as f32;
+            let around = city.pos + Vec2::from_angle(angle) * (city.radius + SAM_RING_DISTANCE);
+            let sam_type = if rng.gen_bool(0.5) { SAMType::SA3 } else { SAMType::SA6 };
+            if let Some(site) = find_site(terrain, cities, around, SITE_SEARCH_RADIUS, rng) {
+                spawn_site(cmd, meshes, mats, rng, sam_type, site, CoalitionType::RED);
+            }
+        }
+        for _ in 0..scaled(SHORAD_SITES_PER_CITY) {
+            let around = city.pos + Vec2::from_angle(rng.gen_range(0.0..TAU)) * city.radius;
+            let sam_type = [SAMType::SA8, SAMType::SA13, SAMType::SA15][rng.gen_range(0..3)];
+            if let Some(site) = find_site(terrain, cities, around, SITE_SEARCH_RADIUS / 2.0, rng) {
+                spawn_site(cmd, meshes, mats, rng, sam_type, site, CoalitionType::RED);
+            }
+        }
+    }
+
+    // Long range sites see furthest from the hilltops
+    let feature_range = LAND_RADIUS * 0.8;
+    for _ in 0..scaled(NUM_HIGH_GROUND_SITES) {
+        let best = (0..HIGH_GROUND_CANDIDATES)
+            .map(|_| Vec2::new(rng.gen_range(-feature_range..feature_range), rng.gen_range(-feature_range..feature_range)))
+            .filter(|p| p.length() > HIGH_GROUND_MIN_AIRBASE_DISTANCE && site_suitable(terrain, cities, *p))
+            .max_by(|a,b| terrain.get_height_world(a.x,a.y).total_cmp(&terrain.get_height_world(b.x,b.y)));
+        if let Some(p) = best {
+            spawn_site(cmd, meshes, mats, rng, SAMType::SA2, Vec3::new(p.x, terrain.get_height_world(p.x,p.y), p.y), CoalitionType::RED);
+        }
+    }
+
+    for bridge in bridges {
+        for _ in 0..scaled(BRIDGE_AAA_SITES) {
+            if let Some(site) = find_site(terrain, cities, *bridge, SITE_SEARCH_RADIUS, rng) {
+                spawn_site(cmd, meshes, mats, rng, SAMType::PGZ95, site, CoalitionType::RED);
+            }
+        }
+    }
+
+    // Guns either side of the runway defend the home airbase, whatever the difficulty
+    let runway_centre = Vec2::new(RUNWAY_LENGTH/2.0, RUNWAY_Z);
+    for i in 0..AIRBASE_AAA_SITES {
+        let p = runway_centre + Vec2::from_angle(FRAC_PI_2 + i as f32 * TAU / AIRBASE_AAA_SITES as f32) * AIRBASE_AAA_DISTANCE;
+        spawn_site(cmd, meshes, mats, rng, SAMType::PGZ95, Vec3::new(p.x, terrain.get_height_world(p.x,p.y), p.y), CoalitionType::BLUE);
+    }
+    info!("{} air defence sites laid out for {:?} difficulty, {} bridges", sites, difficulty, bridges.len());
+}
+
+/// Columns of trucks, some escorting SA-6 or Scud launchers, driving between two cities
+#[allow(clippy::too_many_arguments)]
+fn spawn_traffic(cmd: &mut Commands, asset_server: &Res<AssetServer>, meshes: &mut Assets<Mesh>,
+    mats: &mut Assets<StandardMaterial>, terrain: &TerrainData, cities: &[CityData], network: &RoadNetwork, rng: &mut StdRng)
+{
+    if cities.len() < 2 { return; }
+    for i in 0..NUM_CONVOYS {
+        let from = rng.gen_range(0..cities.len());
+        let to = rng.gen_range(0..cities.len());
+        if from == to { continue; }
+        let (Some(from), Some(to)) = (network.city_node(from), network.city_node(to)) else { continue };
+        let Some(route) = network.route(from, to) else { continue };
+        let mut vehicles = vec![ConvoyVehicleType::TRUCK; rng.gen_range(CONVOY_MIN_VEHICLES..=CONVOY_MAX_VEHICLES)];
+        let cargo = match rng.gen_range(0..10) {
+            0..=2 => Some(ConvoyVehicleType::SA6_TEL),
+            3..=4 => Some(ConvoyVehicleType::SCUD_TEL),
+            _ => None,
+        };
+        if let Some(cargo) = cargo {
+            let n = vehicles.len();
+            vehicles[n/2] = cargo;
+        }
+        spawn_convoy(cmd, asset_server, meshes, mats, terrain, &format!("Convoy {}", i+1), route, from, to, &vehicles, CoalitionType::RED);
+    }
+}
+
+/// Scud launchers, each starting out in a hiding place of its own
+fn spawn_scud_launchers(cmd: &mut Commands, meshes: &mut Assets<Mesh>, mats: &mut Assets<StandardMaterial>,
+    terrain: &TerrainData, network: &RoadNetwork, rng: &mut StdRng)
+{
+    let mut hides = scud_hides(network);
+    hides.shuffle(rng);
+    for (i, hide) in hides.into_iter().take(NUM_SCUD_LAUNCHERS).enumerate() {
+        spawn_scud_launcher(cmd, meshes, mats, terrain, network, &format!("Scud launcher {}", i+1), hide, CoalitionType::RED);
     }
 }
 
@@ -800,6 +1131,8 @@ pub fn setup_procedural_world(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
 ) {
     let mut rng = StdRng::seed_from_u64(TERRAIN_SEED);
     let mut terrain = generate_heightmap(rng.gen());
@@ -811,6 +1144,8 @@ pub fn setup_procedural_world(
     for c in &cities { terrain.flatten_circle(c.pos.x,c.pos.y,c.radius,BASE_HEIGHT); }
 
     let roads = generate_roads(&cities, &terrain);
+    let bridges = find_bridges(&roads, &terrain);
+    let road_network = build_road_network(&cities, &roads, &bridges);
     for r in &roads { terrain.flatten_path(&r.waypoints, 8.0, BASE_HEIGHT); }
 
     let fields = generate_fields(&terrain, &cities, &mut rng);
@@ -829,7 +1164,12 @@ pub fn setup_procedural_world(
     spawn_fields(&mut commands, &mut meshes, &mut materials, &fields, &terrain);
     spawn_airbase(&mut commands, &mut meshes, &mut materials, &bbm,&bby,&bbg,&bbr,&bbw);
     spawn_terrain_features(&mut commands, &mut meshes, &mut materials, &bbm,&bbr, &terrain, &cities, &mut rng);
-    spawn_ships(&mut commands, &mut meshes, &mut materials, &bbm,&bbr,&bbg,&bbw, &mut rng);
+    spawn_ships(&mut commands, &asset_server, &mut meshes, &mut materials, &bbm,&bbr,&bbg,&bbw, &mut rng);
+    spawn_air_defence_network(&mut commands, &mut meshes, &mut materials, &terrain, &cities, &mut rng);
+    spawn_threat_layout(&mut commands, &asset_server, &mut meshes, &mut materials, &terrain, &cities, &bridges, *difficulty, &mut rng);
+    spawn_traffic(&mut commands, &asset_server, &mut meshes, &mut materials, &terrain, &cities, &road_network, &mut rng);
+    spawn_scud_launchers(&mut commands, &mut meshes, &mut materials, &terrain, &road_network, &mut rng);
 
     commands.insert_resource(terrain);
+    commands.insert_resource(road_network);
 }
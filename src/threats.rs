@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use bevy::prelude::Resource;
+use lazy_static::lazy_static;
+use ::serde::Deserialize;
+
+use crate::radar::{RadarBand, RadarEmitter, RadarEmitterType};
+use crate::sam::{EngagementEnvelope, SAMType};
+
+/* Threat catalogue. Everything that sets one air defence system apart from another, from radar
+   bands to missile kinematics, is read from assets/threats/catalogue.toml. The file is built into
+   the binary, so sites can be laid out during startup without waiting for the asset server. */
+
+lazy_static! {
+    pub static ref THREAT_CATALOGUE: HashMap<SAMType, ThreatData> =
+        toml::from_str(include_str!("../assets/threats/catalogue.toml")).expect("Invalid threat catalogue");
+}
+
+pub fn threat_data(sam_type: &SAMType) -> &'static ThreatData {
+    THREAT_CATALOGUE.get(sam_type).expect("Threat type missing from the catalogue")
+}
+
+/// How heavily the world is defended
+#[derive(Resource, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Difficulty {
+    EASY,
+    #[default]
+    NORMAL,
+    HARD,
+}
+
+impl Difficulty {
+    /// Picked on the command line with `--difficulty easy|normal|hard`
+    pub fn from_args() -> Difficulty {
+        let args: Vec<String> = std::env::args().collect();
+        match args.iter().position(|a| a == "--difficulty").and_then(|i| args.get(i + 1)).map(|s| s.as_str()) {
+            Some("easy") => Difficulty::EASY,
+            Some("hard") => Difficulty::HARD,
+            _ => Difficulty::default(),
+        }
+    }
+
+    /// Multiplier for the number of air defence sites laid out
+    pub fn threat_density(&self) -> f32 {
+        match self {
+            Difficulty::EASY => 0.5,
+            Difficulty::NORMAL => 1.0,
+            Difficulty::HARD => 1.5,
+        }
+    }
+
+    /// How likely each site is to use the emitter tactics
+    pub fn tactics(&self) -> TacticsProfile {
+        match self {
+            Difficulty::EASY => TacticsProfile { blink: 0.1, arm_shutdown: 0.0, shoot_and_scoot: 0.0, decoy: 0.0 },
+            Difficulty::NORMAL => TacticsProfile { blink: 0.3, arm_shutdown: 0.5, shoot_and_scoot: 0.3, decoy: 0.15 },
+            Difficulty::HARD => TacticsProfile { blink: 0.6, arm_shutdown: 0.9, shoot_and_scoot: 0.7, decoy: 0.4 },
+        }
+    }
+}
+
+/// Chance of a site using each of the emitter tactics
+#[derive(Debug, Copy, Clone)]
+pub struct TacticsProfile {
+    pub blink: f64,
+    pub arm_shutdown: f64,
+    pub shoot_and_scoot: f64, // Only mobile systems
+    pub decoy: f64, // A decoy emitter set up near the site
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum MissileGuidance {
+    COMMAND, // Steered by the ground station along its radar track
+    SEMI_ACTIVE, // Homes on the reflections of the fire-control radar
+    INFRARED, // Heat seeker, on its own once launched on the radar's lock
+}
+
+#[derive(Deserialize)]
+pub struct RadarData {
+    pub name: String,
+    pub band: RadarBand,
+    pub radar_type: RadarEmitterType,
+    pub gain: f32,
+    pub range_km: f32,
+    pub scan_interval: f32,
+    pub track_interval: f32,
+    pub rwr_code: String,
+}
+
+impl RadarData {
+    /// A dark emitter set up from the catalogue entry. Reaction time is how long a lock must be held before launch.
+    pub fn emitter(&self, reaction_time: f32) -> RadarEmitter {
+        RadarEmitter {
+            radar_type: self.radar_type,
+            radar_gain: self.gain,
+            band: self.band,
+            max_detect_range_km: self.range_km,
+            scan_interval: self.scan_interval,
+            track_interval: self.track_interval,
+            launch_delay: reaction_time,
+            rwr_code: self.rwr_code.clone(),
+            emitting: false,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MissileData {
+    pub guidance: MissileGuidance,
+    pub max_thrust: f32,
+    pub motor_burn_time: f32, // Seconds
+    pub max_turn_rate: f32,
+    pub gain: f32,
+    pub warhead_damage: f32,
+    pub warhead_radius: f32,
+    pub proximity_fuse_distance: f32,
+    pub flare_rejection: Option<f32>, // Heat seekers only
+}
+
+#[derive(Deserialize)]
+pub struct FlakData {
+    pub rate_of_fire: f32, // Bursts per minute
+    pub muzzle_velocity: f32,
+    pub optical_range: f32, // The gunners aim by eye within this range when the radar is dark
+    pub dispersion: f32, // Radians
+    pub burst_radius: f32,
+    pub damage: f32,
+}
+
+#[derive(Deserialize)]
+pub struct ThreatData {
+    pub name: String,
+    pub map_symbol: String,
+    pub reaction_time: f32,
+    pub self_contained: bool, // Radar and weapons on one vehicle
+    pub mobile: bool, // Can drive off to another position after firing
+    pub launchers: usize,
+    pub launcher_spacing: f32, // Distance of the launchers from the centre of the site
+    pub launcher_model: Option<String>,
+    pub missiles_per_launcher: u32,
+    pub reload_time: f32, // Seconds to load one missile
+    pub loaders: usize,
+    pub loader_missiles: u32,
+    pub salvo_size: u32, // Missiles guided at the same target at once
+    pub salvo_interval: f32, // Seconds between two launches
+    pub envelope: EngagementEnvelope,
+    pub acquisition: Option<RadarData>,
+    pub fire_control: RadarData,
+    pub missile: Option<MissileData>,
+    pub gun: Option<FlakData>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogue_covers_every_threat_type() {
+        for sam_type in [SAMType::SA2, SAMType::SA3, SAMType::SA6, SAMType::SA8, SAMType::SA13, SAMType::SA15, SAMType::PGZ95] {
+            let threat = threat_data(&sam_type);
+            assert!(threat.missile.is_some() || threat.gun.is_some(), "{} has no weapons", threat.name);
+            assert!(threat.self_contained || threat.acquisition.is_some(), "{} has no acquisition radar", threat.name);
+            assert!(threat.envelope.min_range < threat.envelope.max_range, "{} envelope", threat.name);
+        }
+    }
+}